@@ -1,15 +1,34 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use jsonwebtoken::Algorithm;
+use serde::de::Error as _;
+use serde::{Deserialize as _, Deserializer};
 use serde_derive::Deserialize;
 use structopt::StructOpt;
 use tokio::{fs::File, io::AsyncReadExt};
 
+use crate::app::models::client_auth::ClientAuthMethod;
+use crate::app::models::oidc::par::ParMode;
+use crate::app::models::oidc::request_object::RequestObjectMode;
+use crate::app::models::pkce::PkceMethod;
+use crate::app::models::webauthn::options::{ResidentKey, UserVerification};
+
 #[derive(StructOpt)]
 #[structopt(name = "webauthexp")]
 pub struct AppArgs {
     #[structopt(short, long, parse(from_os_str))]
-    config: PathBuf,
+    config: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    command: Option<AppCommand>,
+}
+
+#[derive(StructOpt)]
+pub enum AppCommand {
+    /// Prints a new random key for `key` or `key_file` in [session]
+    GenerateSessionKey,
 }
 
 impl AppArgs {
@@ -17,12 +36,16 @@ impl AppArgs {
         Self::from_args()
     }
 
+    /// Command to run instead of the server, if any.
+    pub fn command(&self) -> Option<&AppCommand> {
+        self.command.as_ref()
+    }
+
     pub async fn load_config(&self) -> Result<AppConfig> {
-        let mut file = File::open(&self.config).await?;
-        let mut content = String::new();
-        file.read_to_string(&mut content).await?;
-        let config: AppConfig = toml::from_str(&content)?;
-        Ok(config)
+        match &self.config {
+            Some(path) => AppConfig::load(path).await,
+            None => bail!("--config is required to run the server"),
+        }
     }
 }
 
@@ -36,6 +59,24 @@ impl Default for AppArgs {
 struct ServerConfig {
     bind: String,
     port: u16,
+    public_url: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SigningKeyConfig {
+    pub private_key: PathBuf,
+    pub alg: Algorithm,
+    pub kid: String,
+}
+
+/// Client certificate for mutual-TLS (RFC 8705), as PEM files.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ClientCertificateConfig {
+    /// Leaf certificate, optionally followed by its intermediates.
+    pub certificate: PathBuf,
+    pub private_key: PathBuf,
+    /// Extra root to trust for the provider's server certificate, e.g. a self-signed test provider.
+    pub ca_certificate: Option<PathBuf>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -44,6 +85,43 @@ pub struct GithubConfig {
     pub client_secret: String,
     pub redirect_uri: String,
     pub scope: String,
+    #[serde(default)]
+    pub pkce_method: PkceMethod,
+    pub token_endpoint_auth_method: Option<ClientAuthMethod>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct IdTokenConfig {
+    pub leeway: u64,
+    pub max_iat_age: Option<u64>,
+    pub max_age: Option<u64>,
+    pub acr_values: Vec<String>,
+}
+
+impl Default for IdTokenConfig {
+    fn default() -> Self {
+        Self {
+            leeway: 60,
+            max_iat_age: None,
+            max_age: None,
+            acr_values: vec![],
+        }
+    }
+}
+
+impl IdTokenConfig {
+    /// Authorization request parameters asking the provider to satisfy max_age and acr_values.
+    pub fn authorization_parameters(&self) -> Vec<(&'static str, String)> {
+        let mut parameters = vec![];
+        if let Some(max_age) = self.max_age {
+            parameters.push(("max_age", max_age.to_string()));
+        }
+        if !self.acr_values.is_empty() {
+            parameters.push(("acr_values", self.acr_values.join(" ")));
+        }
+        parameters
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -52,12 +130,46 @@ pub struct GoogleConfig {
     pub client_secret: String,
     pub redirect_uri: String,
     pub scope: String,
+    #[serde(default)]
+    pub id_token: IdTokenConfig,
+    #[serde(default)]
+    pub userinfo: bool,
+    #[serde(default)]
+    pub pkce_method: PkceMethod,
+    #[serde(default)]
+    pub par: ParMode,
+    pub request_object: Option<RequestObjectMode>,
+    pub token_endpoint_auth_method: Option<ClientAuthMethod>,
+    #[serde(default)]
+    pub dpop: bool,
+    pub post_logout_redirect_uri: Option<String>,
 }
 
 impl GoogleConfig {
     pub fn issuer(&self) -> String {
         String::from("https://accounts.google.com")
     }
+
+    /// Google as a generic OpenID Connect provider.
+    pub fn to_oidc_config(&self) -> OidcConfig {
+        OidcConfig {
+            name: String::from("google"),
+            issuer: self.issuer(),
+            client_id: self.client_id.to_owned(),
+            client_secret: self.client_secret.to_owned(),
+            redirect_uri: self.redirect_uri.to_owned(),
+            scope: self.scope.to_owned(),
+            id_token: self.id_token.clone(),
+            userinfo: self.userinfo,
+            pkce_method: self.pkce_method,
+            par: self.par,
+            request_object: self.request_object,
+            token_endpoint_auth_method: self.token_endpoint_auth_method,
+            client_certificate: None,
+            dpop: self.dpop,
+            post_logout_redirect_uri: self.post_logout_redirect_uri.to_owned(),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -65,6 +177,252 @@ pub struct SpotifyConfig {
     pub client_id: String,
     pub redirect_uri: String,
     pub scope: String,
+    #[serde(default)]
+    pub dpop: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct OidcConfig {
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_uri: String,
+    pub scope: String,
+    #[serde(default)]
+    pub id_token: IdTokenConfig,
+    #[serde(default)]
+    pub userinfo: bool,
+    #[serde(default)]
+    pub pkce_method: PkceMethod,
+    #[serde(default)]
+    pub par: ParMode,
+    pub request_object: Option<RequestObjectMode>,
+    pub token_endpoint_auth_method: Option<ClientAuthMethod>,
+    pub client_certificate: Option<ClientCertificateConfig>,
+    #[serde(default)]
+    pub dpop: bool,
+    /// Where the provider sends the browser back after RP-Initiated Logout; must be registered with it.
+    pub post_logout_redirect_uri: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct IntrospectionConfig {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    #[serde(default)]
+    pub required_scopes: Vec<String>,
+    #[serde(default = "IntrospectionConfig::default_max_cache_ttl")]
+    pub max_cache_ttl: u64,
+    pub client_certificate: Option<ClientCertificateConfig>,
+}
+
+impl IntrospectionConfig {
+    fn default_max_cache_ttl() -> u64 {
+        300
+    }
+}
+
+/// Where local user accounts and their linked identities are kept.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct UsersConfig {
+    /// SQLite database file, created on first start.
+    pub database: PathBuf,
+}
+
+impl Default for UsersConfig {
+    fn default() -> Self {
+        Self {
+            database: PathBuf::from("webauthexp.db"),
+        }
+    }
+}
+
+/// Relying party settings for WebAuthn passkeys.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct WebauthnConfig {
+    /// Domain credentials are scoped to; the origins' host or a registrable suffix of it.
+    pub rp_id: String,
+    pub rp_name: String,
+    /// Origins the browser may report in clientDataJSON.
+    pub origins: Vec<String>,
+    /// Milliseconds the browser waits for the user.
+    pub timeout: u64,
+    pub user_verification: UserVerification,
+    pub resident_key: ResidentKey,
+}
+
+impl Default for WebauthnConfig {
+    fn default() -> Self {
+        Self {
+            rp_id: String::from("localhost"),
+            rp_name: String::from("webauthexp"),
+            origins: vec![String::from("http://localhost:8080")],
+            timeout: 300_000,
+            user_verification: UserVerification::default(),
+            resident_key: ResidentKey::default(),
+        }
+    }
+}
+
+/// Backend keeping session state.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SessionStoreConfig {
+    /// Lost on restart; fine for a single process.
+    Memory,
+    Sqlite { database: PathBuf },
+}
+
+/// Reads `store` and `database` from [session]. `#[serde(default)]` never applies to flattened fields,
+/// so a missing `store` is handled here.
+fn deserialize_session_store<'de, D>(deserializer: D) -> std::result::Result<SessionStoreConfig, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    enum Store {
+        Memory,
+        Sqlite,
+    }
+
+    #[derive(Deserialize)]
+    struct Fields {
+        store: Option<Store>,
+        database: Option<PathBuf>,
+    }
+
+    let fields = Fields::deserialize(deserializer)?;
+    match (fields.store, fields.database) {
+        (None | Some(Store::Memory), _) => Ok(SessionStoreConfig::Memory),
+        (Some(Store::Sqlite), Some(database)) => Ok(SessionStoreConfig::Sqlite { database }),
+        (Some(Store::Sqlite), None) => Err(D::Error::missing_field("database")),
+    }
+}
+
+/// How the session ID cookie is protected.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CookieMode {
+    /// Readable by the browser, but tamper-proof.
+    #[default]
+    Signed,
+    /// Encrypted and authenticated.
+    Private,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SameSiteConfig {
+    Strict,
+    /// Needed for the session to survive the redirect back from a provider.
+    #[default]
+    Lax,
+    None,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct SessionCookieConfig {
+    pub name: String,
+    pub secure: bool,
+    pub same_site: SameSiteConfig,
+    pub domain: Option<String>,
+    pub path: String,
+    /// Seconds; defaults to the session ttl, 0 makes it a browser-session cookie.
+    pub max_age: Option<u64>,
+    /// Prefixes the name with `__Host-`, which pins the cookie to this host over HTTPS.
+    pub host_prefix: bool,
+}
+
+impl Default for SessionCookieConfig {
+    fn default() -> Self {
+        Self {
+            name: String::from("webauthexp-session"),
+            secure: true,
+            same_site: SameSiteConfig::Lax,
+            domain: None,
+            path: String::from("/"),
+            max_age: None,
+            host_prefix: false,
+        }
+    }
+}
+
+impl SessionCookieConfig {
+    /// Cookie name including the `__Host-` prefix when enabled.
+    pub fn name(&self) -> String {
+        if self.host_prefix {
+            format!("__Host-{}", self.name)
+        } else {
+            self.name.to_owned()
+        }
+    }
+
+    /// Checks the attributes browsers require for `__Host-` cookies (RFC 6265bis section 4.1.3.2).
+    fn validate(&self) -> Result<()> {
+        if self.host_prefix && (!self.secure || self.domain.is_some() || self.path != "/") {
+            bail!("session cookie with host_prefix needs secure = true, path = \"/\" and no domain");
+        }
+        if self.same_site == SameSiteConfig::None && !self.secure {
+            bail!("session cookie with same_site = \"none\" needs secure = true");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct SessionConfig {
+    #[serde(flatten, deserialize_with = "deserialize_session_store")]
+    pub store: SessionStoreConfig,
+    /// Seconds of inactivity after which a session expires.
+    pub ttl: u64,
+    /// Seconds between sweeps removing expired sessions.
+    pub sweep_interval: u64,
+    /// Base64url key of at least 64 bytes protecting the session cookie; random per start when unset.
+    pub key: Option<String>,
+    /// File containing the key, as printed by `webauthexp generate-session-key`.
+    pub key_file: Option<PathBuf>,
+    /// Keys of earlier rotations, still accepted but no longer used for new cookies.
+    pub previous_keys: Vec<String>,
+    pub cookie_mode: CookieMode,
+    pub cookie: SessionCookieConfig,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            store: SessionStoreConfig::Memory,
+            ttl: 3600,
+            sweep_interval: 300,
+            key: None,
+            key_file: None,
+            previous_keys: vec![],
+            cookie_mode: CookieMode::default(),
+            cookie: SessionCookieConfig::default(),
+        }
+    }
+}
+
+impl SessionConfig {
+    fn validate(&self) -> Result<()> {
+        if self.key.is_some() && self.key_file.is_some() {
+            bail!("set either key or key_file in [session], not both");
+        }
+        self.cookie.validate()
+    }
+
+    pub fn ttl(&self) -> Duration {
+        Duration::from_secs(self.ttl)
+    }
+
+    pub fn sweep_interval(&self) -> Duration {
+        Duration::from_secs(self.sweep_interval)
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -73,10 +431,58 @@ pub struct AppConfig {
     pub github: GithubConfig,
     pub google: GoogleConfig,
     pub spotify: SpotifyConfig,
+    #[serde(default)]
+    pub oidc: Vec<OidcConfig>,
+    pub introspection: Option<IntrospectionConfig>,
+    pub signing_key: Option<SigningKeyConfig>,
+    #[serde(default)]
+    pub users: UsersConfig,
+    #[serde(default)]
+    pub session: SessionConfig,
+    #[serde(default)]
+    pub webauthn: WebauthnConfig,
 }
 
 impl AppConfig {
+    pub async fn load(path: &Path) -> Result<Self> {
+        let mut file = File::open(path).await?;
+        let mut content = String::new();
+        file.read_to_string(&mut content).await?;
+        let config: AppConfig = toml::from_str(&content)?;
+        config.session.validate()?;
+        Ok(config)
+    }
+
     pub fn bind_address(&self) -> (String, u16) {
         (self.server.bind.to_owned(), self.server.port)
     }
+
+    /// Externally visible base URL of this server, for URIs handed to providers.
+    pub fn public_url(&self) -> Option<&str> {
+        self.server.public_url.as_deref().map(|url| url.trim_end_matches('/'))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_config() {
+        let config: SessionConfig = toml::from_str("ttl = 600").unwrap();
+        assert_eq!(SessionStoreConfig::Memory, config.store);
+        assert_eq!(Duration::from_secs(600), config.ttl());
+        assert_eq!(Duration::from_secs(300), config.sweep_interval());
+
+        let config: SessionConfig = toml::from_str("key_file = \"keys/session.key\"").unwrap();
+        assert_eq!(SessionStoreConfig::Memory, config.store);
+        assert_eq!(Some(PathBuf::from("keys/session.key")), config.key_file);
+
+        let config: SessionConfig = toml::from_str("store = \"sqlite\"\ndatabase = \"sessions.db\"").unwrap();
+        assert_eq!(SessionStoreConfig::Sqlite { database: PathBuf::from("sessions.db") }, config.store);
+        assert_eq!(Duration::from_secs(3600), config.ttl());
+
+        assert!(toml::from_str::<SessionConfig>("store = \"sqlite\"").is_err());
+        assert!(toml::from_str::<SessionConfig>("store = \"redis\"").is_err());
+    }
 }
@@ -1,3 +1,100 @@
-pub mod github;
-pub mod google;
-pub mod spotify;
+use std::future::{Ready, ready};
+use std::time::Duration;
+
+use actix_session::Session;
+use actix_web::{FromRequest, HttpRequest, Result, web::Data};
+use actix_web::dev::Payload;
+use actix_web::error::ErrorInternalServerError;
+
+use crate::app::models::keys::SigningKey;
+use crate::app::models::oidc::jwks_store::JwksStore;
+use crate::app::models::oidc::request_object::RequestObjectStore;
+use crate::app::models::provider::ProviderContext;
+use crate::app::models::token::{TokenSet, TokenStore};
+use crate::app::session::store::{SessionResources, SessionState};
+
+pub mod introspection;
+pub mod jwks;
+pub mod oidc;
+pub mod providers;
+pub mod request_objects;
+pub mod users;
+pub mod webauthn;
+
+/// Session key of the signed-in local user.
+const USER_ID_KEY: &str = "user-id";
+
+/// Keeps provider tokens server-side and puts only their handle in the session, discarding any previous tokens.
+async fn save_tokens(session: &Session, token_store: &TokenStore, key: &str, tokens: TokenSet) -> Result<()> {
+    if let Some(previous_handle) = session.get::<String>(key)? {
+        token_store.remove(&previous_handle).await;
+    }
+    session.insert(key, token_store.insert(tokens))?;
+    Ok(())
+}
+
+async fn take_tokens(session: &Session, token_store: &TokenStore, key: &str) -> Result<Option<TokenSet>> {
+    let handle = session.get::<String>(key)?;
+    session.remove(key);
+    match handle {
+        Some(handle) => Ok(token_store.remove(&handle).await),
+        None => Ok(None),
+    }
+}
+
+/// Values that may be token handles; any other string simply names no entry.
+fn token_handles(state: &SessionState) -> impl Iterator<Item = String> + '_ {
+    state.values().filter_map(|value| serde_json::from_str::<String>(value).ok())
+}
+
+/// Ties the stored tokens to the sessions holding their handles.
+impl SessionResources for TokenStore {
+    fn retain(&self, state: &SessionState, ttl: Duration) {
+        for handle in token_handles(state) {
+            self.touch(&handle, ttl);
+        }
+    }
+
+    fn release(&self, state: &SessionState) {
+        for handle in token_handles(state) {
+            self.discard(&handle);
+        }
+    }
+
+    fn sweep(&self) -> usize {
+        TokenStore::sweep(self)
+    }
+}
+
+/// App-wide services handed to providers as their `ProviderContext`.
+pub(super) struct Services {
+    jwks_store: Data<JwksStore>,
+    signing_key: Option<Data<SigningKey>>,
+    request_objects: Data<RequestObjectStore>,
+}
+
+impl Services {
+    pub(super) fn context(&self) -> ProviderContext<'_> {
+        ProviderContext {
+            jwks_store: &self.jwks_store,
+            signing_key: self.signing_key.as_ref().map(|key| key.get_ref()),
+            request_objects: &self.request_objects,
+        }
+    }
+}
+
+impl FromRequest for Services {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let services = req.app_data::<Data<JwksStore>>().cloned()
+            .zip(req.app_data::<Data<RequestObjectStore>>().cloned())
+            .map(|(jwks_store, request_objects)| Self {
+                jwks_store,
+                signing_key: req.app_data::<Data<SigningKey>>().cloned(),
+                request_objects,
+            });
+        ready(services.ok_or_else(|| ErrorInternalServerError("provider services are not configured")))
+    }
+}
@@ -0,0 +1,20 @@
+use actix_web::{HttpResponse, Result, Scope, web::{get, scope}};
+use actix_web::body::BoxBody;
+
+use crate::app::config::IntrospectionConfig;
+use crate::app::models::oidc::introspection::{IntrospectedToken, RequiredScopes};
+
+pub fn create_scope(config: &IntrospectionConfig) -> Scope {
+    scope("/introspection")
+        .app_data(RequiredScopes(config.required_scopes.clone()))
+        .route("", get().to(index))
+        .route("/", get().to(index))
+}
+
+/// Echoes the introspection result, so downstream services can check the tokens they receive.
+async fn index(IntrospectedToken(token): IntrospectedToken) -> Result<HttpResponse<BoxBody>> {
+    let response = HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-store"))
+        .json(token);
+    Ok(response)
+}
@@ -0,0 +1,23 @@
+use actix_web::{HttpResponse, Result, Scope, web::{Data, get, scope}};
+use actix_web::body::BoxBody;
+
+use crate::app::models::keys::SigningKey;
+use crate::app::models::oidc::discovery::Jwks;
+
+pub fn create_scope() -> Scope {
+    scope("/jwks")
+        .route("", get().to(index))
+}
+
+/// Publishes the public half of our signing key, so that providers can verify the JWTs we send them.
+async fn index(signing_key: Option<Data<SigningKey>>) -> Result<HttpResponse<BoxBody>> {
+    let jwks = match signing_key {
+        Some(signing_key) => signing_key.jwks(),
+        None => Jwks::new(vec![]),
+    };
+
+    let response = HttpResponse::Ok()
+        .content_type("application/jwk-set+json")
+        .json(jwks);
+    Ok(response)
+}
@@ -0,0 +1,153 @@
+use actix_session::Session;
+use actix_web::{HttpResponse, ResponseError, Result, Scope, web::{Data, Form, Query, get, post, scope}};
+use actix_web::body::BoxBody;
+use actix_web::http::StatusCode;
+use serde::Serialize;
+use serde_derive::Deserialize;
+use serde_json::Value;
+use thiserror::Error;
+use url::Url;
+
+use crate::app::config::AppConfig;
+use crate::app::models::oidc::logout_token;
+use crate::app::models::oidc::registration::ClientMetadata;
+use crate::app::models::provider::{ProviderError, ProviderRegistry, ProviderSession};
+use crate::app::models::token::TokenStore;
+use crate::app::session::store::{SessionResources, SessionStore, SessionStoreError};
+use super::Services;
+
+/// Endpoints OpenID providers call; has to come before the provider scope, like every fixed scope.
+pub fn create_scope(config: &AppConfig) -> Scope {
+    let registrations: Vec<ClientMetadata> = std::iter::once(config.google.to_oidc_config())
+        .chain(config.oidc.iter().cloned())
+        .map(|oidc| ClientMetadata::new(&oidc, config.public_url()))
+        .collect();
+    scope("/oidc")
+        .app_data(Data::new(registrations))
+        .route("/registration", get().to(registration))
+        .route("/backchannel-logout", post().to(backchannel_logout))
+        .route("/frontchannel-logout", get().to(frontchannel_logout))
+}
+
+fn sid_key(issuer: &str) -> String {
+    format!("oidc-sid {}", issuer)
+}
+
+fn sub_key(issuer: &str) -> String {
+    format!("oidc-sub {}", issuer)
+}
+
+/// Remembers the provider session in the local session, so back-channel logout can find it by `sid` or `sub`.
+pub(super) fn bind_provider_session(session: &Session, provider_session: &ProviderSession) -> Result<()> {
+    session.insert(sub_key(&provider_session.issuer), &provider_session.subject)?;
+    let sid_key = sid_key(&provider_session.issuer);
+    match &provider_session.sid {
+        Some(sid) => session.insert(sid_key, sid)?,
+        None => {
+            session.remove(&sid_key);
+        },
+    }
+    Ok(())
+}
+
+/// Error response from OpenID Connect Back-Channel Logout 1.0 section 2.8.
+#[derive(Debug, Error)]
+enum BackchannelLogoutError {
+    #[error(transparent)]
+    InvalidRequest(#[from] ProviderError),
+
+    #[error("failed to end sessions: {0}")]
+    LogoutFailed(#[from] SessionStoreError),
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorResponse {
+    error: &'static str,
+    error_description: String,
+}
+
+impl ResponseError for BackchannelLogoutError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            Self::LogoutFailed(_) => StatusCode::NOT_IMPLEMENTED,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let response = ErrorResponse {
+            error: "invalid_request",
+            error_description: self.to_string(),
+        };
+        HttpResponse::build(self.status_code())
+            .insert_header(("Cache-Control", "no-store"))
+            .json(response)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LogoutRequest {
+    logout_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct FrontchannelLogoutRequest {
+    iss: String,
+    sid: String,
+}
+
+/// What to register with each OpenID provider, including the back-channel logout URI.
+async fn registration(registrations: Data<Vec<ClientMetadata>>) -> HttpResponse<BoxBody> {
+    HttpResponse::Ok().json(registrations.as_ref())
+}
+
+/// Ends every local session bound to the provider session named in the logout token, or to its subject without `sid`,
+/// along with the tokens they hold.
+async fn backchannel_logout(registry: Data<ProviderRegistry>, services: Services, sessions: Data<dyn SessionStore>, token_store: Data<TokenStore>, Form(request): Form<LogoutRequest>) -> std::result::Result<HttpResponse<BoxBody>, BackchannelLogoutError> {
+    let issuer = logout_token::unverified_issuer(&request.logout_token).map_err(ProviderError::from)?;
+    let provider = registry.find_by_issuer(&issuer)?;
+    let claims = provider.verify_logout_token(&services.context(), &request.logout_token).await?;
+
+    let ended = match (&claims.sid, &claims.sub) {
+        (Some(sid), _) => sessions.delete_matching(&sid_key(&claims.iss), &Value::from(sid.as_str()).to_string())?,
+        (None, Some(sub)) => sessions.delete_matching(&sub_key(&claims.iss), &Value::from(sub.as_str()).to_string())?,
+        (None, None) => vec![],
+    };
+    for state in &ended {
+        token_store.release(state);
+    }
+    log::info!("{} back-channel logout ended {} sessions", provider.name(), ended.len());
+
+    let response = HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-store"))
+        .finish();
+    Ok(response)
+}
+
+/// Rendered by the provider in an iframe, with the user's session cookie; clears the session if it is bound to `sid`
+/// from OpenID Connect Front-Channel Logout 1.0 section 2.
+///
+/// A session already gone, or bound to another `sid`, is left alone but still answered normally.
+async fn frontchannel_logout(registry: Data<ProviderRegistry>, session: Session, Query(request): Query<FrontchannelLogoutRequest>) -> Result<HttpResponse<BoxBody>> {
+    let provider = registry.find_by_issuer(&request.iss)?;
+    match session.get::<String>(&sid_key(&request.iss))? {
+        Some(sid) if sid == request.sid => {
+            session.purge();
+            log::info!("{} front-channel logout ended session {}", provider.name(), sid);
+        },
+        _ => log::info!("{} front-channel logout for unknown session {}", provider.name(), request.sid),
+    }
+
+    // Only the provider may frame this page, and no cache may serve it instead of reaching us
+    let frame_ancestors = match Url::parse(&request.iss) {
+        Ok(issuer) => format!("frame-ancestors {}", issuer.origin().ascii_serialization()),
+        Err(_) => "frame-ancestors 'none'".to_owned(),
+    };
+    let response = HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache, no-store"))
+        .insert_header(("Pragma", "no-cache"))
+        .insert_header(("Content-Security-Policy", frame_ancestors))
+        .content_type("text/html; charset=utf-8")
+        .body("<!DOCTYPE html><title>Logged out</title>");
+    Ok(response)
+}
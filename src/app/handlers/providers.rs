@@ -0,0 +1,179 @@
+use actix_session::Session;
+use actix_web::{HttpResponse, ResponseError, Result, Scope, web::{Data, Path, Query, get, post, scope}};
+use actix_web::body::BoxBody;
+use actix_web::http::StatusCode;
+use serde::Serialize;
+use serde_derive::Deserialize;
+
+use crate::app::models::provider::{AuthorizationResponse, ProviderError, ProviderRegistry, RequestAttributes};
+use crate::app::models::revocation::LogoutReport;
+use crate::app::models::token::{AccessTokenResponse, TokenError, TokenStore};
+use crate::app::models::user::{UserStore, UserStoreError};
+use super::{Services, USER_ID_KEY, oidc, save_tokens, take_tokens};
+
+#[derive(Debug, Serialize)]
+struct ErrorMessage {
+    message: String,
+}
+
+impl ResponseError for ProviderError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::UnknownProvider(_) => StatusCode::NOT_FOUND,
+            Self::RequestAttributesMissing | Self::StateMismatch | Self::AuthorizationFailed(_) | Self::UnknownIssuer(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let message = ErrorMessage {
+            message: self.to_string(),
+        };
+        HttpResponse::build(self.status_code()).json(message)
+    }
+}
+
+/// Serves every registered provider; has to come after the fixed scopes, as `/{provider}` matches any first segment.
+pub fn create_scope() -> Scope {
+    scope("/{provider}")
+        .route("", get().to(index))
+        .route("/", get().to(index))
+        .route("/callback", get().to(callback))
+        .route("/token", get().to(token))
+        // POST only, so other sites cannot sign users out and revoke their tokens with a link or image
+        .route("/logout", post().to(logout))
+        .route("/logout/callback", get().to(logout_callback))
+}
+
+fn attributes_key(name: &str) -> String {
+    format!("{}-oauth", name)
+}
+
+fn token_key(name: &str) -> String {
+    format!("{}-token", name)
+}
+
+fn logout_key(name: &str) -> String {
+    format!("{}-logout", name)
+}
+
+#[derive(Debug, Deserialize)]
+struct LogoutResponse {
+    state: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct LogoutResult {
+    provider: String,
+}
+
+async fn index(registry: Data<ProviderRegistry>, services: Services, session: Session, name: Path<String>) -> Result<HttpResponse<BoxBody>> {
+    let provider = registry.get(&name)?;
+    let request = provider.authorization_request(&services.context()).await?;
+    session.insert(attributes_key(provider.name()), &request.attributes)?;
+
+    let response = HttpResponse::Found()
+        .insert_header(("Location", request.request_uri))
+        .finish();
+    Ok(response)
+}
+
+async fn callback(registry: Data<ProviderRegistry>, services: Services, token_store: Data<TokenStore>, users: Data<UserStore>, session: Session, name: Path<String>, Query(response): Query<AuthorizationResponse>) -> Result<HttpResponse<BoxBody>> {
+    let provider = registry.get(&name)?;
+    let key = attributes_key(provider.name());
+    let attributes = session.get::<RequestAttributes>(&key)?;
+    session.remove(&key);
+    let attributes = attributes.ok_or(ProviderError::RequestAttributesMissing)?;
+    let code = response.code(&attributes)?;
+
+    let context = services.context();
+    let tokens = provider.exchange_code(&context, code, &attributes).await?;
+    let identity = provider.identity(&context, &tokens, &attributes).await?;
+
+    // Signing in while already signed in links the identity to the current user
+    let user_id = match session.get::<String>(USER_ID_KEY)? {
+        Some(user_id) if users.find(&user_id)?.is_some() => {
+            users.link(&user_id, &identity)?;
+            user_id
+        },
+        _ => {
+            session.renew();
+            users.sign_in(&identity)?
+        },
+    };
+    session.insert(USER_ID_KEY, &user_id)?;
+    if let Some(provider_session) = &identity.provider_session {
+        oidc::bind_provider_session(&session, provider_session)?;
+    }
+    save_tokens(&session, &token_store, &token_key(provider.name()), tokens).await?;
+
+    let user = users.find(&user_id)?.ok_or(UserStoreError::UserNotFound(user_id))?;
+    let response = HttpResponse::Ok().json(user);
+    Ok(response)
+}
+
+async fn token(registry: Data<ProviderRegistry>, token_store: Data<TokenStore>, session: Session, name: Path<String>) -> Result<HttpResponse<BoxBody>> {
+    let provider = registry.get(&name)?;
+    let refresh = match provider.refresh_token_request() {
+        Some(refresh) => refresh,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let handle = session.get::<String>(&token_key(provider.name()))?.ok_or(TokenError::NotFound)?;
+    let tokens = token_store.access_token(&handle, &refresh).await?;
+
+    let response = HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-store"))
+        .json(AccessTokenResponse::from(&tokens));
+    Ok(response)
+}
+
+/// Revokes the provider's tokens and signs out locally, then ends the session at the provider if it supports
+/// RP-initiated logout.
+async fn logout(registry: Data<ProviderRegistry>, services: Services, token_store: Data<TokenStore>, session: Session, name: Path<String>) -> Result<HttpResponse<BoxBody>> {
+    let provider = registry.get(&name)?;
+    let tokens = match take_tokens(&session, &token_store, &token_key(provider.name())).await? {
+        Some(tokens) => tokens,
+        None => {
+            session.purge();
+            return Ok(HttpResponse::Ok().json(LogoutReport::default()))
+        },
+    };
+    let report = provider.sign_out(&services.context(), &tokens).await?;
+
+    let response = match provider.end_session(&tokens).await? {
+        Some(request) => {
+            // The browser leaves for the provider, so the revocation results only go to the log.
+            // The session only keeps the logout state until the callback purges it.
+            log::info!("{} logout: {:?}", provider.name(), report);
+            session.remove(USER_ID_KEY);
+            if let Some(state) = &request.state {
+                session.insert(logout_key(provider.name()), state)?;
+            }
+            HttpResponse::Found()
+                .insert_header(("Location", request.request_uri))
+                .finish()
+        },
+        None => {
+            session.purge();
+            HttpResponse::Ok().json(report)
+        },
+    };
+    Ok(response)
+}
+
+/// Post-logout redirect target; clears the local session once the state matches the one sent.
+async fn logout_callback(registry: Data<ProviderRegistry>, session: Session, name: Path<String>, Query(response): Query<LogoutResponse>) -> Result<HttpResponse<BoxBody>> {
+    let provider = registry.get(&name)?;
+    let key = logout_key(provider.name());
+    let state = session.get::<String>(&key)?;
+    session.remove(&key);
+    if state.is_none() || state != response.state {
+        return Err(ProviderError::StateMismatch.into())
+    }
+    session.purge();
+
+    let response = HttpResponse::Ok().json(LogoutResult {
+        provider: provider.name().to_owned(),
+    });
+    Ok(response)
+}
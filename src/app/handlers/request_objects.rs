@@ -0,0 +1,20 @@
+use actix_web::{HttpResponse, Result, Scope, web::{Data, Path, get, scope}};
+use actix_web::body::BoxBody;
+
+use crate::app::models::oidc::request_object::RequestObjectStore;
+
+pub fn create_scope() -> Scope {
+    scope("/request-objects")
+        .route("/{id}", get().to(show))
+}
+
+async fn show(request_objects: Data<RequestObjectStore>, id: Path<String>) -> Result<HttpResponse<BoxBody>> {
+    let response = match request_objects.get(&id) {
+        Some(request_object) => HttpResponse::Ok()
+            .content_type("application/oauth-authz-req+jwt")
+            .insert_header(("Cache-Control", "no-store"))
+            .body(request_object),
+        None => HttpResponse::NotFound().finish(),
+    };
+    Ok(response)
+}
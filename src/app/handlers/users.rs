@@ -0,0 +1,57 @@
+use actix_session::Session;
+use actix_web::{HttpResponse, ResponseError, Result, Scope, web::{Data, get, post, scope}};
+use actix_web::body::BoxBody;
+use actix_web::http::StatusCode;
+use serde::Serialize;
+
+use crate::app::models::user::{UserStore, UserStoreError};
+use super::USER_ID_KEY;
+
+#[derive(Debug, Serialize)]
+struct ErrorMessage {
+    message: String,
+}
+
+impl ResponseError for UserStoreError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::AlreadyLinked(_, _) | Self::CredentialExists(_) => StatusCode::CONFLICT,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let message = ErrorMessage {
+            message: self.to_string(),
+        };
+        HttpResponse::build(self.status_code()).json(message)
+    }
+}
+
+pub fn create_scope() -> Scope {
+    scope("/user")
+        .route("", get().to(index))
+        .route("/", get().to(index))
+        // POST only, so other sites cannot sign users out with a link or image
+        .route("/logout", post().to(logout))
+}
+
+/// The signed-in user with all linked identities.
+async fn index(users: Data<UserStore>, session: Session) -> Result<HttpResponse<BoxBody>> {
+    let user = match session.get::<String>(USER_ID_KEY)? {
+        Some(user_id) => users.find(&user_id)?,
+        None => None,
+    };
+
+    let response = match user {
+        Some(user) => HttpResponse::Ok().json(user),
+        None => HttpResponse::Unauthorized().json(ErrorMessage { message: "not signed in".to_owned() }),
+    };
+    Ok(response)
+}
+
+/// Ends the login session; provider tokens stay until their own logout.
+async fn logout(session: Session) -> Result<HttpResponse<BoxBody>> {
+    session.remove(USER_ID_KEY);
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,127 @@
+use actix_session::Session;
+use actix_web::{HttpResponse, ResponseError, Result, Scope, web::{Data, Json, post, scope}};
+use actix_web::body::BoxBody;
+use actix_web::http::StatusCode;
+use serde::Serialize;
+
+use crate::app::config::WebauthnConfig;
+use crate::app::models::user::{User, UserStore, UserStoreError};
+use crate::app::models::webauthn::authentication::{AuthenticationError, AuthenticationOptions, AuthenticationResponse, AuthenticationState, AuthenticationVerification};
+use crate::app::models::webauthn::registration::{RegistrationError, RegistrationOptions, RegistrationResponse, RegistrationState, RegistrationVerification};
+use super::USER_ID_KEY;
+
+const REGISTRATION_KEY: &str = "webauthn-registration";
+const AUTHENTICATION_KEY: &str = "webauthn-authentication";
+
+#[derive(Debug, Serialize)]
+struct ErrorMessage {
+    message: String,
+}
+
+impl ResponseError for RegistrationError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let message = ErrorMessage {
+            message: self.to_string(),
+        };
+        HttpResponse::build(self.status_code()).json(message)
+    }
+}
+
+impl ResponseError for AuthenticationError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::CeremonyNotStarted | Self::InvalidEncoding(_) | Self::InvalidCredentialType(_) | Self::CredentialIdMismatch => StatusCode::BAD_REQUEST,
+            _ => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let message = ErrorMessage {
+            message: self.to_string(),
+        };
+        HttpResponse::build(self.status_code()).json(message)
+    }
+}
+
+pub fn create_scope(config: &WebauthnConfig) -> Scope {
+    scope("/webauthn")
+        .app_data(Data::new(config.clone()))
+        .route("/register/options", post().to(register_options))
+        .route("/register", post().to(register))
+        .route("/login/options", post().to(login_options))
+        .route("/login", post().to(login))
+}
+
+fn not_signed_in() -> HttpResponse<BoxBody> {
+    HttpResponse::Unauthorized().json(ErrorMessage { message: "not signed in".to_owned() })
+}
+
+fn signed_in_user(users: &UserStore, session: &Session) -> Result<Option<User>> {
+    let user = match session.get::<String>(USER_ID_KEY)? {
+        Some(user_id) => users.find(&user_id)?,
+        None => None,
+    };
+    Ok(user)
+}
+
+/// Creation options for adding a passkey to the signed-in user.
+async fn register_options(config: Data<WebauthnConfig>, users: Data<UserStore>, session: Session) -> Result<HttpResponse<BoxBody>> {
+    let user = match signed_in_user(&users, &session)? {
+        Some(user) => user,
+        None => return Ok(not_signed_in()),
+    };
+    let (options, state) = RegistrationOptions::new(&config, &user).start();
+    session.insert(REGISTRATION_KEY, &state)?;
+
+    let response = HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-store"))
+        .json(options);
+    Ok(response)
+}
+
+/// Verifies the new credential and stores it; each challenge can be answered once.
+async fn register(config: Data<WebauthnConfig>, users: Data<UserStore>, session: Session, Json(response): Json<RegistrationResponse>) -> Result<HttpResponse<BoxBody>> {
+    let state = session.get::<RegistrationState>(REGISTRATION_KEY)?;
+    session.remove(REGISTRATION_KEY);
+    let state = state.ok_or(RegistrationError::CeremonyNotStarted)?;
+    // The user may have signed out or switched accounts since the options were issued
+    if session.get::<String>(USER_ID_KEY)?.as_deref() != Some(state.user_id.as_str()) {
+        return Ok(not_signed_in())
+    }
+
+    let credential = RegistrationVerification::new(&config, &state).verify(&response)?;
+    users.add_credential(&state.user_id, &credential)?;
+    Ok(HttpResponse::Created().json(credential))
+}
+
+/// Request options for signing in with a passkey; no user name needed, as passkeys are discoverable.
+async fn login_options(config: Data<WebauthnConfig>, session: Session) -> Result<HttpResponse<BoxBody>> {
+    let (options, state) = AuthenticationOptions::new(&config).start();
+    session.insert(AUTHENTICATION_KEY, &state)?;
+
+    let response = HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-store"))
+        .json(options);
+    Ok(response)
+}
+
+/// Verifies the assertion and signs the credential's user in, like a provider callback does.
+async fn login(config: Data<WebauthnConfig>, users: Data<UserStore>, session: Session, Json(response): Json<AuthenticationResponse>) -> Result<HttpResponse<BoxBody>> {
+    let state = session.get::<AuthenticationState>(AUTHENTICATION_KEY)?;
+    session.remove(AUTHENTICATION_KEY);
+    let state = state.ok_or(AuthenticationError::CeremonyNotStarted)?;
+
+    let credential_id = response.credential_id()?;
+    let (user_id, credential) = users.find_credential(&credential_id)?.ok_or(AuthenticationError::UnknownCredential)?;
+    let result = AuthenticationVerification::new(&config, &state).verify(&response, &user_id, &credential)?;
+    users.update_credential(&credential_id, result.sign_count, result.backed_up)?;
+
+    session.renew();
+    session.insert(USER_ID_KEY, &user_id)?;
+    let user = users.find(&user_id)?.ok_or(UserStoreError::UserNotFound(user_id))?;
+    Ok(HttpResponse::Ok().json(user))
+}
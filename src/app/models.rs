@@ -1,6 +1,16 @@
+pub mod base64url;
+pub mod client_auth;
+pub mod device;
+pub mod dpop;
 pub mod github;
-pub mod google;
+pub mod keys;
+pub mod mtls;
 pub mod oidc;
 pub mod pkce;
+pub mod provider;
 pub mod random;
+pub mod revocation;
 pub mod spotify;
+pub mod token;
+pub mod user;
+pub mod webauthn;
@@ -0,0 +1,8 @@
+/// Base64url without padding (RFC 4648 section 5), as JOSE, PKCE and WebAuthn encode binary values.
+pub fn encode<T: AsRef<[u8]>>(value: T) -> String {
+    base64::encode_config(value, base64::URL_SAFE_NO_PAD)
+}
+
+pub fn decode<T: AsRef<[u8]>>(value: T) -> Result<Vec<u8>, base64::DecodeError> {
+    base64::decode_config(value, base64::URL_SAFE_NO_PAD)
+}
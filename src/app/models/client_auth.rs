@@ -0,0 +1,266 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use reqwest::RequestBuilder;
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+use url::form_urlencoded;
+
+use crate::app::models::keys::{KeyError, SigningKey};
+use crate::app::models::mtls::ClientCertificate;
+use crate::app::models::random::{RandomString, RandomStringGenerator};
+
+#[derive(Debug, Error)]
+pub enum ClientAuthError {
+    #[error("client authentication method {0} is not supported by the provider")]
+    MethodNotSupported(&'static str),
+
+    #[error("private_key_jwt needs a configured signing key")]
+    SigningKeyMissing,
+
+    #[error("tls_client_auth needs a configured client certificate")]
+    CertificateMissing,
+
+    #[error("signing client assertion failed: {0}")]
+    SigningFailed(#[from] KeyError),
+
+    #[error("JWT error")]
+    JwtError(#[from] jsonwebtoken::errors::Error),
+}
+
+type Result<T> = std::result::Result<T, ClientAuthError>;
+
+const CLIENT_ASSERTION_TYPE: &str = "urn:ietf:params:oauth:client-assertion-type:jwt-bearer";
+
+/// Client authentication methods from OpenID Connect Core section 9 and RFC 8705 section 2.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientAuthMethod {
+    /// Public clients, which only identify themselves with `client_id`.
+    None,
+    ClientSecretPost,
+    ClientSecretBasic,
+    ClientSecretJwt,
+    PrivateKeyJwt,
+    TlsClientAuth,
+    SelfSignedTlsClientAuth,
+}
+
+impl ClientAuthMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::ClientSecretPost => "client_secret_post",
+            Self::ClientSecretBasic => "client_secret_basic",
+            Self::ClientSecretJwt => "client_secret_jwt",
+            Self::PrivateKeyJwt => "private_key_jwt",
+            Self::TlsClientAuth => "tls_client_auth",
+            Self::SelfSignedTlsClientAuth => "self_signed_tls_client_auth",
+        }
+    }
+
+    /// Checks the configured method against `token_endpoint_auth_methods_supported`, or picks one when none is configured,
+    /// preferring methods that keep the secret off the wire. Mutual-TLS methods are only used when configured.
+    pub fn negotiate(configured: Option<Self>, supported: &[String], has_signing_key: bool) -> Result<Self> {
+        // OpenID Connect Discovery section 3: the default when omitted is client_secret_basic
+        let default = [Self::ClientSecretBasic.as_str().to_owned()];
+        let supported = if supported.is_empty() { &default[..] } else { supported };
+        let is_supported = |method: &Self| supported.iter().any(|s| s == method.as_str());
+
+        if let Some(method) = configured {
+            return if is_supported(&method) { Ok(method) } else { Err(ClientAuthError::MethodNotSupported(method.as_str())) }
+        }
+        let mut preferences = vec![Self::ClientSecretBasic, Self::ClientSecretPost, Self::ClientSecretJwt];
+        if has_signing_key {
+            preferences.insert(0, Self::PrivateKeyJwt);
+        }
+        preferences.into_iter()
+            .find(is_supported)
+            .ok_or(ClientAuthError::MethodNotSupported(Self::ClientSecretBasic.as_str()))
+    }
+}
+
+/// Client assertion claims from RFC 7523 section 3.
+#[derive(Debug, Deserialize, Serialize)]
+struct ClientAssertion {
+    iss: String,
+    sub: String,
+    aud: String,
+    jti: String,
+    iat: u64,
+    exp: u64,
+}
+
+/// Authenticates the client on a request to a token, PAR or similar endpoint.
+pub struct ClientAuthentication<'a> {
+    method: ClientAuthMethod,
+    client_id: &'a str,
+    client_secret: &'a str,
+    signing_key: Option<&'a SigningKey>,
+    client_certificate: Option<&'a ClientCertificate>,
+}
+
+impl<'a> ClientAuthentication<'a> {
+    pub fn new(method: ClientAuthMethod, client_id: &'a str, client_secret: &'a str) -> Self {
+        Self {
+            method,
+            client_id,
+            client_secret,
+            signing_key: None,
+            client_certificate: None,
+        }
+    }
+
+    pub fn with_signing_key(self, signing_key: Option<&'a SigningKey>) -> Self {
+        Self { signing_key, ..self }
+    }
+
+    /// Certificate for the mutual-TLS methods; the request must be sent with its `client()`.
+    pub fn with_client_certificate(self, client_certificate: Option<&'a ClientCertificate>) -> Self {
+        Self { client_certificate, ..self }
+    }
+
+    pub fn client_id(&self) -> &'a str {
+        self.client_id
+    }
+
+    /// HTTP client to send authenticated requests with, presenting the client certificate if there is one.
+    pub fn http_client(&self) -> reqwest::Client {
+        self.client_certificate.map(|certificate| certificate.client().clone()).unwrap_or_default()
+    }
+
+    /// Sends `parameters` as the form body together with the client credentials; `audience` is the endpoint URL.
+    pub fn apply(&self, request: RequestBuilder, audience: &str, parameters: &[(&str, &str)]) -> Result<RequestBuilder> {
+        let mut form: Vec<(&str, String)> = parameters.iter()
+            .filter(|(name, _)| !matches!(*name, "client_id" | "client_secret"))
+            .map(|(name, value)| (*name, value.to_string()))
+            .collect();
+        form.push(("client_id", self.client_id.to_owned()));
+
+        let request = match self.method {
+            ClientAuthMethod::None => request,
+            ClientAuthMethod::ClientSecretPost => {
+                form.push(("client_secret", self.client_secret.to_owned()));
+                request
+            },
+            // RFC 6749 section 2.3.1: credentials are form-encoded before being put in the Authorization header
+            ClientAuthMethod::ClientSecretBasic => request.basic_auth(form_encode(self.client_id), Some(form_encode(self.client_secret))),
+            ClientAuthMethod::ClientSecretJwt => {
+                let key = EncodingKey::from_secret(self.client_secret.as_bytes());
+                let assertion = jsonwebtoken::encode(&Header::new(Algorithm::HS256), &self.assertion(audience), &key)?;
+                form.push(("client_assertion_type", CLIENT_ASSERTION_TYPE.to_owned()));
+                form.push(("client_assertion", assertion));
+                request
+            },
+            ClientAuthMethod::PrivateKeyJwt => {
+                let key = self.signing_key.ok_or(ClientAuthError::SigningKeyMissing)?;
+                let assertion = key.sign("JWT", &self.assertion(audience))?;
+                form.push(("client_assertion_type", CLIENT_ASSERTION_TYPE.to_owned()));
+                form.push(("client_assertion", assertion));
+                request
+            },
+            // RFC 8705 section 2: the client is authenticated by the TLS handshake, client_id alone goes in the body
+            ClientAuthMethod::TlsClientAuth | ClientAuthMethod::SelfSignedTlsClientAuth => {
+                self.client_certificate.ok_or(ClientAuthError::CertificateMissing)?;
+                request
+            },
+        };
+        Ok(request.form(&form))
+    }
+
+    fn assertion(&self, audience: &str) -> ClientAssertion {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        ClientAssertion {
+            iss: self.client_id.to_owned(),
+            sub: self.client_id.to_owned(),
+            aud: audience.to_owned(),
+            jti: RandomString::new().generate(32),
+            iat: now,
+            exp: now + 60,
+        }
+    }
+}
+
+fn form_encode(value: &str) -> String {
+    form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{DecodingKey, Validation};
+    use crate::app::models::keys::tests::generate_es256_key;
+    use super::*;
+
+    fn form_value(body: &[u8], name: &str) -> Option<String> {
+        form_urlencoded::parse(body)
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    }
+
+    const TOKEN_ENDPOINT: &str = "https://example.com/token";
+
+    fn build(authentication: &ClientAuthentication<'_>) -> Result<reqwest::Request> {
+        let request = authentication.apply(reqwest::Client::new().post(TOKEN_ENDPOINT), TOKEN_ENDPOINT, &[("grant_type", "authorization_code")])?;
+        Ok(request.build().unwrap())
+    }
+
+    fn body(request: &reqwest::Request) -> &[u8] {
+        request.body().unwrap().as_bytes().unwrap()
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let supported = vec!["client_secret_post".to_owned(), "private_key_jwt".to_owned()];
+        assert_eq!(ClientAuthMethod::ClientSecretPost, ClientAuthMethod::negotiate(None, &supported, false).unwrap());
+        assert_eq!(ClientAuthMethod::PrivateKeyJwt, ClientAuthMethod::negotiate(None, &supported, true).unwrap());
+        assert_eq!(ClientAuthMethod::ClientSecretBasic, ClientAuthMethod::negotiate(None, &[], false).unwrap());
+
+        let result = ClientAuthMethod::negotiate(Some(ClientAuthMethod::ClientSecretJwt), &supported, false);
+        assert!(matches!(result, Err(ClientAuthError::MethodNotSupported("client_secret_jwt"))));
+    }
+
+    #[test]
+    fn test_client_secret_methods() {
+        let request = build(&ClientAuthentication::new(ClientAuthMethod::ClientSecretPost, "client-01", "secret/01")).unwrap();
+        assert_eq!(Some("secret/01".to_owned()), form_value(body(&request), "client_secret"));
+        assert_eq!(Some("authorization_code".to_owned()), form_value(body(&request), "grant_type"));
+
+        let request = build(&ClientAuthentication::new(ClientAuthMethod::ClientSecretBasic, "client-01", "secret/01")).unwrap();
+        // "client-01:secret%2F01"
+        assert_eq!("Basic Y2xpZW50LTAxOnNlY3JldCUyRjAx", request.headers()["Authorization"]);
+        assert_eq!(None, form_value(body(&request), "client_secret"));
+
+        let request = build(&ClientAuthentication::new(ClientAuthMethod::None, "client-01", "secret-01")).unwrap();
+        assert_eq!(Some("client-01".to_owned()), form_value(body(&request), "client_id"));
+        assert_eq!(None, form_value(body(&request), "client_secret"));
+        assert!(request.headers().get("Authorization").is_none());
+
+        let result = build(&ClientAuthentication::new(ClientAuthMethod::TlsClientAuth, "client-01", "secret-01"));
+        assert!(matches!(result, Err(ClientAuthError::CertificateMissing)));
+    }
+
+    #[test]
+    fn test_jwt_assertions() {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.set_audience(&[TOKEN_ENDPOINT]);
+
+        let request = build(&ClientAuthentication::new(ClientAuthMethod::ClientSecretJwt, "client-01", "secret-01")).unwrap();
+        assert_eq!(Some(CLIENT_ASSERTION_TYPE.to_owned()), form_value(body(&request), "client_assertion_type"));
+        let assertion = form_value(body(&request), "client_assertion").unwrap();
+        let claims = jsonwebtoken::decode::<ClientAssertion>(&assertion, &DecodingKey::from_secret(b"secret-01"), &validation).unwrap().claims;
+        assert_eq!("client-01", claims.sub);
+
+        let key = generate_es256_key("key-01");
+        let authentication = ClientAuthentication::new(ClientAuthMethod::PrivateKeyJwt, "client-01", "secret-01")
+            .with_signing_key(Some(&key));
+        let request = build(&authentication).unwrap();
+        assert_eq!(None, form_value(body(&request), "client_secret"));
+        let assertion = form_value(body(&request), "client_assertion").unwrap();
+        validation.algorithms = vec![Algorithm::ES256];
+        let claims = jsonwebtoken::decode::<ClientAssertion>(&assertion, &key.public_jwk().decoding_key().unwrap(), &validation).unwrap().claims;
+        assert_eq!("client-01", claims.iss);
+
+        let result = build(&ClientAuthentication::new(ClientAuthMethod::PrivateKeyJwt, "client-01", "secret-01"));
+        assert!(matches!(result, Err(ClientAuthError::SigningKeyMissing)));
+    }
+}
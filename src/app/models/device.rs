@@ -0,0 +1,255 @@
+use std::time::{Duration, Instant};
+
+use serde_derive::Deserialize;
+use thiserror::Error;
+
+use crate::app::models::client_auth::{ClientAuthError, ClientAuthentication};
+use crate::app::models::token::TokenResponse;
+
+const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+#[derive(Debug, Error)]
+pub enum DeviceError {
+    #[error("device code expired before the user approved the request")]
+    Expired,
+
+    #[error("user denied the authorization request")]
+    AccessDenied,
+
+    #[error("device authorization failed: {0}")]
+    Rejected(String),
+
+    #[error("device authorization request failed")]
+    RequestFailed(#[from] reqwest::Error),
+
+    #[error("client authentication failed: {0}")]
+    ClientAuthFailed(#[from] ClientAuthError),
+}
+
+type Result<T> = std::result::Result<T, DeviceError>;
+
+/// Device authorization response from RFC 8628 section 3.2.
+#[derive(Debug, Deserialize)]
+pub struct DeviceAuthorizationResponse {
+    pub device_code: String,
+    pub user_code: String,
+    // Google still answers with the pre-RFC "verification_url"
+    #[serde(alias = "verification_url")]
+    pub verification_uri: String,
+    pub verification_uri_complete: Option<String>,
+    pub expires_in: u64,
+    #[serde(default = "DeviceAuthorizationResponse::default_interval")]
+    pub interval: u64,
+}
+
+impl DeviceAuthorizationResponse {
+    fn default_interval() -> u64 {
+        5
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+impl ErrorResponse {
+    fn into_error(self) -> DeviceError {
+        match self.error.as_str() {
+            "expired_token" => DeviceError::Expired,
+            "access_denied" => DeviceError::AccessDenied,
+            _ => DeviceError::Rejected(self.error_description.unwrap_or(self.error)),
+        }
+    }
+}
+
+// GitHub reports errors with 200 OK, so responses are told apart by their body rather than the status
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum EndpointResponse<T> {
+    Success(T),
+    Error(ErrorResponse),
+}
+
+/// Device authorization request from RFC 8628 section 3.1, authenticating the client as at the token endpoint.
+pub struct DeviceAuthorizationRequest<'a> {
+    endpoint: &'a str,
+    authentication: &'a ClientAuthentication<'a>,
+    scope: &'a str,
+}
+
+impl<'a> DeviceAuthorizationRequest<'a> {
+    pub fn new(endpoint: &'a str, authentication: &'a ClientAuthentication<'a>, scope: &'a str) -> Self {
+        Self {
+            endpoint,
+            authentication,
+            scope,
+        }
+    }
+
+    pub async fn execute(&self) -> Result<DeviceAuthorizationResponse> {
+        let request = self.authentication.http_client().post(self.endpoint)
+            .header("Accept", "application/json");
+        let response = self.authentication.apply(request, self.endpoint, &[("scope", self.scope)])?
+            .send()
+            .await?
+            .json::<EndpointResponse<DeviceAuthorizationResponse>>()
+            .await?;
+
+        match response {
+            EndpointResponse::Success(authorization) => Ok(authorization),
+            EndpointResponse::Error(error) => Err(error.into_error()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DevicePoll {
+    Pending,
+    SlowDown,
+    Complete(TokenResponse),
+}
+
+/// Device access token request from RFC 8628 section 3.4.
+pub struct DeviceTokenRequest<'a> {
+    token_endpoint: &'a str,
+    authentication: &'a ClientAuthentication<'a>,
+}
+
+impl<'a> DeviceTokenRequest<'a> {
+    pub fn new(token_endpoint: &'a str, authentication: &'a ClientAuthentication<'a>) -> Self {
+        Self {
+            token_endpoint,
+            authentication,
+        }
+    }
+
+    /// Polls until the user approves or denies the request, or the device code expires.
+    pub async fn poll(&self, authorization: &DeviceAuthorizationResponse) -> Result<TokenResponse> {
+        let deadline = Instant::now() + Duration::from_secs(authorization.expires_in);
+        let mut interval = Duration::from_secs(authorization.interval);
+        loop {
+            tokio::time::sleep(interval).await;
+            if Instant::now() >= deadline {
+                return Err(DeviceError::Expired)
+            }
+            match self.execute(&authorization.device_code).await? {
+                DevicePoll::Pending => {},
+                // RFC 8628 section 3.5: slow_down increases the interval by 5 seconds for all subsequent requests
+                DevicePoll::SlowDown => interval += Duration::from_secs(5),
+                DevicePoll::Complete(token_response) => return Ok(token_response),
+            }
+        }
+    }
+
+    pub async fn execute(&self, device_code: &str) -> Result<DevicePoll> {
+        let parameters = [
+            ("grant_type", DEVICE_CODE_GRANT_TYPE),
+            ("device_code", device_code),
+        ];
+        let request = self.authentication.http_client().post(self.token_endpoint)
+            .header("Accept", "application/json");
+        let response = self.authentication.apply(request, self.token_endpoint, &parameters)?
+            .send()
+            .await?
+            .json::<EndpointResponse<TokenResponse>>()
+            .await?;
+
+        match response {
+            EndpointResponse::Success(token_response) => Ok(DevicePoll::Complete(token_response)),
+            EndpointResponse::Error(error) => match error.error.as_str() {
+                "authorization_pending" => Ok(DevicePoll::Pending),
+                "slow_down" => Ok(DevicePoll::SlowDown),
+                _ => Err(error.into_error()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::MockServer;
+    use httpmock::Method::POST;
+    use serde_json::json;
+    use crate::app::models::client_auth::ClientAuthMethod;
+    use super::*;
+
+    #[actix_rt::test]
+    async fn test_device_authorization() {
+        let server = MockServer::start();
+        let _mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/device")
+                .body_contains("client_id=client-01")
+                .body_contains("client_secret=secret-01")
+                .body_contains("scope=openid");
+            then.status(200).json_body(json!({
+                "device_code": "device-code-01",
+                "user_code": "ABCD-EFGH",
+                "verification_url": "https://example.com/device",
+                "expires_in": 1800,
+            }));
+        });
+
+        let endpoint = server.url("/device");
+        let authentication = ClientAuthentication::new(ClientAuthMethod::ClientSecretPost, "client-01", "secret-01");
+        let authorization = DeviceAuthorizationRequest::new(&endpoint, &authentication, "openid").execute().await.unwrap();
+        assert_eq!("ABCD-EFGH", authorization.user_code);
+        assert_eq!("https://example.com/device", authorization.verification_uri);
+        assert_eq!(5, authorization.interval);
+    }
+
+    #[actix_rt::test]
+    async fn test_device_token_responses() {
+        let server = MockServer::start();
+        let responses = [
+            ("device-code-01", json!({ "error": "authorization_pending" })),
+            ("device-code-02", json!({ "error": "slow_down" })),
+            ("device-code-03", json!({ "error": "expired_token" })),
+            ("device-code-04", json!({ "error": "access_denied" })),
+            ("device-code-05", json!({ "access_token": "access-token-01", "token_type": "bearer" })),
+        ];
+        for (device_code, body) in responses {
+            server.mock(|when, then| {
+                when.method(POST)
+                    .path("/token")
+                    .body_contains("grant_type=urn%3Aietf%3Aparams%3Aoauth%3Agrant-type%3Adevice_code")
+                    .body_contains(format!("device_code={}", device_code));
+                then.status(200).json_body(body);
+            });
+        }
+
+        let token_endpoint = server.url("/token");
+        let authentication = ClientAuthentication::new(ClientAuthMethod::None, "client-01", "");
+        let request = DeviceTokenRequest::new(&token_endpoint, &authentication);
+        assert!(matches!(request.execute("device-code-01").await, Ok(DevicePoll::Pending)));
+        assert!(matches!(request.execute("device-code-02").await, Ok(DevicePoll::SlowDown)));
+        assert!(matches!(request.execute("device-code-03").await, Err(DeviceError::Expired)));
+        assert!(matches!(request.execute("device-code-04").await, Err(DeviceError::AccessDenied)));
+        assert!(matches!(request.execute("device-code-05").await, Ok(DevicePoll::Complete(response)) if response.access_token == "access-token-01"));
+    }
+
+    #[actix_rt::test]
+    async fn test_poll_stops_at_expiry() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST).path("/token");
+            then.status(400).json_body(json!({ "error": "authorization_pending" }));
+        });
+
+        let authorization = DeviceAuthorizationResponse {
+            device_code: "device-code-01".to_owned(),
+            user_code: "ABCD-EFGH".to_owned(),
+            verification_uri: "https://example.com/device".to_owned(),
+            verification_uri_complete: None,
+            expires_in: 0,
+            interval: 0,
+        };
+        let token_endpoint = server.url("/token");
+        let authentication = ClientAuthentication::new(ClientAuthMethod::None, "client-01", "");
+        let result = DeviceTokenRequest::new(&token_endpoint, &authentication).poll(&authorization).await;
+        assert!(matches!(result, Err(DeviceError::Expired)));
+        assert_eq!(0, mock.hits());
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use reqwest::header::{AUTHORIZATION, HeaderValue, InvalidHeaderValue};
+use reqwest::{Client, Request, RequestBuilder, Response, StatusCode};
+use ring::rand::SystemRandom;
+use ring::signature::{self, EcdsaKeyPair, KeyPair};
+use serde_derive::Serialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use url::Url;
+
+use crate::app::models::base64url;
+use crate::app::models::oidc::discovery::{JsonWebKey, KeyParameters};
+use crate::app::models::random::{RandomString, RandomStringGenerator};
+use crate::app::models::token::TokenResponse;
+
+#[derive(Debug, Error)]
+pub enum DpopError {
+    #[error("generating DPoP key failed")]
+    KeyGenerationFailed,
+
+    #[error("signing DPoP proof failed")]
+    SigningFailed,
+
+    #[error("expected a DPoP-bound token, but token_type is {0}")]
+    NotDpopBound(String),
+
+    #[error("invalid DPoP header value")]
+    InvalidHeader(#[from] InvalidHeaderValue),
+
+    #[error("DPoP request failed")]
+    RequestFailed(#[from] reqwest::Error),
+}
+
+type Result<T> = std::result::Result<T, DpopError>;
+
+const PROOF_TYPE: &str = "dpop+jwt";
+const NONCE_HEADER: &str = "DPoP-Nonce";
+
+#[derive(Serialize)]
+struct ProofHeader<'a> {
+    typ: &'static str,
+    alg: &'static str,
+    jwk: &'a JsonWebKey,
+}
+
+/// DPoP proof claims from RFC 9449 section 4.2.
+#[derive(Serialize)]
+struct ProofClaims {
+    jti: String,
+    htm: String,
+    htu: String,
+    iat: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ath: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nonce: Option<String>,
+}
+
+/// Ephemeral ES256 key pair that DPoP-bound tokens (RFC 9449) are tied to, generated per login session.
+///
+/// Clones share the key and the nonces last handed out by each server.
+#[derive(Clone)]
+pub struct DpopKey {
+    key_pair: Arc<EcdsaKeyPair>,
+    jwk: JsonWebKey,
+    nonces: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl fmt::Debug for DpopKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DpopKey").field("jkt", &self.thumbprint()).finish()
+    }
+}
+
+impl DpopKey {
+    pub fn generate() -> Result<Self> {
+        let algorithm = &signature::ECDSA_P256_SHA256_FIXED_SIGNING;
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(algorithm, &SystemRandom::new())
+            .map_err(|_| DpopError::KeyGenerationFailed)?;
+        let key_pair = EcdsaKeyPair::from_pkcs8(algorithm, pkcs8.as_ref())
+            .map_err(|_| DpopError::KeyGenerationFailed)?;
+        let jwk = JsonWebKey::from_p256_public_key(key_pair.public_key().as_ref());
+        Ok(Self {
+            key_pair: Arc::new(key_pair),
+            jwk,
+            nonces: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// JWK thumbprint (RFC 7638) of the public key, as found in `cnf.jkt` of bound tokens.
+    pub fn thumbprint(&self) -> String {
+        match &self.jwk.key {
+            KeyParameters::Ec { x, y, .. } => {
+                let canonical = format!(r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#, x, y);
+                base64url::encode(Sha256::digest(canonical.as_bytes()))
+            },
+            _ => unreachable!("DPoP keys are always P-256"),
+        }
+    }
+
+    /// Signs a proof for a request; `access_token` adds the `ath` claim for resource requests.
+    pub fn proof(&self, method: &str, url: &Url, access_token: Option<&str>) -> Result<String> {
+        let mut htu = url.clone();
+        htu.set_query(None);
+        htu.set_fragment(None);
+        let claims = ProofClaims {
+            jti: RandomString::new().generate(32),
+            htm: method.to_owned(),
+            htu: htu.into(),
+            iat: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            ath: access_token.map(|token| base64url::encode(Sha256::digest(token.as_bytes()))),
+            nonce: self.nonce(url),
+        };
+        let header = ProofHeader {
+            typ: PROOF_TYPE,
+            alg: "ES256",
+            jwk: &self.jwk,
+        };
+        let header = serde_json::to_vec(&header).map_err(|_| DpopError::SigningFailed)?;
+        let claims = serde_json::to_vec(&claims).map_err(|_| DpopError::SigningFailed)?;
+        let message = format!("{}.{}", base64url::encode(&header), base64url::encode(&claims));
+        let signature = self.key_pair.sign(&SystemRandom::new(), message.as_bytes())
+            .map_err(|_| DpopError::SigningFailed)?;
+        Ok(format!("{}.{}", message, base64url::encode(signature.as_ref())))
+    }
+
+    /// Sends a request with a DPoP proof, and with `access_token` in a DPoP `Authorization` header if given.
+    ///
+    /// A server may reject the first proof and ask for one with its nonce (RFC 9449 sections 8 and 9);
+    /// the request is then retried once with the new nonce.
+    pub async fn send(&self, client: &Client, request: Request, access_token: Option<&str>) -> Result<Response> {
+        let retry = request.try_clone();
+        let used_nonce = self.nonce(request.url());
+        let response = self.send_once(client, request, access_token).await?;
+
+        let challenged = matches!(response.status(), StatusCode::BAD_REQUEST | StatusCode::UNAUTHORIZED)
+            && self.nonce(response.url()) != used_nonce;
+        match retry {
+            Some(retry) if challenged => self.send_once(client, retry, access_token).await,
+            _ => Ok(response),
+        }
+    }
+
+    async fn send_once(&self, client: &Client, mut request: Request, access_token: Option<&str>) -> Result<Response> {
+        let proof = self.proof(request.method().as_str(), request.url(), access_token)?;
+        let headers = request.headers_mut();
+        headers.insert("DPoP", HeaderValue::from_str(&proof)?);
+        if let Some(access_token) = access_token {
+            headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("DPoP {}", access_token))?);
+        }
+
+        let response = client.execute(request).await?;
+        if let Some(nonce) = response.headers().get(NONCE_HEADER).and_then(|value| value.to_str().ok()) {
+            self.nonces.lock().unwrap().insert(origin(response.url()), nonce.to_owned());
+        }
+        Ok(response)
+    }
+
+    fn nonce(&self, url: &Url) -> Option<String> {
+        self.nonces.lock().unwrap().get(&origin(url)).cloned()
+    }
+}
+
+/// Sends a request with a proof from `dpop_key` if there is one, otherwise with `access_token` as a bearer token.
+pub async fn send(dpop_key: Option<&DpopKey>, client: &Client, request: RequestBuilder, access_token: Option<&str>) -> Result<Response> {
+    match (dpop_key, access_token) {
+        (Some(dpop_key), _) => dpop_key.send(client, request.build()?, access_token).await,
+        (None, Some(access_token)) => Ok(request.bearer_auth(access_token).send().await?),
+        (None, None) => Ok(request.send().await?),
+    }
+}
+
+/// RFC 9449 section 5: a token requested with a proof must come back as `token_type: DPoP`, not as a bearer token.
+pub fn verify_token_type(token_response: &TokenResponse) -> Result<()> {
+    match token_response.token_type.as_deref() {
+        Some(token_type) if token_type.eq_ignore_ascii_case("DPoP") => Ok(()),
+        token_type => Err(DpopError::NotDpopBound(token_type.unwrap_or("missing").to_owned())),
+    }
+}
+
+fn origin(url: &Url) -> String {
+    url.origin().ascii_serialization()
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::{HttpMockRequest, MockServer};
+    use httpmock::Method::POST;
+    use jsonwebtoken::{Algorithm, Validation};
+    use serde_json::{json, Value};
+    use super::*;
+
+    fn proof_claims(proof: &str, key: &DpopKey) -> Value {
+        let header = jsonwebtoken::decode_header(proof).unwrap();
+        assert_eq!(Some(PROOF_TYPE.to_owned()), header.typ);
+        let mut validation = Validation::new(Algorithm::ES256);
+        validation.required_spec_claims.clear();
+        validation.validate_exp = false;
+        jsonwebtoken::decode::<Value>(proof, &key.jwk.decoding_key().unwrap(), &validation).unwrap().claims
+    }
+
+    fn has_nonce(request: &HttpMockRequest) -> bool {
+        let proof = request.headers.iter().flatten()
+            .find(|(name, _)| name.eq_ignore_ascii_case("DPoP"))
+            .map(|(_, value)| value.to_owned())
+            .unwrap_or_default();
+        proof.split('.').nth(1)
+            .and_then(|payload| base64url::decode(payload).ok())
+            .and_then(|payload| serde_json::from_slice::<Value>(&payload).ok())
+            .is_some_and(|claims| claims["nonce"] == "nonce-01")
+    }
+
+    fn lacks_nonce(request: &HttpMockRequest) -> bool {
+        !has_nonce(request)
+    }
+
+    #[test]
+    fn test_proof() {
+        let key = DpopKey::generate().unwrap();
+        let url = Url::parse("https://example.com/resource?query=1#fragment").unwrap();
+        let proof = key.proof("GET", &url, Some("access-token-01")).unwrap();
+
+        let header: Value = serde_json::from_slice(&base64url::decode(proof.split('.').next().unwrap()).unwrap()).unwrap();
+        assert_eq!(json!({ "kty": "EC", "crv": "P-256", "x": header["jwk"]["x"], "y": header["jwk"]["y"] }), header["jwk"]);
+
+        let claims = proof_claims(&proof, &key);
+        assert_eq!("GET", claims["htm"]);
+        assert_eq!("https://example.com/resource", claims["htu"]);
+        assert_eq!(base64url::encode(Sha256::digest(b"access-token-01")), claims["ath"]);
+        assert_eq!(None, claims.get("nonce"));
+        assert_eq!(43, key.thumbprint().len());
+    }
+
+    #[actix_rt::test]
+    async fn test_nonce_challenge_is_retried() {
+        let server = MockServer::start();
+        let challenge = server.mock(|when, then| {
+            when.method(POST).path("/token").matches(lacks_nonce);
+            then.status(400)
+                .header(NONCE_HEADER, "nonce-01")
+                .json_body(json!({ "error": "use_dpop_nonce" }));
+        });
+        let token = server.mock(|when, then| {
+            when.method(POST)
+                .path("/token")
+                .header_exists("DPoP")
+                .body_contains("grant_type=authorization_code")
+                .matches(has_nonce);
+            then.status(200).json_body(json!({ "access_token": "access-token-01", "token_type": "DPoP" }));
+        });
+
+        let key = DpopKey::generate().unwrap();
+        let client = Client::new();
+        let request = client.post(server.url("/token")).form(&[("grant_type", "authorization_code")]).build().unwrap();
+        let response = key.send(&client, request, None).await.unwrap()
+            .json::<TokenResponse>()
+            .await
+            .unwrap();
+        assert_eq!(1, challenge.hits());
+        assert_eq!(1, token.hits());
+        assert!(verify_token_type(&response).is_ok());
+
+        // The nonce is remembered for later requests to the same server
+        let request = client.post(server.url("/token")).form(&[("grant_type", "authorization_code")]).build().unwrap();
+        key.send(&client, request, None).await.unwrap();
+        assert_eq!(1, challenge.hits());
+        assert_eq!(2, token.hits());
+    }
+
+    #[test]
+    fn test_verify_token_type() {
+        let response = |token_type: &str| TokenResponse {
+            access_token: "access-token-01".to_owned(),
+            token_type: Some(token_type.to_owned()),
+            expires_in: None,
+            refresh_token: None,
+            scope: None,
+            id_token: None,
+        };
+        assert!(verify_token_type(&response("dpop")).is_ok());
+        assert!(matches!(verify_token_type(&response("Bearer")), Err(DpopError::NotDpopBound(token_type)) if token_type == "Bearer"));
+    }
+}
@@ -1,150 +1,180 @@
-use actix_web::{HttpResponse, ResponseError};
-use anyhow::Result;
-use rand::{RngCore, SeedableRng, rngs::StdRng};
+use async_trait::async_trait;
 use serde_derive::{Deserialize, Serialize};
 use thiserror::Error;
 use url::Url;
 
 use crate::app::config::GithubConfig;
-
-pub struct GithubAutorizationRequest<'a> {
-    config: &'a GithubConfig,
+use crate::app::models::client_auth::{ClientAuthError, ClientAuthMethod, ClientAuthentication};
+use crate::app::models::pkce::{PkceGenerator, PkceMethod};
+use crate::app::models::provider::{AuthProvider, AuthorizationRequest, Identity, ProviderContext, ProviderError, RequestAttributes};
+use crate::app::models::revocation::{LogoutReport, RevocationError, RevocationResult};
+use crate::app::models::token::{TokenResponse, TokenSet};
+
+const AUTHORIZATION_ENDPOINT: &str = "https://github.com/login/oauth/authorize";
+pub const DEVICE_AUTHORIZATION_ENDPOINT: &str = "https://github.com/login/device/code";
+pub const TOKEN_ENDPOINT: &str = "https://github.com/login/oauth/access_token";
+
+// GitHub has no discovery document; its PKCE support is limited to S256
+const CODE_CHALLENGE_METHODS_SUPPORTED: &[&str] = &["S256"];
+const TOKEN_ENDPOINT_AUTH_METHODS_SUPPORTED: &[&str] = &["client_secret_post", "client_secret_basic"];
+
+pub struct GithubProvider {
+    config: GithubConfig,
 }
 
-impl<'a> GithubAutorizationRequest<'a> {
-    pub fn new(config: &'a GithubConfig) -> Self {
+impl GithubProvider {
+    pub fn new(config: GithubConfig) -> Self {
         Self {
             config,
         }
     }
+}
+
+#[async_trait(?Send)]
+impl AuthProvider for GithubProvider {
+    fn name(&self) -> &str {
+        "github"
+    }
 
-    pub fn create(&self) -> Result<(String, String)> {
-        let config = self.config;
-        let state = self.generate_state();
-        let base = "https://github.com/login/oauth/authorize";
+    async fn authorization_request(&self, _context: &ProviderContext<'_>) -> Result<AuthorizationRequest, ProviderError> {
+        let config = &self.config;
+        let supported: Vec<String> = CODE_CHALLENGE_METHODS_SUPPORTED.iter().map(|method| method.to_string()).collect();
+        let pkce_method = PkceMethod::negotiate(config.pkce_method, &supported);
+        let pkce = PkceGenerator::default().generate_with_method(pkce_method, 32);
+        let attributes = RequestAttributes::new(pkce.code_verifier);
         let parameters = vec![
-            ("client_id", &config.client_id),
+            ("client_id", config.client_id.as_str()),
             ("redirect_uri", &config.redirect_uri),
             ("scope", &config.scope),
-            ("state", &state),
+            ("state", &attributes.state),
+            ("code_challenge_method", pkce.code_challenge_method.as_str()),
+            ("code_challenge", &pkce.code_challenge),
         ];
-        let url = Url::parse_with_params(base, &parameters)?;
-        Ok((url.into(), state))
+        let url = Url::parse_with_params(AUTHORIZATION_ENDPOINT, &parameters).map_err(GithubSigninError::from)?;
+        Ok(AuthorizationRequest {
+            request_uri: url.into(),
+            attributes,
+        })
     }
 
-    fn generate_state(&self) -> String {
-        let mut rng = StdRng::from_entropy();
-        let mut rs: [u8; 32] = [0; 32];
-        rng.fill_bytes(&mut rs);
-        base64::encode_config(rs, base64::URL_SAFE)
+    async fn exchange_code(&self, _context: &ProviderContext<'_>, code: &str, attributes: &RequestAttributes) -> Result<TokenSet, ProviderError> {
+        let token_response = AccessTokenRequest::new(&self.config)
+            .execute(code, &attributes.state, &attributes.code_verifier)
+            .await?;
+        Ok(TokenSet::new(token_response))
+    }
+
+    async fn identity(&self, _context: &ProviderContext<'_>, tokens: &TokenSet, _attributes: &RequestAttributes) -> Result<Identity, ProviderError> {
+        let user = UserRequest::new()
+            .execute(&tokens.access_token)
+            .await?;
+        Ok(Identity::new(self.name(), user.id.to_string(), &user))
     }
-}
 
-#[derive(Debug, Deserialize)]
-pub struct GithubAuthorizationResponse {
-    pub code: String,
-    pub state: String,
+    async fn sign_out(&self, _context: &ProviderContext<'_>, tokens: &TokenSet) -> Result<LogoutReport, ProviderError> {
+        Ok(GithubSignout::new(&self.config).execute(tokens).await)
+    }
 }
 
 #[derive(Debug, Error)]
 pub enum GithubSigninError {
-    #[error("no saved state found")]
-    StateNotFound,
-
-    #[error("states do not match")]
-    StateMismatch,
-
     #[error(transparent)]
     RequestFailed(#[from] reqwest::Error),
 
-    #[error("not implemented yet")]
-    NotImplemented,
-}
+    #[error("client authentication failed: {0}")]
+    ClientAuthFailed(#[from] ClientAuthError),
 
-impl ResponseError for GithubSigninError {
-    fn status_code(&self) -> reqwest::StatusCode {
-        reqwest::StatusCode::INTERNAL_SERVER_ERROR
-    }
+    #[error("invalid url")]
+    InvalidUrl(#[from] url::ParseError),
 
-    fn error_response(&self) -> HttpResponse {
-        HttpResponse::InternalServerError().finish()
-    }
+    #[error("not implemented yet")]
+    NotImplemented,
 }
 
-pub struct GithubSignin<'a> {
+struct AccessTokenRequest<'a> {
     config: &'a GithubConfig,
 }
 
-impl<'a> GithubSignin<'a> {
-    pub fn new(config: &'a GithubConfig) -> Self {
+impl<'a> AccessTokenRequest<'a> {
+    fn new(config: &'a GithubConfig) -> Self {
         Self {
             config,
         }
     }
 
-    pub async fn execute(&self, auth: &GithubAuthorizationResponse, saved_state: Option<String>) -> Result<GithubUser, GithubSigninError> {
-        let state = saved_state.ok_or(GithubSigninError::StateNotFound)?;
-        if state != auth.state {
-            return Err(GithubSigninError::StateMismatch)
-        }
+    async fn execute(&self, code: &str, state: &str, code_verifier: &str) -> Result<TokenResponse, GithubSigninError> {
+        let config = self.config;
+        let supported: Vec<String> = TOKEN_ENDPOINT_AUTH_METHODS_SUPPORTED.iter().map(|method| method.to_string()).collect();
+        let method = config.token_endpoint_auth_method.unwrap_or(ClientAuthMethod::ClientSecretPost);
+        let method = ClientAuthMethod::negotiate(Some(method), &supported, false)?;
+        let authentication = ClientAuthentication::new(method, &config.client_id, &config.client_secret);
 
-        let access_token = AccessTokenRequest::new(self.config)
-            .execute(&auth.code, &state)
-            .await?;
+        let client = reqwest::Client::new();
+        let parameters = [
+            ("code", code),
+            ("redirect_uri", &config.redirect_uri),
+            ("state", state),
+            ("code_verifier", code_verifier),
+        ];
+        let request = client.post(TOKEN_ENDPOINT)
+            .header("Accept", "application/json");
+        let result = authentication.apply(request, TOKEN_ENDPOINT, &parameters)?
+            .send()
+            .await?
+            .json::<TokenResponse>().await?;
 
-        UserRequest::new()
-            .execute(&access_token.token)
-            .await
+        Ok(result)
     }
 }
 
-struct AccessTokenRequest<'a> {
+/// GitHub does not implement RFC 7009, but OAuth apps can delete their own grants through the REST API.
+pub struct GithubSignout<'a> {
     config: &'a GithubConfig,
 }
 
-#[derive(Deserialize)]
-struct AccessToken {
-    #[serde(rename(deserialize = "access_token"))]
-    token: String,
-}
-
-impl<'a> AccessTokenRequest<'a> {
-    fn new(config: &'a GithubConfig) -> Self {
+impl<'a> GithubSignout<'a> {
+    pub fn new(config: &'a GithubConfig) -> Self {
         Self {
             config,
         }
     }
 
-    async fn execute(&self, code: &String, state: &String) -> Result<AccessToken, GithubSigninError> {
+    pub async fn execute(&self, tokens: &TokenSet) -> LogoutReport {
+        let result = self.delete_token(&tokens.access_token).await;
+        LogoutReport {
+            revocations: vec![RevocationResult::new("access_token", result)],
+        }
+    }
+
+    async fn delete_token(&self, access_token: &str) -> Result<(), RevocationError> {
         let config = self.config;
         let client = reqwest::Client::new();
-        let parameters = [
-            ("client_id", &config.client_id),
-            ("client_secret", &config.client_secret),
-            ("code", code),
-            ("redirect_uri", &config.redirect_uri),
-            ("state", state),
-        ];
-        let result = client.post("https://github.com/login/oauth/access_token")
-            .header("Accept", "application/json")
-            .form(&parameters)
+        let response = client.delete(format!("https://api.github.com/applications/{}/token", config.client_id))
+            .header("Accept", "application/vnd.github+json")
+            .header("User-Agent", "Webauthexp")
+            .basic_auth(&config.client_id, Some(&config.client_secret))
+            .json(&serde_json::json!({ "access_token": access_token }))
             .send()
-            .await?
-            .json::<AccessToken>().await?;
+            .await?;
 
-        Ok(result)
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(RevocationError::Rejected(response.status()))
+        }
     }
 }
 
-struct UserRequest {
+#[derive(Default)]
+pub struct UserRequest {
 }
 
 impl UserRequest {
-    fn new() -> Self {
+    pub fn new() -> Self {
         Self {}
     }
 
-    async fn execute(&self, access_token: &str) -> Result<GithubUser, GithubSigninError> {
+    pub async fn execute(&self, access_token: &str) -> Result<GithubUser, GithubSigninError> {
         let client = reqwest::Client::new();
         let response = client.get("https://api.github.com/user")
             .header("Accept", "application/vnd.github.v3+json")
@@ -0,0 +1,228 @@
+use std::path::Path;
+
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::PKey;
+use openssl::stack::Stack;
+use openssl::x509::X509;
+use reqwest::{Certificate, Client, Identity};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tokio::{fs::File, io::AsyncReadExt};
+
+use crate::app::config::ClientCertificateConfig;
+use crate::app::models::base64url;
+use crate::app::models::token::Confirmation;
+
+#[derive(Debug, Error)]
+pub enum MtlsError {
+    #[error("failed to read client certificate: {0}")]
+    ReadFailed(#[from] std::io::Error),
+
+    #[error("no certificate found in client certificate file")]
+    CertificateMissing,
+
+    #[error("client certificate or key is invalid: {0}")]
+    InvalidCertificate(#[from] openssl::error::ErrorStack),
+
+    #[error("building the TLS client failed: {0}")]
+    ClientBuildFailed(#[from] reqwest::Error),
+
+    #[error("access token is bound to a different client certificate")]
+    CertificateMismatch,
+
+    #[error("provider issues certificate-bound access tokens, but the access token has no x5t#S256")]
+    ConfirmationMissing,
+}
+
+type Result<T> = std::result::Result<T, MtlsError>;
+
+/// Client certificate presented to the provider for mutual-TLS (RFC 8705), with an HTTP client that presents it.
+pub struct ClientCertificate {
+    client: Client,
+    thumbprint: String,
+}
+
+impl ClientCertificate {
+    pub async fn load(config: &ClientCertificateConfig) -> Result<Self> {
+        let certificate = read(&config.certificate).await?;
+        let private_key = read(&config.private_key).await?;
+        let ca_certificate = match &config.ca_certificate {
+            Some(path) => Some(read(path).await?),
+            None => None,
+        };
+        Self::from_pem(&certificate, &private_key, ca_certificate.as_deref())
+    }
+
+    pub fn from_pem(certificate: &[u8], private_key: &[u8], ca_certificate: Option<&[u8]>) -> Result<Self> {
+        let mut chain = X509::stack_from_pem(certificate)?.into_iter();
+        let leaf = chain.next().ok_or(MtlsError::CertificateMissing)?;
+        let key = PKey::private_key_from_pem(private_key)?;
+
+        // reqwest only takes PKCS#12 identities with native-tls, so the PEM files are repackaged
+        let mut builder = Pkcs12::builder();
+        let mut intermediates = Stack::new()?;
+        for certificate in chain {
+            intermediates.push(certificate)?;
+        }
+        if !intermediates.is_empty() {
+            builder.ca(intermediates);
+        }
+        let pkcs12 = builder.build("", "client", &key, &leaf)?.to_der()?;
+
+        let mut client = Client::builder().identity(Identity::from_pkcs12_der(&pkcs12, "")?);
+        if let Some(ca_certificate) = ca_certificate {
+            client = client.add_root_certificate(Certificate::from_pem(ca_certificate)?);
+        }
+        Ok(Self {
+            client: client.build()?,
+            thumbprint: thumbprint(&leaf.to_der()?),
+        })
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// `x5t#S256` of the certificate, from RFC 8705 section 3.1.
+    pub fn thumbprint(&self) -> &str {
+        &self.thumbprint
+    }
+
+    /// Checks that a JWT access token is bound to this certificate.
+    ///
+    /// Opaque tokens cannot be checked here. A JWT without a binding is only an error when the
+    /// provider advertises `tls_client_certificate_bound_access_tokens`.
+    pub fn verify_binding(&self, access_token: &str, required: bool) -> Result<()> {
+        let confirmation = match Confirmation::from_access_token(access_token) {
+            Some(confirmation) => confirmation,
+            None => return Ok(()),
+        };
+        match confirmation.x5t_s256 {
+            Some(thumbprint) if thumbprint == self.thumbprint => Ok(()),
+            Some(_) => Err(MtlsError::CertificateMismatch),
+            None if required => Err(MtlsError::ConfirmationMissing),
+            None => Ok(()),
+        }
+    }
+}
+
+async fn read(path: &Path) -> Result<Vec<u8>> {
+    let mut file = File::open(path).await?;
+    let mut content = vec![];
+    file.read_to_end(&mut content).await?;
+    Ok(content)
+}
+
+fn thumbprint(der: &[u8]) -> String {
+    base64url::encode(Sha256::digest(der))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::Private;
+    use openssl::ssl::{SslAcceptor, SslMethod, SslVerifyMode};
+    use openssl::x509::X509NameBuilder;
+    use openssl::x509::extension::SubjectAlternativeName;
+    use serde_json::{json, Value};
+    use super::*;
+
+    fn self_signed(common_name: &str) -> (X509, PKey<Private>) {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, common_name).unwrap();
+        let name = name.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap()).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        let san = SubjectAlternativeName::new().dns(common_name).build(&builder.x509v3_context(None, None)).unwrap();
+        builder.append_extension(san).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        (builder.build(), key)
+    }
+
+    fn create_client_certificate(ca_certificate: Option<&X509>) -> (ClientCertificate, X509) {
+        let (certificate, key) = self_signed("client-01");
+        let ca_certificate = ca_certificate.map(|certificate| certificate.to_pem().unwrap());
+        let client_certificate = ClientCertificate::from_pem(
+            &certificate.to_pem().unwrap(),
+            &key.private_key_to_pem_pkcs8().unwrap(),
+            ca_certificate.as_deref(),
+        ).unwrap();
+        (client_certificate, certificate)
+    }
+
+    fn jwt(claims: Value) -> String {
+        let encode = |value: &Value| base64url::encode(value.to_string());
+        format!("{}.{}.signature", encode(&json!({ "alg": "RS256" })), encode(&claims))
+    }
+
+    #[actix_rt::test]
+    async fn test_client_certificate_is_presented() {
+        let (server_certificate, server_key) = self_signed("localhost");
+        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+        acceptor.set_certificate(&server_certificate).unwrap();
+        acceptor.set_private_key(&server_key).unwrap();
+        // The stand-in provider accepts any client certificate and echoes its thumbprint
+        acceptor.set_verify_callback(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT, |_, _| true);
+        let acceptor = acceptor.build();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut stream = acceptor.accept(stream).unwrap();
+            let peer_certificate = stream.ssl().peer_certificate().unwrap();
+            let mut reader = BufReader::new(&mut stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let body = json!({ "x5t#S256": thumbprint(&peer_certificate.to_der().unwrap()) }).to_string();
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body).unwrap();
+        });
+
+        let (client_certificate, _) = create_client_certificate(Some(&server_certificate));
+        let response = client_certificate.client()
+            .get(format!("https://localhost:{}/", port))
+            .send()
+            .await
+            .unwrap()
+            .json::<Value>()
+            .await
+            .unwrap();
+        server.join().unwrap();
+        assert_eq!(client_certificate.thumbprint(), response["x5t#S256"]);
+    }
+
+    #[test]
+    fn test_verify_binding() {
+        let (client_certificate, certificate) = create_client_certificate(None);
+        let bound = jwt(json!({ "sub": "user-01", "cnf": { "x5t#S256": thumbprint(&certificate.to_der().unwrap()) } }));
+        assert!(client_certificate.verify_binding(&bound, true).is_ok());
+
+        let other = jwt(json!({ "sub": "user-01", "cnf": { "x5t#S256": "other" } }));
+        assert!(matches!(client_certificate.verify_binding(&other, false), Err(MtlsError::CertificateMismatch)));
+
+        let unbound = jwt(json!({ "sub": "user-01" }));
+        assert!(client_certificate.verify_binding(&unbound, false).is_ok());
+        assert!(matches!(client_certificate.verify_binding(&unbound, true), Err(MtlsError::ConfirmationMissing)));
+
+        assert!(client_certificate.verify_binding("opaque-access-token", true).is_ok());
+    }
+}
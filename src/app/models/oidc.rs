@@ -1 +1,10 @@
 pub mod discovery;
+pub mod id_token;
+pub mod introspection;
+pub mod jwks_store;
+pub mod logout_token;
+pub mod par;
+pub mod registration;
+pub mod request_object;
+pub mod signin;
+pub mod userinfo;
@@ -1,8 +1,14 @@
 use std::cmp::PartialEq;
+use std::str::FromStr;
 
+use jsonwebtoken::{Algorithm, DecodingKey};
+use reqwest::header::HeaderMap;
+use serde::{Deserialize as _, Deserializer};
 use serde_derive::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::app::models::base64url;
+
 #[derive(Debug, Error)]
 pub enum DiscoveryError {
     #[error("Decoding response body for {uri} failed: {message}")]
@@ -29,19 +35,66 @@ impl From<reqwest::Error> for DiscoveryError {
 
 type Result<T> = std::result::Result<T, DiscoveryError>;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct OpenIdConfiguration {
     pub issuer: String,
     pub authorization_endpoint: String,
     pub token_endpoint: String,
     pub userinfo_endpoint: Option<String>,
     pub jwks_uri: String,
+    pub revocation_endpoint: Option<String>,
+    pub introspection_endpoint: Option<String>,
+    pub device_authorization_endpoint: Option<String>,
+    pub pushed_authorization_request_endpoint: Option<String>,
+    /// From OpenID Connect RP-Initiated Logout 1.0 section 2.1.
+    pub end_session_endpoint: Option<String>,
+    #[serde(default)]
+    pub require_pushed_authorization_requests: bool,
+    #[serde(default)]
+    pub id_token_signing_alg_values_supported: Vec<String>,
+    #[serde(default)]
+    pub userinfo_signing_alg_values_supported: Vec<String>,
+    #[serde(default)]
+    pub code_challenge_methods_supported: Vec<String>,
+    #[serde(default)]
+    pub token_endpoint_auth_methods_supported: Vec<String>,
+    #[serde(default)]
+    pub tls_client_certificate_bound_access_tokens: bool,
+    pub mtls_endpoint_aliases: Option<MtlsEndpointAliases>,
 }
 
 impl OpenIdConfiguration {
     pub async fn find_jwks(&self) -> Result<Jwks> {
         JwksDiscovery::new(&self.jwks_uri).execute().await
     }
+
+    /// Replaces endpoints with their `mtls_endpoint_aliases`, for clients presenting a certificate.
+    pub fn with_mtls_endpoint_aliases(self) -> Self {
+        let aliases = match &self.mtls_endpoint_aliases {
+            Some(aliases) => aliases.clone(),
+            None => return self,
+        };
+        Self {
+            token_endpoint: aliases.token_endpoint.unwrap_or(self.token_endpoint),
+            userinfo_endpoint: aliases.userinfo_endpoint.or(self.userinfo_endpoint),
+            revocation_endpoint: aliases.revocation_endpoint.or(self.revocation_endpoint),
+            introspection_endpoint: aliases.introspection_endpoint.or(self.introspection_endpoint),
+            device_authorization_endpoint: aliases.device_authorization_endpoint.or(self.device_authorization_endpoint),
+            pushed_authorization_request_endpoint: aliases.pushed_authorization_request_endpoint.or(self.pushed_authorization_request_endpoint),
+            ..self
+        }
+    }
+}
+
+/// Endpoint aliases from RFC 8705 section 5, to be used when presenting a client certificate.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct MtlsEndpointAliases {
+    pub token_endpoint: Option<String>,
+    pub userinfo_endpoint: Option<String>,
+    pub revocation_endpoint: Option<String>,
+    pub introspection_endpoint: Option<String>,
+    pub device_authorization_endpoint: Option<String>,
+    pub pushed_authorization_request_endpoint: Option<String>,
 }
 
 pub struct OpenIdConfigurationDiscovery {
@@ -67,24 +120,139 @@ impl OpenIdConfigurationDiscovery {
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Jwks {
+    #[serde(deserialize_with = "deserialize_supported_keys")]
     keys: Vec<JsonWebKey>,
 }
 
+// RFC 7517 section 5: keys with an unknown "kty" (or curve) must be ignored rather than rejecting the whole set.
+// Symmetric "oct" keys are not supported either: a published secret would let anyone sign tokens.
+fn deserialize_supported_keys<'de, D>(deserializer: D) -> std::result::Result<Vec<JsonWebKey>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let keys = Vec::<serde_json::Value>::deserialize(deserializer)?;
+    let keys = keys.into_iter()
+        .filter_map(|key| serde_json::from_value(key).ok())
+        .collect();
+    Ok(keys)
+}
+
 impl Jwks {
+    pub fn new(keys: Vec<JsonWebKey>) -> Self {
+        Self { keys }
+    }
+
+    pub fn keys(&self) -> &[JsonWebKey] {
+        &self.keys
+    }
+
     pub fn find_by_kid(&self, kid: &str) -> Option<&JsonWebKey> {
-        self.keys.iter().find(|jwk| jwk.kid == kid)
+        self.keys.iter().find(|jwk| jwk.kid.as_deref() == Some(kid))
+    }
+
+    pub fn single_key(&self) -> Option<&JsonWebKey> {
+        match self.keys.as_slice() {
+            [jwk] => Some(jwk),
+            _ => None,
+        }
     }
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct JsonWebKey {
-    pub alg: String,
-    #[serde(rename = "use")]
-    pub key_use: String,
-    pub kid: String,
-    pub e: String,
-    pub kty: String,
-    pub n: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alg: Option<String>,
+    #[serde(rename = "use", skip_serializing_if = "Option::is_none")]
+    pub key_use: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_ops: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x5c: Option<Vec<String>>,
+    #[serde(flatten)]
+    pub key: KeyParameters,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "kty")]
+pub enum KeyParameters {
+    #[serde(rename = "RSA")]
+    Rsa { n: String, e: String },
+
+    #[serde(rename = "EC")]
+    Ec { crv: EllipticCurve, x: String, y: String },
+
+    #[serde(rename = "OKP")]
+    Okp { crv: EdwardsCurve, x: String },
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum EllipticCurve {
+    #[serde(rename = "P-256")]
+    P256,
+
+    #[serde(rename = "P-384")]
+    P384,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum EdwardsCurve {
+    Ed25519,
+}
+
+impl JsonWebKey {
+    /// Public P-256 key from its uncompressed SEC1 point, 0x04 || x || y, as ring hands it out.
+    pub fn from_p256_public_key(point: &[u8]) -> Self {
+        let (x, y) = point[1..].split_at((point.len() - 1) / 2);
+        Self {
+            kid: None,
+            alg: None,
+            key_use: None,
+            key_ops: None,
+            x5c: None,
+            key: KeyParameters::Ec {
+                crv: EllipticCurve::P256,
+                x: base64url::encode(x),
+                y: base64url::encode(y),
+            },
+        }
+    }
+
+    /// Whether this key may be used to verify signatures according to its "use" and "key_ops" parameters.
+    pub fn is_signing_key(&self) -> bool {
+        let usable = self.key_use.as_deref().is_none_or(|key_use| key_use == "sig");
+        let operable = self.key_ops.as_ref().is_none_or(|ops| ops.iter().any(|op| op == "verify"));
+        usable && operable
+    }
+
+    /// Algorithms this key can verify, narrowed to its "alg" parameter when present.
+    pub fn algorithms(&self) -> Vec<Algorithm> {
+        let algorithms = match self.key {
+            KeyParameters::Rsa { .. } => vec![
+                Algorithm::RS256, Algorithm::RS384, Algorithm::RS512,
+                Algorithm::PS256, Algorithm::PS384, Algorithm::PS512,
+            ],
+            KeyParameters::Ec { crv: EllipticCurve::P256, .. } => vec![Algorithm::ES256],
+            KeyParameters::Ec { crv: EllipticCurve::P384, .. } => vec![Algorithm::ES384],
+            KeyParameters::Okp { crv: EdwardsCurve::Ed25519, .. } => vec![Algorithm::EdDSA],
+        };
+
+        match self.alg.as_deref() {
+            Some(alg) => algorithms.into_iter()
+                .filter(|algorithm| Algorithm::from_str(alg).ok() == Some(*algorithm))
+                .collect(),
+            None => algorithms,
+        }
+    }
+
+    pub fn decoding_key(&self) -> jsonwebtoken::errors::Result<DecodingKey> {
+        match &self.key {
+            KeyParameters::Rsa { n, e } => DecodingKey::from_rsa_components(n, e),
+            KeyParameters::Ec { x, y, .. } => DecodingKey::from_ec_components(x, y),
+            KeyParameters::Okp { x, .. } => DecodingKey::from_ed_components(x),
+        }
+    }
 }
 
 pub struct JwksDiscovery {
@@ -99,10 +267,16 @@ impl JwksDiscovery {
     }
 
     pub async fn execute(&self) -> Result<Jwks> {
+        let (jwks, _) = self.fetch().await?;
+        Ok(jwks)
+    }
+
+    pub async fn fetch(&self) -> Result<(Jwks, HeaderMap)> {
         let client = reqwest::Client::new();
         let response = client.get(&self.uri).send().await?;
+        let headers = response.headers().clone();
         let result = response.json::<Jwks>().await?;
-        Ok(result)
+        Ok((result, headers))
     }
 }
 
@@ -143,6 +317,27 @@ mod tests {
         assert_eq!(jwks_endpoint, result.jwks_uri);
     }
 
+    #[test]
+    fn test_mtls_endpoint_aliases() {
+        let openid_config: OpenIdConfiguration = serde_json::from_value(json!({
+            "issuer": "https://example.com",
+            "authorization_endpoint": "https://example.com/authorize",
+            "token_endpoint": "https://example.com/token",
+            "userinfo_endpoint": "https://example.com/userinfo",
+            "jwks_uri": "https://example.com/jwks",
+            "tls_client_certificate_bound_access_tokens": true,
+            "mtls_endpoint_aliases": {
+                "token_endpoint": "https://mtls.example.com/token",
+            },
+        })).unwrap();
+        assert!(openid_config.tls_client_certificate_bound_access_tokens);
+
+        let openid_config = openid_config.with_mtls_endpoint_aliases();
+        assert_eq!("https://mtls.example.com/token", openid_config.token_endpoint);
+        assert_eq!(Some("https://example.com/userinfo"), openid_config.userinfo_endpoint.as_deref());
+        assert_eq!("https://example.com/authorize", openid_config.authorization_endpoint);
+    }
+
     #[actix_rt::test]
     async fn test_find_jwks() {
         let server = MockServer::start();
@@ -154,11 +349,12 @@ mod tests {
         let jwks_endpoint = format!("{}/jwks", issuer);
 
         let oidc_config = OpenIdConfiguration {
-            issuer: issuer,
-            authorization_endpoint: authorization_endpoint,
-            token_endpoint: token_endpoint,
+            issuer,
+            authorization_endpoint,
+            token_endpoint,
             userinfo_endpoint: Some(userinfo_endpoint),
             jwks_uri: jwks_endpoint,
+            ..Default::default()
         };
         let _mock = server.mock(|when, then| {
             when.method(GET).path("/jwks");
@@ -180,31 +376,55 @@ mod tests {
         assert_eq!(1, results.keys.len());
 
         let jwk = results.keys.first().unwrap();
-        assert_eq!("RS256", jwk.alg);
-        assert_eq!("RSA", jwk.kty);
-        assert_eq!("test-key-id", jwk.kid);
-        assert_eq!("sig", jwk.key_use);
-        assert_eq!("xxxxxx", jwk.n);
-        assert_eq!("yyyyyyy", jwk.e);
+        assert_eq!(Some("RS256"), jwk.alg.as_deref());
+        assert_eq!(Some("test-key-id"), jwk.kid.as_deref());
+        assert_eq!(Some("sig"), jwk.key_use.as_deref());
+        assert_eq!(KeyParameters::Rsa { n: "xxxxxx".to_owned(), e: "yyyyyyy".to_owned() }, jwk.key);
+    }
+
+    #[test]
+    fn test_jwks_key_types() {
+        let jwks: Jwks = serde_json::from_value(json!({
+            "keys": [
+                { "kty": "EC", "kid": "ec-01", "crv": "P-256", "x": "xxxxxx", "y": "yyyyyy" },
+                { "kty": "EC", "kid": "ec-02", "crv": "secp256k1", "x": "xxxxxx", "y": "yyyyyy" },
+                { "kty": "OKP", "kid": "okp-01", "crv": "Ed25519", "x": "xxxxxx", "key_ops": ["verify"] },
+                { "kty": "oct", "kid": "oct-01", "k": "kkkkkk", "alg": "HS256" },
+                { "kty": "unknown", "kid": "unknown-01" },
+            ]
+        })).unwrap();
+        assert_eq!(2, jwks.keys.len());
+
+        let ec = jwks.find_by_kid("ec-01").unwrap();
+        assert_eq!(KeyParameters::Ec { crv: EllipticCurve::P256, x: "xxxxxx".to_owned(), y: "yyyyyy".to_owned() }, ec.key);
+        assert_eq!(vec![Algorithm::ES256], ec.algorithms());
+
+        let okp = jwks.find_by_kid("okp-01").unwrap();
+        assert_eq!(vec![Algorithm::EdDSA], okp.algorithms());
+        assert!(okp.is_signing_key());
+
+        assert_eq!(None, jwks.find_by_kid("oct-01"));
+        assert_eq!(None, jwks.find_by_kid("ec-02"));
+        assert_eq!(None, jwks.find_by_kid("unknown-01"));
     }
 
     #[test]
     fn test_find_by_kid() {
         let jwk1 = JsonWebKey {
-            alg: "RS256".to_owned(),
-            key_use: "sig".to_owned(),
-            kid: "key-01".to_owned(),
-            e: "xxxxxx".to_owned(),
-            kty: "RSA".to_owned(),
-            n: "yyyyyy".to_owned(),
+            kid: Some("key-01".to_owned()),
+            alg: Some("RS256".to_owned()),
+            key_use: Some("sig".to_owned()),
+            key_ops: None,
+            x5c: None,
+            key: KeyParameters::Rsa { n: "yyyyyy".to_owned(), e: "xxxxxx".to_owned() },
         };
         let jwk2 = JsonWebKey {
-            alg: "RS256".to_owned(),
-            key_use: "sig".to_owned(),
-            kid: "key-02".to_owned(),
-            e: "aaaaa".to_owned(),
-            kty: "RSA".to_owned(),
-            n: "bbbbb".to_owned(),
+            kid: Some("key-02".to_owned()),
+            alg: Some("RS256".to_owned()),
+            key_use: Some("sig".to_owned()),
+            key_ops: None,
+            x5c: None,
+            key: KeyParameters::Rsa { n: "bbbbb".to_owned(), e: "aaaaa".to_owned() },
         };
         let jwks = Jwks {
             keys: vec![
@@ -0,0 +1,605 @@
+use std::str::FromStr;
+use std::time::{SystemTime, SystemTimeError};
+
+use jsonwebtoken::{Algorithm, DecodingKey, Header, TokenData, Validation};
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use thiserror::Error;
+
+use crate::app::config::IdTokenConfig;
+use crate::app::models::base64url;
+use crate::app::models::oidc::discovery::{JsonWebKey, OpenIdConfiguration};
+
+#[derive(Debug, Error)]
+pub enum IdTokenError {
+    #[error("ID token is signed with {0:?}, which the provider does not advertise")]
+    AlgorithmNotSupported(Algorithm),
+
+    #[error("JWK cannot verify {0:?} signatures")]
+    AlgorithmKeyMismatch(Algorithm),
+
+    #[error("JWK is not meant for signature verification")]
+    KeyNotForSigning,
+
+    #[error("{0:?} signatures are keyed with the client secret, which is not configured")]
+    ClientSecretMissing(Algorithm),
+
+    #[error("JWT error")]
+    JwtError(#[from] jsonwebtoken::errors::Error),
+
+    #[error("invalid issuer on ID token")]
+    InvalidIssuer,
+
+    #[error("ID token is not issued for this client")]
+    InvalidAudience,
+
+    #[error("ID token has multiple audiences but no azp claim")]
+    AuthorizedPartyMissing,
+
+    #[error("azp claim on ID token is not this client")]
+    InvalidAuthorizedParty,
+
+    #[error("ID token already expired")]
+    Expired,
+
+    #[error("ID token is not valid yet")]
+    NotYetValid,
+
+    #[error("ID token is issued in the future")]
+    IssuedInFuture,
+
+    #[error("ID token was issued too long ago")]
+    IssuedTooLongAgo,
+
+    #[error("nonce missing on ID token")]
+    NonceMissing,
+
+    #[error("nonce mismatch")]
+    NonceMismatch,
+
+    #[error("at_hash does not match access token")]
+    AccessTokenHashMismatch,
+
+    #[error("c_hash does not match authorization code")]
+    CodeHashMismatch,
+
+    #[error("auth_time missing on ID token")]
+    AuthTimeMissing,
+
+    #[error("authentication is older than max_age")]
+    AuthenticationTooOld,
+
+    #[error("acr claim does not satisfy requested acr_values")]
+    AcrNotSatisfied,
+
+    #[error("Failed to get duration for current time")]
+    InvalidCurrentTime(#[from] SystemTimeError),
+}
+
+type Result<T> = std::result::Result<T, IdTokenError>;
+
+/// Whether the algorithm is an HMAC, keyed with the client secret rather than a key from the provider's JWKS.
+pub fn is_symmetric(algorithm: Algorithm) -> bool {
+    matches!(algorithm, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512)
+}
+
+pub struct IdTokenVerifier<'a> {
+    supported_algorithms: &'a [String],
+    client_secret: Option<&'a str>,
+}
+
+impl<'a> IdTokenVerifier<'a> {
+    pub fn new(openid_config: &'a OpenIdConfiguration) -> Self {
+        Self {
+            supported_algorithms: &openid_config.id_token_signing_alg_values_supported,
+            client_secret: None,
+        }
+    }
+
+    /// Verifier for signed UserInfo responses, which are constrained by `userinfo_signing_alg_values_supported` instead.
+    pub fn for_userinfo(openid_config: &'a OpenIdConfiguration) -> Self {
+        Self {
+            supported_algorithms: &openid_config.userinfo_signing_alg_values_supported,
+            client_secret: None,
+        }
+    }
+
+    pub fn with_client_secret(self, client_secret: &'a str) -> Self {
+        Self { client_secret: Some(client_secret), ..self }
+    }
+
+    pub fn verify<T: DeserializeOwned>(&self, id_token: &str, header: &Header, jwk: &JsonWebKey) -> Result<TokenData<T>> {
+        let algorithm = self.select_algorithm(header, jwk)?;
+        decode(id_token, algorithm, &jwk.decoding_key()?)
+    }
+
+    /// Verifies an HS256, HS384 or HS512 signature with the octets of the client secret as the key,
+    /// as OpenID Connect Core section 10.1 requires; keys published in a JWKS are never used for these.
+    pub fn verify_with_client_secret<T: DeserializeOwned>(&self, id_token: &str, header: &Header) -> Result<TokenData<T>> {
+        let algorithm = header.alg;
+        if !is_symmetric(algorithm) {
+            return Err(IdTokenError::AlgorithmKeyMismatch(algorithm))
+        }
+        if !self.supported_algorithms().contains(&algorithm) {
+            return Err(IdTokenError::AlgorithmNotSupported(algorithm))
+        }
+        let client_secret = self.client_secret.ok_or(IdTokenError::ClientSecretMissing(algorithm))?;
+        decode(id_token, algorithm, &DecodingKey::from_secret(client_secret.as_bytes()))
+    }
+
+    /// Accepts the algorithm from the JWT header only if both the key and the provider allow it.
+    pub fn select_algorithm(&self, header: &Header, jwk: &JsonWebKey) -> Result<Algorithm> {
+        let algorithm = header.alg;
+        if !self.supported_algorithms().contains(&algorithm) {
+            return Err(IdTokenError::AlgorithmNotSupported(algorithm))
+        }
+        if !jwk.is_signing_key() {
+            return Err(IdTokenError::KeyNotForSigning)
+        }
+        if !jwk.algorithms().contains(&algorithm) {
+            return Err(IdTokenError::AlgorithmKeyMismatch(algorithm))
+        }
+        Ok(algorithm)
+    }
+
+    fn supported_algorithms(&self) -> Vec<Algorithm> {
+        let supported = self.supported_algorithms;
+        // RS256 must always be supported by OpenID providers (OpenID Connect Discovery 1.0, section 3)
+        if supported.is_empty() {
+            return vec![Algorithm::RS256]
+        }
+        supported.iter()
+            .filter_map(|alg| Algorithm::from_str(alg).ok())
+            .collect()
+    }
+}
+
+fn decode<T: DeserializeOwned>(jwt: &str, algorithm: Algorithm, decoding_key: &DecodingKey) -> Result<TokenData<T>> {
+    let mut validation = Validation::new(algorithm);
+    // Time based claims are checked by IdTokenValidator with the configured leeway
+    validation.validate_exp = false;
+    validation.required_spec_claims.clear();
+    let token_data = jsonwebtoken::decode::<T>(jwt, decoding_key, &validation)?;
+    Ok(token_data)
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Audience {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl Audience {
+    pub fn contains(&self, audience: &str) -> bool {
+        match self {
+            Self::Single(aud) => aud == audience,
+            Self::Multiple(auds) => auds.iter().any(|aud| aud == audience),
+        }
+    }
+
+    pub fn is_multiple(&self) -> bool {
+        matches!(self, Self::Multiple(auds) if auds.len() > 1)
+    }
+}
+
+/// Claims defined by OpenID Connect Core section 2, with provider specific claims flattened into `additional`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct IdTokenClaims<T> {
+    pub iss: String,
+    pub sub: String,
+    pub aud: Audience,
+    pub exp: u64,
+    pub iat: u64,
+    pub nbf: Option<u64>,
+    pub auth_time: Option<u64>,
+    pub nonce: Option<String>,
+    pub acr: Option<String>,
+    pub azp: Option<String>,
+    pub at_hash: Option<String>,
+    pub c_hash: Option<String>,
+    /// Session ID at the provider, from OpenID Connect Front-Channel and Back-Channel Logout.
+    pub sid: Option<String>,
+    #[serde(flatten)]
+    pub additional: T,
+}
+
+/// Validates ID token claims following OpenID Connect Core sections 3.1.3.7 and 3.1.3.8.
+pub struct IdTokenValidator<'a> {
+    config: &'a IdTokenConfig,
+    issuer: &'a str,
+    client_id: &'a str,
+    nonce: Option<&'a str>,
+    access_token: Option<&'a str>,
+    code: Option<&'a str>,
+}
+
+impl<'a> IdTokenValidator<'a> {
+    pub fn new(config: &'a IdTokenConfig, issuer: &'a str, client_id: &'a str) -> Self {
+        Self {
+            config,
+            issuer,
+            client_id,
+            nonce: None,
+            access_token: None,
+            code: None,
+        }
+    }
+
+    pub fn with_nonce(self, nonce: &'a str) -> Self {
+        Self { nonce: Some(nonce), ..self }
+    }
+
+    pub fn with_access_token(self, access_token: Option<&'a str>) -> Self {
+        Self { access_token, ..self }
+    }
+
+    pub fn with_code(self, code: &'a str) -> Self {
+        Self { code: Some(code), ..self }
+    }
+
+    pub fn validate<T>(&self, claims: &IdTokenClaims<T>, algorithm: Algorithm) -> Result<()> {
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_secs();
+        self.validate_at(claims, algorithm, now)
+    }
+
+    fn validate_at<T>(&self, claims: &IdTokenClaims<T>, algorithm: Algorithm, now: u64) -> Result<()> {
+        let leeway = self.config.leeway;
+
+        if claims.iss != self.issuer {
+            return Err(IdTokenError::InvalidIssuer)
+        }
+        if !claims.aud.contains(self.client_id) {
+            return Err(IdTokenError::InvalidAudience)
+        }
+        match claims.azp.as_deref() {
+            Some(azp) if azp != self.client_id => return Err(IdTokenError::InvalidAuthorizedParty),
+            None if claims.aud.is_multiple() => return Err(IdTokenError::AuthorizedPartyMissing),
+            _ => {}
+        }
+
+        if now > claims.exp.saturating_add(leeway) {
+            return Err(IdTokenError::Expired)
+        }
+        if matches!(claims.nbf, Some(nbf) if nbf > now.saturating_add(leeway)) {
+            return Err(IdTokenError::NotYetValid)
+        }
+        if claims.iat > now.saturating_add(leeway) {
+            return Err(IdTokenError::IssuedInFuture)
+        }
+        if matches!(self.config.max_iat_age, Some(max_iat_age) if now > claims.iat.saturating_add(max_iat_age).saturating_add(leeway)) {
+            return Err(IdTokenError::IssuedTooLongAgo)
+        }
+
+        if let Some(nonce) = self.nonce {
+            match claims.nonce.as_deref() {
+                Some(claimed) if claimed == nonce => {}
+                Some(_) => return Err(IdTokenError::NonceMismatch),
+                None => return Err(IdTokenError::NonceMissing),
+            }
+        }
+
+        if let (Some(access_token), Some(at_hash)) = (self.access_token, claims.at_hash.as_deref()) {
+            if token_hash(access_token, algorithm) != at_hash {
+                return Err(IdTokenError::AccessTokenHashMismatch)
+            }
+        }
+        if let (Some(code), Some(c_hash)) = (self.code, claims.c_hash.as_deref()) {
+            if token_hash(code, algorithm) != c_hash {
+                return Err(IdTokenError::CodeHashMismatch)
+            }
+        }
+
+        if let Some(max_age) = self.config.max_age {
+            let auth_time = claims.auth_time.ok_or(IdTokenError::AuthTimeMissing)?;
+            if now > auth_time.saturating_add(max_age).saturating_add(leeway) {
+                return Err(IdTokenError::AuthenticationTooOld)
+            }
+        }
+
+        if !self.config.acr_values.is_empty() {
+            let acr = claims.acr.as_deref().ok_or(IdTokenError::AcrNotSatisfied)?;
+            if !self.config.acr_values.iter().any(|value| value == acr) {
+                return Err(IdTokenError::AcrNotSatisfied)
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Left-most half of the hash of `value`, using the hash function of the JWS algorithm, as at_hash and c_hash are defined.
+pub fn token_hash(value: &str, algorithm: Algorithm) -> String {
+    let digest = match algorithm {
+        Algorithm::HS256 | Algorithm::RS256 | Algorithm::PS256 | Algorithm::ES256 => Sha256::digest(value.as_bytes()).to_vec(),
+        Algorithm::HS384 | Algorithm::RS384 | Algorithm::PS384 | Algorithm::ES384 => Sha384::digest(value.as_bytes()).to_vec(),
+        // Ed25519 is the only EdDSA curve supported, which uses SHA-512
+        Algorithm::HS512 | Algorithm::RS512 | Algorithm::PS512 | Algorithm::EdDSA => Sha512::digest(value.as_bytes()).to_vec(),
+    };
+    base64url::encode(&digest[..digest.len() / 2])
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::EncodingKey;
+    use ring::rand::SystemRandom;
+    use ring::signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, Ed25519KeyPair, KeyPair};
+    use serde_derive::{Deserialize, Serialize};
+    use crate::app::models::oidc::discovery::{EdwardsCurve, EllipticCurve, KeyParameters};
+    use super::*;
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Claims {
+        sub: String,
+        exp: u64,
+    }
+
+    fn create_openid_config(algorithms: &[&str]) -> OpenIdConfiguration {
+        OpenIdConfiguration {
+            id_token_signing_alg_values_supported: algorithms.iter().map(|alg| alg.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn create_jwk(key: KeyParameters) -> JsonWebKey {
+        JsonWebKey {
+            kid: Some("key-01".to_owned()),
+            alg: None,
+            key_use: Some("sig".to_owned()),
+            key_ops: None,
+            x5c: None,
+            key,
+        }
+    }
+
+    fn claims() -> Claims {
+        Claims {
+            sub: "user-01".to_owned(),
+            exp: 4_102_444_800,
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct NoAdditionalClaims {}
+
+    const NOW: u64 = 1_600_000_000;
+
+    fn id_token_claims() -> IdTokenClaims<NoAdditionalClaims> {
+        IdTokenClaims {
+            iss: "https://issuer.example.com".to_owned(),
+            sub: "user-01".to_owned(),
+            aud: Audience::Single("client-01".to_owned()),
+            exp: NOW + 300,
+            iat: NOW - 10,
+            nbf: None,
+            auth_time: Some(NOW - 60),
+            nonce: Some("nonce-01".to_owned()),
+            acr: None,
+            azp: None,
+            at_hash: None,
+            c_hash: None,
+            sid: None,
+            additional: NoAdditionalClaims {},
+        }
+    }
+
+    fn validate(config: &IdTokenConfig, claims: &IdTokenClaims<NoAdditionalClaims>) -> Result<()> {
+        IdTokenValidator::new(config, "https://issuer.example.com", "client-01")
+            .with_nonce("nonce-01")
+            .with_access_token(Some("access-token-01"))
+            .validate_at(claims, Algorithm::RS256, NOW)
+    }
+
+    #[test]
+    fn test_validate_valid_claims() {
+        let config = IdTokenConfig::default();
+        assert!(validate(&config, &id_token_claims()).is_ok());
+    }
+
+    #[test]
+    fn test_validate_issuer_and_audience() {
+        let config = IdTokenConfig::default();
+
+        let claims = IdTokenClaims { iss: "https://evil.example.com".to_owned(), ..id_token_claims() };
+        assert!(matches!(validate(&config, &claims), Err(IdTokenError::InvalidIssuer)));
+
+        let claims = IdTokenClaims { aud: Audience::Single("client-02".to_owned()), ..id_token_claims() };
+        assert!(matches!(validate(&config, &claims), Err(IdTokenError::InvalidAudience)));
+
+        let audiences = Audience::Multiple(vec!["client-01".to_owned(), "client-02".to_owned()]);
+        let claims = IdTokenClaims { aud: audiences, ..id_token_claims() };
+        assert!(matches!(validate(&config, &claims), Err(IdTokenError::AuthorizedPartyMissing)));
+
+        let audiences = Audience::Multiple(vec!["client-01".to_owned(), "client-02".to_owned()]);
+        let claims = IdTokenClaims { aud: audiences, azp: Some("client-02".to_owned()), ..id_token_claims() };
+        assert!(matches!(validate(&config, &claims), Err(IdTokenError::InvalidAuthorizedParty)));
+
+        let audiences = Audience::Multiple(vec!["client-01".to_owned(), "client-02".to_owned()]);
+        let claims = IdTokenClaims { aud: audiences, azp: Some("client-01".to_owned()), ..id_token_claims() };
+        assert!(validate(&config, &claims).is_ok());
+    }
+
+    #[test]
+    fn test_validate_time_claims() {
+        let config = IdTokenConfig { leeway: 30, max_iat_age: Some(600), ..Default::default() };
+
+        let claims = IdTokenClaims { exp: NOW - 20, ..id_token_claims() };
+        assert!(validate(&config, &claims).is_ok());
+        let claims = IdTokenClaims { exp: NOW - 40, ..id_token_claims() };
+        assert!(matches!(validate(&config, &claims), Err(IdTokenError::Expired)));
+
+        let claims = IdTokenClaims { nbf: Some(NOW + 40), ..id_token_claims() };
+        assert!(matches!(validate(&config, &claims), Err(IdTokenError::NotYetValid)));
+
+        let claims = IdTokenClaims { iat: NOW + 40, ..id_token_claims() };
+        assert!(matches!(validate(&config, &claims), Err(IdTokenError::IssuedInFuture)));
+
+        let claims = IdTokenClaims { iat: NOW - 700, ..id_token_claims() };
+        assert!(matches!(validate(&config, &claims), Err(IdTokenError::IssuedTooLongAgo)));
+    }
+
+    #[test]
+    fn test_validate_time_claims_overflow() {
+        let config = IdTokenConfig { leeway: 30, max_iat_age: Some(600), max_age: Some(120), ..Default::default() };
+
+        let claims = IdTokenClaims { exp: u64::MAX, ..id_token_claims() };
+        assert!(validate(&config, &claims).is_ok());
+        let claims = IdTokenClaims { iat: u64::MAX, ..id_token_claims() };
+        assert!(matches!(validate(&config, &claims), Err(IdTokenError::IssuedInFuture)));
+        let claims = IdTokenClaims { nbf: Some(u64::MAX), ..id_token_claims() };
+        assert!(matches!(validate(&config, &claims), Err(IdTokenError::NotYetValid)));
+        let claims = IdTokenClaims { auth_time: Some(u64::MAX), ..id_token_claims() };
+        assert!(validate(&config, &claims).is_ok());
+    }
+
+    #[test]
+    fn test_validate_nonce() {
+        let config = IdTokenConfig::default();
+
+        let claims = IdTokenClaims { nonce: None, ..id_token_claims() };
+        assert!(matches!(validate(&config, &claims), Err(IdTokenError::NonceMissing)));
+
+        let claims = IdTokenClaims { nonce: Some("nonce-02".to_owned()), ..id_token_claims() };
+        assert!(matches!(validate(&config, &claims), Err(IdTokenError::NonceMismatch)));
+    }
+
+    #[test]
+    fn test_validate_token_hashes() {
+        let config = IdTokenConfig::default();
+
+        let claims = IdTokenClaims { at_hash: Some(token_hash("access-token-01", Algorithm::RS256)), ..id_token_claims() };
+        assert!(validate(&config, &claims).is_ok());
+
+        let claims = IdTokenClaims { at_hash: Some(token_hash("access-token-02", Algorithm::RS256)), ..id_token_claims() };
+        assert!(matches!(validate(&config, &claims), Err(IdTokenError::AccessTokenHashMismatch)));
+
+        let claims = IdTokenClaims { c_hash: Some(token_hash("code-02", Algorithm::RS256)), ..id_token_claims() };
+        let result = IdTokenValidator::new(&config, "https://issuer.example.com", "client-01")
+            .with_code("code-01")
+            .validate_at(&claims, Algorithm::RS256, NOW);
+        assert!(matches!(result, Err(IdTokenError::CodeHashMismatch)));
+    }
+
+    #[test]
+    fn test_token_hash() {
+        let access_token = "jHkWEdUXMU1BwAsC4vtUsZwnNc6y3pu3ILlJ3dNdc5";
+        assert_eq!("qd4JLRdpIgzdLm5WhrKVZg", token_hash(access_token, Algorithm::RS256));
+        assert_eq!("JByx08JHQIT5d2xPPKQpK2QRmsfjpFuf", token_hash(access_token, Algorithm::ES384));
+    }
+
+    #[test]
+    fn test_validate_max_age_and_acr() {
+        let config = IdTokenConfig { max_age: Some(120), ..Default::default() };
+        assert!(validate(&config, &id_token_claims()).is_ok());
+
+        let claims = IdTokenClaims { auth_time: None, ..id_token_claims() };
+        assert!(matches!(validate(&config, &claims), Err(IdTokenError::AuthTimeMissing)));
+
+        let config = IdTokenConfig { leeway: 0, max_age: Some(30), ..Default::default() };
+        assert!(matches!(validate(&config, &id_token_claims()), Err(IdTokenError::AuthenticationTooOld)));
+
+        let config = IdTokenConfig { acr_values: vec!["urn:mace:incommon:iap:silver".to_owned()], ..Default::default() };
+        assert!(matches!(validate(&config, &id_token_claims()), Err(IdTokenError::AcrNotSatisfied)));
+
+        let claims = IdTokenClaims { acr: Some("urn:mace:incommon:iap:silver".to_owned()), ..id_token_claims() };
+        assert!(validate(&config, &claims).is_ok());
+    }
+
+    #[test]
+    fn test_audience_deserialization() {
+        let single: Audience = serde_json::from_str(r#""client-01""#).unwrap();
+        assert!(single.contains("client-01"));
+        assert!(!single.is_multiple());
+
+        let multiple: Audience = serde_json::from_str(r#"["client-01", "client-02"]"#).unwrap();
+        assert!(multiple.contains("client-02"));
+        assert!(multiple.is_multiple());
+    }
+
+    #[test]
+    fn test_verify_es256() {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref()).unwrap();
+        let point = key_pair.public_key().as_ref();
+        let jwk = create_jwk(KeyParameters::Ec { crv: EllipticCurve::P256, x: base64url::encode(&point[1..33]), y: base64url::encode(&point[33..]) });
+
+        let header = Header::new(Algorithm::ES256);
+        let token = jsonwebtoken::encode(&header, &claims(), &EncodingKey::from_ec_der(pkcs8.as_ref())).unwrap();
+
+        let openid_config = create_openid_config(&["RS256", "ES256"]);
+        let result = IdTokenVerifier::new(&openid_config).verify::<Claims>(&token, &header, &jwk).unwrap();
+        assert_eq!(claims(), result.claims);
+    }
+
+    #[test]
+    fn test_verify_eddsa() {
+        let rng = SystemRandom::new();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let jwk = create_jwk(KeyParameters::Okp { crv: EdwardsCurve::Ed25519, x: base64url::encode(key_pair.public_key().as_ref()) });
+
+        let header = Header::new(Algorithm::EdDSA);
+        let token = jsonwebtoken::encode(&header, &claims(), &EncodingKey::from_ed_der(pkcs8.as_ref())).unwrap();
+
+        let openid_config = create_openid_config(&["EdDSA"]);
+        let result = IdTokenVerifier::new(&openid_config).verify::<Claims>(&token, &header, &jwk).unwrap();
+        assert_eq!(claims(), result.claims);
+
+        let openid_config = create_openid_config(&["RS256"]);
+        let result = IdTokenVerifier::new(&openid_config).verify::<Claims>(&token, &header, &jwk);
+        assert!(matches!(result, Err(IdTokenError::AlgorithmNotSupported(Algorithm::EdDSA))));
+    }
+
+    #[test]
+    fn test_select_algorithm() {
+        let rsa = create_jwk(KeyParameters::Rsa { n: "nnnnnn".to_owned(), e: "AQAB".to_owned() });
+        let openid_config = create_openid_config(&[]);
+        let verifier = IdTokenVerifier::new(&openid_config);
+
+        assert_eq!(Algorithm::RS256, verifier.select_algorithm(&Header::new(Algorithm::RS256), &rsa).unwrap());
+        assert!(matches!(
+            verifier.select_algorithm(&Header::new(Algorithm::HS256), &rsa),
+            Err(IdTokenError::AlgorithmNotSupported(Algorithm::HS256))
+        ));
+
+        let openid_config = create_openid_config(&["RS256", "PS256", "HS256"]);
+        let verifier = IdTokenVerifier::new(&openid_config);
+        assert!(matches!(
+            verifier.select_algorithm(&Header::new(Algorithm::HS256), &rsa),
+            Err(IdTokenError::AlgorithmKeyMismatch(Algorithm::HS256))
+        ));
+
+        let rsa_rs256_only = JsonWebKey { alg: Some("RS256".to_owned()), ..rsa.clone() };
+        assert!(matches!(
+            verifier.select_algorithm(&Header::new(Algorithm::PS256), &rsa_rs256_only),
+            Err(IdTokenError::AlgorithmKeyMismatch(Algorithm::PS256))
+        ));
+
+        let encryption_key = JsonWebKey { key_use: Some("enc".to_owned()), ..rsa };
+        assert!(matches!(
+            verifier.select_algorithm(&Header::new(Algorithm::RS256), &encryption_key),
+            Err(IdTokenError::KeyNotForSigning)
+        ));
+    }
+
+    #[test]
+    fn test_verify_with_client_secret() {
+        let token = jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims(), &EncodingKey::from_secret(b"secret-01")).unwrap();
+        let header = jsonwebtoken::decode_header(&token).unwrap();
+
+        let openid_config = create_openid_config(&["HS256"]);
+        let result = IdTokenVerifier::new(&openid_config).with_client_secret("secret-01").verify_with_client_secret::<Claims>(&token, &header).unwrap();
+        assert_eq!(claims(), result.claims);
+        let result = IdTokenVerifier::new(&openid_config).with_client_secret("secret-02").verify_with_client_secret::<Claims>(&token, &header);
+        assert!(matches!(result, Err(IdTokenError::JwtError(_))));
+        let result = IdTokenVerifier::new(&openid_config).verify_with_client_secret::<Claims>(&token, &header);
+        assert!(matches!(result, Err(IdTokenError::ClientSecretMissing(Algorithm::HS256))));
+
+        // Only when the provider advertises the algorithm
+        let openid_config = create_openid_config(&["RS256"]);
+        let result = IdTokenVerifier::new(&openid_config).with_client_secret("secret-01").verify_with_client_secret::<Claims>(&token, &header);
+        assert!(matches!(result, Err(IdTokenError::AlgorithmNotSupported(Algorithm::HS256))));
+    }
+}
@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use reqwest::header::{CACHE_CONTROL, DATE, EXPIRES, HeaderMap};
+
+use crate::app::models::oidc::discovery::{DiscoveryError, JsonWebKey, Jwks, JwksDiscovery};
+
+type Result<T> = std::result::Result<T, DiscoveryError>;
+
+/// Shared cache of JSON Web Key Sets keyed by `jwks_uri`.
+///
+/// Key sets are kept as long as the provider's `Cache-Control` or `Expires` headers allow.
+/// A lookup for an unknown `kid` forces a refetch, but no more often than `min_refresh_interval`
+/// per URI, and keys dropped by the provider are still accepted for `rotation_grace` after they disappear.
+pub struct JwksStore {
+    entries: Mutex<HashMap<String, Arc<tokio::sync::Mutex<JwksEntry>>>>,
+    default_ttl: Duration,
+    max_ttl: Duration,
+    min_refresh_interval: Duration,
+    rotation_grace: Duration,
+}
+
+impl Default for JwksStore {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(60 * 60), Duration::from_secs(24 * 60 * 60), Duration::from_secs(60), Duration::from_secs(10 * 60))
+    }
+}
+
+#[derive(Default)]
+struct JwksEntry {
+    current: Option<Jwks>,
+    /// Keys the provider dropped, each accepted until its own grace deadline.
+    retired: Vec<(JsonWebKey, Instant)>,
+    expires_at: Option<Instant>,
+    fetched_at: Option<Instant>,
+}
+
+impl JwksEntry {
+    fn is_fresh(&self, now: Instant) -> bool {
+        matches!(self.expires_at, Some(expires_at) if now < expires_at)
+    }
+
+    fn may_refresh(&self, now: Instant, min_refresh_interval: Duration) -> bool {
+        match self.fetched_at {
+            Some(fetched_at) => now.duration_since(fetched_at) >= min_refresh_interval,
+            None => true,
+        }
+    }
+
+    fn find(&self, kid: Option<&str>, now: Instant) -> Option<JsonWebKey> {
+        let current = self.current.as_ref()?;
+        match kid {
+            Some(kid) => current.find_by_kid(kid)
+                .or_else(|| {
+                    self.retired.iter()
+                        .find(|(jwk, retained_until)| now < *retained_until && jwk.kid.as_deref() == Some(kid))
+                        .map(|(jwk, _)| jwk)
+                })
+                .cloned(),
+            None => current.single_key().cloned(),
+        }
+    }
+
+    fn replace(&mut self, jwks: Jwks, ttl: Duration, rotation_grace: Duration, now: Instant) {
+        let is_published = |jwk: &JsonWebKey| jwks.keys().contains(jwk);
+        // Retired keys keep the deadline from when they disappeared, however often the set is refetched
+        self.retired.retain(|(jwk, retained_until)| now < *retained_until && !is_published(jwk));
+        if let Some(current) = self.current.take() {
+            for jwk in current.keys() {
+                if !is_published(jwk) {
+                    self.retired.push((jwk.clone(), now + rotation_grace));
+                }
+            }
+        }
+        self.current = Some(jwks);
+        self.expires_at = Some(now + ttl);
+        self.fetched_at = Some(now);
+    }
+}
+
+impl JwksStore {
+    pub fn new(default_ttl: Duration, max_ttl: Duration, min_refresh_interval: Duration, rotation_grace: Duration) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            default_ttl,
+            max_ttl,
+            min_refresh_interval,
+            rotation_grace,
+        }
+    }
+
+    pub async fn find(&self, jwks_uri: &str, kid: Option<&str>) -> Result<Option<JsonWebKey>> {
+        let entry = self.entry(jwks_uri);
+        let mut entry = entry.lock().await;
+
+        if !entry.is_fresh(Instant::now()) {
+            if let Err(error) = self.refresh(jwks_uri, &mut entry).await {
+                // Keep serving the stale key set rather than failing every sign-in while the provider is unreachable
+                if entry.current.is_none() {
+                    return Err(error)
+                }
+                log::warn!("Failed to refresh JWKS from {}: {}", jwks_uri, error);
+            }
+        }
+
+        let now = Instant::now();
+        if let Some(jwk) = entry.find(kid, now) {
+            return Ok(Some(jwk))
+        }
+        if kid.is_none() || !entry.may_refresh(now, self.min_refresh_interval) {
+            return Ok(None)
+        }
+
+        self.refresh(jwks_uri, &mut entry).await?;
+        Ok(entry.find(kid, Instant::now()))
+    }
+
+    fn entry(&self, jwks_uri: &str) -> Arc<tokio::sync::Mutex<JwksEntry>> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.entry(jwks_uri.to_owned())
+            .or_default()
+            .clone()
+    }
+
+    async fn refresh(&self, jwks_uri: &str, entry: &mut JwksEntry) -> Result<()> {
+        let (jwks, headers) = JwksDiscovery::new(jwks_uri).fetch().await?;
+        let ttl = cache_lifetime(&headers, SystemTime::now())
+            .unwrap_or(self.default_ttl)
+            .min(self.max_ttl);
+        entry.replace(jwks, ttl, self.rotation_grace, Instant::now());
+        Ok(())
+    }
+}
+
+fn cache_lifetime(headers: &HeaderMap, now: SystemTime) -> Option<Duration> {
+    if let Some(cache_control) = headers.get(CACHE_CONTROL).and_then(|value| value.to_str().ok()) {
+        for directive in cache_control.split(',').map(|d| d.trim().to_ascii_lowercase()) {
+            if directive == "no-store" || directive == "no-cache" {
+                return Some(Duration::ZERO)
+            }
+            if let Some(seconds) = directive.strip_prefix("max-age=") {
+                if let Ok(seconds) = seconds.trim_matches('"').parse::<u64>() {
+                    return Some(Duration::from_secs(seconds))
+                }
+            }
+        }
+    }
+
+    let expires = headers.get(EXPIRES)?.to_str().ok()?;
+    // An invalid Expires value such as "0" means the response is already expired
+    let expires = match httpdate::parse_http_date(expires) {
+        Ok(expires) => expires,
+        Err(_) => return Some(Duration::ZERO),
+    };
+    let date = headers.get(DATE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| httpdate::parse_http_date(value).ok())
+        .unwrap_or(now);
+    Some(expires.duration_since(date).unwrap_or(Duration::ZERO))
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::MockServer;
+    use httpmock::Method::GET;
+    use reqwest::header::HeaderValue;
+    use serde_json::{json, Value};
+    use super::*;
+
+    fn jwks_body(kids: &[&str]) -> Value {
+        let keys: Vec<Value> = kids.iter()
+            .map(|kid| json!({
+                "alg": "RS256",
+                "kty": "RSA",
+                "kid": kid,
+                "use": "sig",
+                "n": "xxxxxx",
+                "e": "AQAB",
+            }))
+            .collect();
+        json!({ "keys": keys })
+    }
+
+    fn create_store() -> JwksStore {
+        JwksStore::new(Duration::from_secs(60), Duration::from_secs(600), Duration::from_secs(60), Duration::from_secs(600))
+    }
+
+    #[actix_rt::test]
+    async fn test_find_uses_cached_jwks() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/jwks");
+            then.status(200).json_body(jwks_body(&["key-01"]));
+        });
+
+        let store = create_store();
+        let uri = server.url("/jwks");
+        assert!(store.find(&uri, Some("key-01")).await.unwrap().is_some());
+        assert!(store.find(&uri, Some("key-01")).await.unwrap().is_some());
+        assert_eq!(1, mock.hits());
+    }
+
+    #[actix_rt::test]
+    async fn test_find_honors_cache_control() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/jwks");
+            then.status(200)
+                .header("Cache-Control", "public, max-age=0")
+                .json_body(jwks_body(&["key-01"]));
+        });
+
+        let store = create_store();
+        let uri = server.url("/jwks");
+        store.find(&uri, Some("key-01")).await.unwrap();
+        store.find(&uri, Some("key-01")).await.unwrap();
+        assert_eq!(2, mock.hits());
+    }
+
+    #[actix_rt::test]
+    async fn test_unknown_kid_refetch_is_rate_limited() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/jwks");
+            then.status(200).json_body(jwks_body(&["key-01"]));
+        });
+
+        let store = create_store();
+        let uri = server.url("/jwks");
+        store.find(&uri, Some("key-01")).await.unwrap();
+        assert_eq!(None, store.find(&uri, Some("unknown")).await.unwrap());
+        assert_eq!(None, store.find(&uri, Some("unknown")).await.unwrap());
+        assert_eq!(1, mock.hits());
+    }
+
+    #[actix_rt::test]
+    async fn test_previous_keys_survive_rotation() {
+        let server = MockServer::start();
+        let mut old_keys = server.mock(|when, then| {
+            when.method(GET).path("/jwks");
+            then.status(200).json_body(jwks_body(&["key-01"]));
+        });
+
+        let store = JwksStore::new(Duration::from_secs(60), Duration::from_secs(600), Duration::ZERO, Duration::from_secs(600));
+        let uri = server.url("/jwks");
+        store.find(&uri, Some("key-01")).await.unwrap();
+
+        old_keys.delete();
+        let new_keys = server.mock(|when, then| {
+            when.method(GET).path("/jwks");
+            then.status(200).json_body(jwks_body(&["key-02"]));
+        });
+
+        assert!(store.find(&uri, Some("key-02")).await.unwrap().is_some());
+        assert!(store.find(&uri, Some("key-01")).await.unwrap().is_some());
+        assert_eq!(1, new_keys.hits());
+    }
+
+    #[actix_rt::test]
+    async fn test_retired_keys_survive_repeated_refetch() {
+        let server = MockServer::start();
+        let store = JwksStore::new(Duration::from_secs(60), Duration::from_secs(600), Duration::ZERO, Duration::from_secs(600));
+        let uri = server.url("/jwks");
+
+        // Each rotation refetches within the grace period of key-01
+        for (kid, kids) in [("key-01", vec!["key-01"]), ("key-02", vec!["key-02"]), ("key-03", vec!["key-03"])] {
+            let mut mock = server.mock(|when, then| {
+                when.method(GET).path("/jwks");
+                then.status(200).json_body(jwks_body(&kids));
+            });
+            assert!(store.find(&uri, Some(kid)).await.unwrap().is_some());
+            mock.delete();
+        }
+
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/jwks");
+            then.status(200).json_body(jwks_body(&["key-03"]));
+        });
+        assert!(store.find(&uri, Some("key-01")).await.unwrap().is_some());
+        assert!(store.find(&uri, Some("key-02")).await.unwrap().is_some());
+        assert_eq!(0, mock.hits());
+    }
+
+    #[test]
+    fn test_retired_keys_expire() {
+        let jwks = |kids: &[&str]| serde_json::from_value::<Jwks>(jwks_body(kids)).unwrap();
+        let now = Instant::now();
+        let grace = Duration::from_secs(600);
+        let mut entry = JwksEntry::default();
+        entry.replace(jwks(&["key-01"]), Duration::from_secs(60), grace, now);
+        entry.replace(jwks(&["key-02"]), Duration::from_secs(60), grace, now);
+        entry.replace(jwks(&["key-03"]), Duration::from_secs(60), grace, now + Duration::from_secs(300));
+
+        let later = now + Duration::from_secs(700);
+        assert_eq!(None, entry.find(Some("key-01"), later));
+        assert!(entry.find(Some("key-02"), later).is_some());
+    }
+
+    #[test]
+    fn test_cache_lifetime() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(None, cache_lifetime(&headers, now));
+
+        headers.insert(EXPIRES, HeaderValue::from_static("Sun, 13 Sep 2020 13:26:40 GMT"));
+        assert_eq!(Some(Duration::from_secs(3600)), cache_lifetime(&headers, now));
+
+        headers.insert(DATE, HeaderValue::from_static("Sun, 13 Sep 2020 13:16:40 GMT"));
+        assert_eq!(Some(Duration::from_secs(600)), cache_lifetime(&headers, now));
+
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("public, max-age=120"));
+        assert_eq!(Some(Duration::from_secs(120)), cache_lifetime(&headers, now));
+
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        assert_eq!(Some(Duration::ZERO), cache_lifetime(&headers, now));
+    }
+}
@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, SystemTimeError};
+
+use serde_derive::Deserialize;
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+use crate::app::config::IdTokenConfig;
+use crate::app::models::base64url;
+use crate::app::models::oidc::discovery::{DiscoveryError, OpenIdConfiguration};
+use crate::app::models::oidc::id_token::{Audience, IdTokenError, IdTokenVerifier, is_symmetric};
+use crate::app::models::oidc::jwks_store::JwksStore;
+
+/// Event identifying a logout token, from OpenID Connect Back-Channel Logout 1.0 section 2.4.
+pub const BACKCHANNEL_LOGOUT_EVENT: &str = "http://schemas.openid.net/event/backchannel-logout";
+
+/// How old a logout token may be when `max_iat_age` is not configured.
+const DEFAULT_MAX_AGE: u64 = 300;
+
+#[derive(Debug, Error)]
+pub enum LogoutTokenError {
+    #[error("logout token is not a JWT")]
+    Malformed,
+
+    #[error("no JWK found for logout token")]
+    JwkNotFound,
+
+    #[error("JWKS lookup failed: {0}")]
+    JwksFailed(#[from] DiscoveryError),
+
+    #[error("JWT error")]
+    JwtError(#[from] jsonwebtoken::errors::Error),
+
+    #[error("logout token verification failed: {0}")]
+    VerificationFailed(#[from] IdTokenError),
+
+    #[error("invalid issuer on logout token")]
+    InvalidIssuer,
+
+    #[error("logout token is not issued for this client")]
+    InvalidAudience,
+
+    #[error("logout token already expired")]
+    Expired,
+
+    #[error("logout token is issued in the future")]
+    IssuedInFuture,
+
+    #[error("logout token was issued too long ago")]
+    IssuedTooLongAgo,
+
+    #[error("logout token has no back-channel logout event")]
+    EventMissing,
+
+    #[error("logout token must not contain a nonce")]
+    NonceNotAllowed,
+
+    #[error("logout token has neither sid nor sub")]
+    SubjectMissing,
+
+    #[error("logout token {0} was already used")]
+    Replayed(String),
+
+    #[error("Failed to get duration for current time")]
+    InvalidCurrentTime(#[from] SystemTimeError),
+}
+
+type Result<T> = std::result::Result<T, LogoutTokenError>;
+
+/// Logout token claims from OpenID Connect Back-Channel Logout 1.0 section 2.4.
+#[derive(Debug, Deserialize)]
+pub struct LogoutTokenClaims {
+    pub iss: String,
+    pub aud: Audience,
+    pub iat: u64,
+    pub exp: Option<u64>,
+    pub jti: String,
+    #[serde(default)]
+    pub events: Map<String, Value>,
+    pub sub: Option<String>,
+    pub sid: Option<String>,
+    pub nonce: Option<Value>,
+}
+
+/// Issuer of a logout token before its signature is checked, to find the provider whose keys verify it.
+pub fn unverified_issuer(logout_token: &str) -> Result<String> {
+    #[derive(Deserialize)]
+    struct Claims {
+        iss: String,
+    }
+
+    let payload = match logout_token.split('.').collect::<Vec<_>>().as_slice() {
+        [_, payload, _] => base64url::decode(payload).map_err(|_| LogoutTokenError::Malformed)?,
+        _ => return Err(LogoutTokenError::Malformed),
+    };
+    let claims = serde_json::from_slice::<Claims>(&payload).map_err(|_| LogoutTokenError::Malformed)?;
+    Ok(claims.iss)
+}
+
+/// Verifies a logout token following OpenID Connect Back-Channel Logout 1.0 section 2.6.
+///
+/// Logout tokens are signed like ID tokens, so the provider's cached JWKS and ID token algorithms apply.
+pub struct LogoutTokenVerification<'a> {
+    jwks_store: &'a JwksStore,
+    openid_config: &'a OpenIdConfiguration,
+    validator: LogoutTokenValidator<'a>,
+    client_secret: Option<&'a str>,
+}
+
+impl<'a> LogoutTokenVerification<'a> {
+    pub fn new(jwks_store: &'a JwksStore, openid_config: &'a OpenIdConfiguration, validator: LogoutTokenValidator<'a>) -> Self {
+        Self {
+            jwks_store,
+            openid_config,
+            validator,
+            client_secret: None,
+        }
+    }
+
+    /// Secret that tokens signed with HS256, HS384 or HS512 are keyed with.
+    pub fn with_client_secret(self, client_secret: &'a str) -> Self {
+        Self { client_secret: Some(client_secret), ..self }
+    }
+
+    pub async fn execute(&self, logout_token: &str) -> Result<LogoutTokenClaims> {
+        let header = jsonwebtoken::decode_header(logout_token)?;
+        let verifier = IdTokenVerifier::new(self.openid_config);
+        let verifier = match self.client_secret {
+            Some(client_secret) => verifier.with_client_secret(client_secret),
+            None => verifier,
+        };
+        let claims = if is_symmetric(header.alg) {
+            verifier.verify_with_client_secret::<LogoutTokenClaims>(logout_token, &header)?
+        } else {
+            let jwk = self.jwks_store.find(&self.openid_config.jwks_uri, header.kid.as_deref()).await?
+                .ok_or(LogoutTokenError::JwkNotFound)?;
+            verifier.verify::<LogoutTokenClaims>(logout_token, &header, &jwk)?
+        }.claims;
+        self.validator.validate(&claims)?;
+        Ok(claims)
+    }
+}
+
+pub struct LogoutTokenValidator<'a> {
+    config: &'a IdTokenConfig,
+    issuer: &'a str,
+    client_id: &'a str,
+    replay_cache: &'a LogoutTokenReplayCache,
+}
+
+impl<'a> LogoutTokenValidator<'a> {
+    pub fn new(config: &'a IdTokenConfig, issuer: &'a str, client_id: &'a str, replay_cache: &'a LogoutTokenReplayCache) -> Self {
+        Self {
+            config,
+            issuer,
+            client_id,
+            replay_cache,
+        }
+    }
+
+    pub fn validate(&self, claims: &LogoutTokenClaims) -> Result<()> {
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_secs();
+        self.validate_at(claims, now)
+    }
+
+    fn validate_at(&self, claims: &LogoutTokenClaims, now: u64) -> Result<()> {
+        let leeway = self.config.leeway;
+        let max_age = self.config.max_iat_age.unwrap_or(DEFAULT_MAX_AGE);
+
+        if claims.iss != self.issuer {
+            return Err(LogoutTokenError::InvalidIssuer)
+        }
+        if !claims.aud.contains(self.client_id) {
+            return Err(LogoutTokenError::InvalidAudience)
+        }
+        if claims.iat > now.saturating_add(leeway) {
+            return Err(LogoutTokenError::IssuedInFuture)
+        }
+        if now > claims.iat.saturating_add(max_age).saturating_add(leeway) {
+            return Err(LogoutTokenError::IssuedTooLongAgo)
+        }
+        if matches!(claims.exp, Some(exp) if now > exp.saturating_add(leeway)) {
+            return Err(LogoutTokenError::Expired)
+        }
+        if !matches!(claims.events.get(BACKCHANNEL_LOGOUT_EVENT), Some(Value::Object(_))) {
+            return Err(LogoutTokenError::EventMissing)
+        }
+        // A nonce would let an ID token pass as a logout token
+        if claims.nonce.is_some() {
+            return Err(LogoutTokenError::NonceNotAllowed)
+        }
+        if claims.sid.is_none() && claims.sub.is_none() {
+            return Err(LogoutTokenError::SubjectMissing)
+        }
+
+        // Tokens older than this fail the iat check, so their jti need not be remembered longer
+        let expires_at = now.saturating_add(max_age).saturating_add(leeway.saturating_mul(2));
+        self.replay_cache.check_at(&claims.iss, &claims.jti, expires_at, now)
+    }
+}
+
+/// `jti` values of accepted logout tokens, per issuer, so a captured token cannot be replayed.
+#[derive(Default)]
+pub struct LogoutTokenReplayCache {
+    seen: Mutex<HashMap<(String, String), u64>>,
+}
+
+impl LogoutTokenReplayCache {
+    /// Records the `jti` until `expires_at`, failing if it was already seen.
+    fn check_at(&self, issuer: &str, jti: &str, expires_at: u64, now: u64) -> Result<()> {
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, expires_at| *expires_at > now);
+        let key = (issuer.to_owned(), jti.to_owned());
+        if seen.contains_key(&key) {
+            return Err(LogoutTokenError::Replayed(jti.to_owned()))
+        }
+        seen.insert(key, expires_at);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    const NOW: u64 = 1_600_000_000;
+
+    fn logout_token_claims() -> LogoutTokenClaims {
+        LogoutTokenClaims {
+            iss: "https://issuer.example.com".to_owned(),
+            aud: Audience::Single("client-01".to_owned()),
+            iat: NOW - 10,
+            exp: Some(NOW + 110),
+            jti: "jti-01".to_owned(),
+            events: json!({ BACKCHANNEL_LOGOUT_EVENT: {} }).as_object().cloned().unwrap(),
+            sub: Some("user-01".to_owned()),
+            sid: Some("sid-01".to_owned()),
+            nonce: None,
+        }
+    }
+
+    fn validate(replay_cache: &LogoutTokenReplayCache, claims: &LogoutTokenClaims) -> Result<()> {
+        let config = IdTokenConfig::default();
+        LogoutTokenValidator::new(&config, "https://issuer.example.com", "client-01", replay_cache)
+            .validate_at(claims, NOW)
+    }
+
+    #[test]
+    fn test_validate_claims() {
+        let cache = LogoutTokenReplayCache::default();
+        assert!(validate(&cache, &logout_token_claims()).is_ok());
+
+        let claims = LogoutTokenClaims { jti: "jti-02".to_owned(), sid: None, ..logout_token_claims() };
+        assert!(validate(&cache, &claims).is_ok());
+
+        let claims = LogoutTokenClaims { iss: "https://evil.example.com".to_owned(), ..logout_token_claims() };
+        assert!(matches!(validate(&cache, &claims), Err(LogoutTokenError::InvalidIssuer)));
+        let claims = LogoutTokenClaims { aud: Audience::Single("client-02".to_owned()), ..logout_token_claims() };
+        assert!(matches!(validate(&cache, &claims), Err(LogoutTokenError::InvalidAudience)));
+        let claims = LogoutTokenClaims { iat: NOW + 120, ..logout_token_claims() };
+        assert!(matches!(validate(&cache, &claims), Err(LogoutTokenError::IssuedInFuture)));
+        let claims = LogoutTokenClaims { iat: NOW - 600, ..logout_token_claims() };
+        assert!(matches!(validate(&cache, &claims), Err(LogoutTokenError::IssuedTooLongAgo)));
+        let claims = LogoutTokenClaims { exp: Some(NOW - 120), ..logout_token_claims() };
+        assert!(matches!(validate(&cache, &claims), Err(LogoutTokenError::Expired)));
+        let claims = LogoutTokenClaims { events: Map::new(), ..logout_token_claims() };
+        assert!(matches!(validate(&cache, &claims), Err(LogoutTokenError::EventMissing)));
+        let claims = LogoutTokenClaims { nonce: Some(json!("nonce-01")), ..logout_token_claims() };
+        assert!(matches!(validate(&cache, &claims), Err(LogoutTokenError::NonceNotAllowed)));
+        let claims = LogoutTokenClaims { sid: None, sub: None, ..logout_token_claims() };
+        assert!(matches!(validate(&cache, &claims), Err(LogoutTokenError::SubjectMissing)));
+    }
+
+    #[test]
+    fn test_validate_time_claims_overflow() {
+        let cache = LogoutTokenReplayCache::default();
+        let claims = LogoutTokenClaims { exp: Some(u64::MAX), ..logout_token_claims() };
+        assert!(validate(&cache, &claims).is_ok());
+        let claims = LogoutTokenClaims { jti: "jti-02".to_owned(), iat: u64::MAX, ..logout_token_claims() };
+        assert!(matches!(validate(&cache, &claims), Err(LogoutTokenError::IssuedInFuture)));
+
+        let config = IdTokenConfig { leeway: u64::MAX, max_iat_age: Some(u64::MAX), ..Default::default() };
+        let validator = LogoutTokenValidator::new(&config, "https://issuer.example.com", "client-01", &cache);
+        let claims = LogoutTokenClaims { jti: "jti-03".to_owned(), ..logout_token_claims() };
+        assert!(validator.validate_at(&claims, NOW).is_ok());
+        assert!(matches!(validator.validate_at(&claims, NOW), Err(LogoutTokenError::Replayed(_))));
+    }
+
+    #[test]
+    fn test_replayed_jti_is_rejected() {
+        let cache = LogoutTokenReplayCache::default();
+        assert!(validate(&cache, &logout_token_claims()).is_ok());
+        assert!(matches!(validate(&cache, &logout_token_claims()), Err(LogoutTokenError::Replayed(jti)) if jti == "jti-01"));
+
+        // The same jti from another issuer is a different token
+        let other = LogoutTokenReplayCache::default();
+        other.check_at("https://other.example.com", "jti-01", NOW + 60, NOW).unwrap();
+        other.check_at("https://issuer.example.com", "jti-01", NOW + 60, NOW).unwrap();
+        // Expired entries are forgotten
+        other.check_at("https://issuer.example.com", "jti-01", NOW + 120, NOW + 61).unwrap();
+    }
+
+    #[test]
+    fn test_unverified_issuer() {
+        let payload = base64url::encode(json!({ "iss": "https://issuer.example.com" }).to_string());
+        assert_eq!("https://issuer.example.com", unverified_issuer(&format!("e30.{}.c2ln", payload)).unwrap());
+        assert!(matches!(unverified_issuer("not-a-jwt"), Err(LogoutTokenError::Malformed)));
+    }
+}
@@ -0,0 +1,189 @@
+use serde_derive::Deserialize;
+use thiserror::Error;
+use url::Url;
+
+use crate::app::models::client_auth::{ClientAuthError, ClientAuthentication};
+use crate::app::models::oidc::discovery::OpenIdConfiguration;
+
+#[derive(Debug, Error)]
+pub enum ParError {
+    #[error("provider requires pushed authorization requests, but PAR is disabled in config")]
+    ParRequired,
+
+    #[error("PAR is enabled in config, but the provider has no pushed_authorization_request_endpoint")]
+    EndpointMissing,
+
+    #[error("pushed authorization request was rejected: {0}")]
+    Rejected(String),
+
+    #[error("client authentication failed: {0}")]
+    ClientAuthFailed(#[from] ClientAuthError),
+
+    #[error("pushed authorization request failed")]
+    RequestFailed(#[from] reqwest::Error),
+
+    #[error("invalid url")]
+    InvalidUrl(#[from] url::ParseError),
+}
+
+type Result<T> = std::result::Result<T, ParError>;
+
+/// Whether authorization parameters are pushed to the provider (RFC 9126) instead of sent through the browser.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ParMode {
+    /// Push when the provider advertises a `pushed_authorization_request_endpoint`.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ParResponse {
+    pub request_uri: String,
+    pub expires_in: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+/// Builds the URL the browser is redirected to, pushing the parameters first when PAR is in use.
+pub struct AuthorizationUrl<'a> {
+    openid_config: &'a OpenIdConfiguration,
+    mode: ParMode,
+    authentication: &'a ClientAuthentication<'a>,
+}
+
+impl<'a> AuthorizationUrl<'a> {
+    pub fn new(openid_config: &'a OpenIdConfiguration, mode: ParMode, authentication: &'a ClientAuthentication<'a>) -> Self {
+        Self {
+            openid_config,
+            mode,
+            authentication,
+        }
+    }
+
+    pub async fn build(&self, parameters: &[(&str, &str)]) -> Result<Url> {
+        let openid_config = self.openid_config;
+        let endpoint = match (self.mode, &openid_config.pushed_authorization_request_endpoint) {
+            (ParMode::Never, _) if openid_config.require_pushed_authorization_requests => return Err(ParError::ParRequired),
+            (ParMode::Never, _) | (ParMode::Auto, None) => {
+                return Ok(Url::parse_with_params(&openid_config.authorization_endpoint, parameters)?)
+            },
+            (ParMode::Always, None) => return Err(ParError::EndpointMissing),
+            (_, Some(endpoint)) => endpoint,
+        };
+
+        let response = PushedAuthorizationRequest::new(endpoint, &openid_config.issuer, self.authentication)
+            .execute(parameters)
+            .await?;
+        let url = Url::parse_with_params(&openid_config.authorization_endpoint, &[
+            ("client_id", self.authentication.client_id()),
+            ("request_uri", &response.request_uri),
+        ])?;
+        Ok(url)
+    }
+}
+
+/// Pushed authorization request from RFC 9126 section 2.1.
+pub struct PushedAuthorizationRequest<'a> {
+    endpoint: &'a str,
+    issuer: &'a str,
+    authentication: &'a ClientAuthentication<'a>,
+}
+
+impl<'a> PushedAuthorizationRequest<'a> {
+    pub fn new(endpoint: &'a str, issuer: &'a str, authentication: &'a ClientAuthentication<'a>) -> Self {
+        Self {
+            endpoint,
+            issuer,
+            authentication,
+        }
+    }
+
+    pub async fn execute(&self, parameters: &[(&str, &str)]) -> Result<ParResponse> {
+        let client = self.authentication.http_client();
+        let request = client.post(self.endpoint)
+            .header("Accept", "application/json");
+        // RFC 9126 section 2: client assertions name the issuer as their audience
+        let response = self.authentication.apply(request, self.issuer, parameters)?
+            .send()
+            .await?;
+
+        if response.status().is_client_error() {
+            let error = response.json::<ErrorResponse>().await?;
+            return Err(ParError::Rejected(error.error_description.unwrap_or(error.error)))
+        }
+        let result = response.error_for_status()?
+            .json::<ParResponse>()
+            .await?;
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::MockServer;
+    use httpmock::Method::POST;
+    use serde_json::json;
+    use crate::app::models::client_auth::ClientAuthMethod;
+    use super::*;
+
+    fn create_openid_config(par_endpoint: Option<String>, required: bool) -> OpenIdConfiguration {
+        OpenIdConfiguration {
+            issuer: "https://example.com".to_owned(),
+            authorization_endpoint: "https://example.com/authorize".to_owned(),
+            pushed_authorization_request_endpoint: par_endpoint,
+            require_pushed_authorization_requests: required,
+            ..Default::default()
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_pushed_authorization_request() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/par")
+                .body_contains("response_type=code")
+                .body_contains("state=state-01")
+                .body_contains("client_id=client-01")
+                .body_contains("client_secret=secret-01");
+            then.status(201).json_body(json!({
+                "request_uri": "urn:ietf:params:oauth:request_uri:request-01",
+                "expires_in": 60,
+            }));
+        });
+
+        let openid_config = create_openid_config(Some(server.url("/par")), false);
+        let parameters = [("response_type", "code"), ("client_id", "client-01"), ("state", "state-01")];
+        let authentication = ClientAuthentication::new(ClientAuthMethod::ClientSecretPost, "client-01", "secret-01");
+        let url = AuthorizationUrl::new(&openid_config, ParMode::Auto, &authentication)
+            .build(&parameters)
+            .await
+            .unwrap();
+        assert_eq!(1, mock.hits());
+        assert_eq!("https://example.com/authorize?client_id=client-01&request_uri=urn%3Aietf%3Aparams%3Aoauth%3Arequest_uri%3Arequest-01", url.as_str());
+    }
+
+    #[actix_rt::test]
+    async fn test_par_modes() {
+        let parameters = [("response_type", "code")];
+        let authentication = ClientAuthentication::new(ClientAuthMethod::ClientSecretPost, "client-01", "secret-01");
+
+        let openid_config = create_openid_config(None, false);
+        let url = AuthorizationUrl::new(&openid_config, ParMode::Auto, &authentication).build(&parameters).await.unwrap();
+        assert_eq!("https://example.com/authorize?response_type=code", url.as_str());
+
+        let result = AuthorizationUrl::new(&openid_config, ParMode::Always, &authentication).build(&parameters).await;
+        assert!(matches!(result, Err(ParError::EndpointMissing)));
+
+        let openid_config = create_openid_config(Some("https://example.com/par".to_owned()), true);
+        let result = AuthorizationUrl::new(&openid_config, ParMode::Never, &authentication).build(&parameters).await;
+        assert!(matches!(result, Err(ParError::ParRequired)));
+    }
+}
@@ -0,0 +1,40 @@
+use serde_derive::Serialize;
+
+use crate::app::config::OidcConfig;
+
+/// Client metadata from OpenID Connect Dynamic Client Registration 1.0 section 2, extended by the logout specs,
+/// describing what to register with a provider for this app.
+#[derive(Debug, Serialize)]
+pub struct ClientMetadata {
+    pub client_name: String,
+    pub client_id: String,
+    pub redirect_uris: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub post_logout_redirect_uris: Vec<String>,
+    /// Needs `public_url`, as providers call it directly rather than through the browser.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backchannel_logout_uri: Option<String>,
+    /// Asks for `sid` in logout tokens; the RP side of the provider's `backchannel_logout_session_supported`.
+    pub backchannel_logout_session_required: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frontchannel_logout_uri: Option<String>,
+    /// Asks for `iss` and `sid` on the front-channel logout URI, which are needed to find the session.
+    pub frontchannel_logout_session_required: bool,
+}
+
+impl ClientMetadata {
+    pub fn new(config: &OidcConfig, public_url: Option<&str>) -> Self {
+        let backchannel_logout_uri = public_url.map(|url| format!("{}/oidc/backchannel-logout", url));
+        let frontchannel_logout_uri = public_url.map(|url| format!("{}/oidc/frontchannel-logout", url));
+        Self {
+            client_name: config.name.to_owned(),
+            client_id: config.client_id.to_owned(),
+            redirect_uris: vec![config.redirect_uri.to_owned()],
+            post_logout_redirect_uris: config.post_logout_redirect_uri.iter().cloned().collect(),
+            backchannel_logout_session_required: backchannel_logout_uri.is_some(),
+            backchannel_logout_uri,
+            frontchannel_logout_session_required: frontchannel_logout_uri.is_some(),
+            frontchannel_logout_uri,
+        }
+    }
+}
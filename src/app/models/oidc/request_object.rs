@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_derive::Deserialize;
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+use crate::app::models::keys::{KeyError, SigningKey};
+use crate::app::models::random::{RandomString, RandomStringGenerator};
+
+#[derive(Debug, Error)]
+pub enum RequestObjectError {
+    #[error("request objects are enabled, but no signing key is configured")]
+    SigningKeyMissing,
+
+    #[error("request objects by reference need server.public_url")]
+    PublicUrlMissing,
+
+    #[error("signing request object failed: {0}")]
+    SigningFailed(#[from] KeyError),
+}
+
+type Result<T> = std::result::Result<T, RequestObjectError>;
+
+const REQUEST_OBJECT_TYPE: &str = "oauth-authz-req+jwt";
+
+/// Request objects kept at most; anyone can start a sign-in, so without a cap they could fill memory within the TTL.
+const DEFAULT_MAX_ENTRIES: usize = 10_000;
+
+/// How a signed request object (RFC 9101) is passed to the provider.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RequestObjectMode {
+    /// In the `request` parameter.
+    Value,
+    /// As a `request_uri` the provider fetches from our server.
+    Reference,
+}
+
+/// Request objects passed by reference, served from our server until they expire.
+///
+/// When `max_entries` are stored, the oldest one makes room for the next; providers fetch them right after
+/// the redirect, so only stale ones are lost.
+pub struct RequestObjectStore {
+    base_uri: Option<String>,
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl RequestObjectStore {
+    pub fn new(base_uri: Option<String>, ttl: Duration) -> Self {
+        Self {
+            base_uri,
+            ttl,
+            max_entries: DEFAULT_MAX_ENTRIES,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_max_entries(self, max_entries: usize) -> Self {
+        Self { max_entries, ..self }
+    }
+
+    pub fn insert(&self, request_object: String) -> Result<String> {
+        let base_uri = self.base_uri.as_ref().ok_or(RequestObjectError::PublicUrlMissing)?;
+        let id = RandomString::new().generate(32);
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (_, expires_at)| now < *expires_at);
+        // Every entry lives for the same TTL, so the oldest one expires first
+        if entries.len() >= self.max_entries {
+            let oldest = entries.iter()
+                .min_by_key(|(_, (_, expires_at))| *expires_at)
+                .map(|(id, _)| id.to_owned());
+            if let Some(id) = oldest {
+                entries.remove(&id);
+            }
+        }
+        entries.insert(id.to_owned(), (request_object, now + self.ttl));
+        Ok(format!("{}/{}", base_uri, id))
+    }
+
+    pub fn get(&self, id: &str) -> Option<String> {
+        let entries = self.entries.lock().unwrap();
+        entries.get(id)
+            .filter(|(_, expires_at)| Instant::now() < *expires_at)
+            .map(|(request_object, _)| request_object.to_owned())
+    }
+}
+
+pub struct RequestObject<'a> {
+    key: &'a SigningKey,
+    store: &'a RequestObjectStore,
+}
+
+impl<'a> RequestObject<'a> {
+    pub fn new(key: &'a SigningKey, store: &'a RequestObjectStore) -> Self {
+        Self {
+            key,
+            store,
+        }
+    }
+
+    /// Replaces the authorization parameters with a signed request object.
+    ///
+    /// `client_id`, `response_type` and `scope` are repeated outside the request object,
+    /// as OpenID Connect Core section 6.1 requires.
+    pub fn apply(&self, mode: RequestObjectMode, audience: &str, parameters: &[(&str, &str)]) -> Result<Vec<(String, String)>> {
+        let mut claims: Map<String, Value> = parameters.iter()
+            .map(|(name, value)| (name.to_string(), Value::from(*value)))
+            .collect();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        if let Some(client_id) = claims.get("client_id").cloned() {
+            claims.insert("iss".to_owned(), client_id);
+        }
+        claims.insert("aud".to_owned(), Value::from(audience));
+        claims.insert("iat".to_owned(), Value::from(now));
+        claims.insert("nbf".to_owned(), Value::from(now));
+        claims.insert("exp".to_owned(), Value::from(now + self.store.ttl.as_secs()));
+        claims.insert("jti".to_owned(), Value::from(RandomString::new().generate(32)));
+        let request_object = self.key.sign(REQUEST_OBJECT_TYPE, &claims)?;
+
+        let mut result: Vec<(String, String)> = parameters.iter()
+            .filter(|(name, _)| matches!(*name, "client_id" | "response_type" | "scope"))
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        match mode {
+            RequestObjectMode::Value => result.push(("request".to_owned(), request_object)),
+            RequestObjectMode::Reference => result.push(("request_uri".to_owned(), self.store.insert(request_object)?)),
+        }
+        Ok(result)
+    }
+}
+
+/// Authorization parameters as sent to the provider, wrapped in a request object when one is configured.
+pub fn authorization_parameters(mode: Option<RequestObjectMode>, request_object: Option<&RequestObject>, audience: &str, parameters: &[(&str, &str)]) -> Result<Vec<(String, String)>> {
+    match mode {
+        Some(mode) => request_object.ok_or(RequestObjectError::SigningKeyMissing)?
+            .apply(mode, audience, parameters),
+        None => Ok(parameters.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{Algorithm, Validation};
+    use crate::app::models::keys::tests::generate_es256_key;
+    use super::*;
+
+    const PARAMETERS: [(&str, &str); 4] = [
+        ("response_type", "code"),
+        ("client_id", "client-01"),
+        ("scope", "openid"),
+        ("state", "state-01"),
+    ];
+
+    #[test]
+    fn test_request_object_by_value() {
+        let key = generate_es256_key("key-01");
+        let store = RequestObjectStore::new(None, Duration::from_secs(60));
+        let parameters = RequestObject::new(&key, &store)
+            .apply(RequestObjectMode::Value, "https://example.com", &PARAMETERS)
+            .unwrap();
+        let names: Vec<&str> = parameters.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(vec!["response_type", "client_id", "scope", "request"], names);
+
+        let jwt = &parameters[3].1;
+        let header = jsonwebtoken::decode_header(jwt).unwrap();
+        assert_eq!(Some("oauth-authz-req+jwt".to_owned()), header.typ);
+
+        let mut validation = Validation::new(Algorithm::ES256);
+        validation.set_audience(&["https://example.com"]);
+        let claims = jsonwebtoken::decode::<Value>(jwt, &key.public_jwk().decoding_key().unwrap(), &validation).unwrap().claims;
+        assert_eq!("client-01", claims["iss"]);
+        assert_eq!("state-01", claims["state"]);
+    }
+
+    #[test]
+    fn test_request_object_by_reference() {
+        let key = generate_es256_key("key-01");
+        let store = RequestObjectStore::new(Some("https://rp.example.com/request-objects".to_owned()), Duration::from_secs(60));
+        let parameters = RequestObject::new(&key, &store)
+            .apply(RequestObjectMode::Reference, "https://example.com", &PARAMETERS)
+            .unwrap();
+        let (name, request_uri) = &parameters[3];
+        assert_eq!("request_uri", name);
+
+        let id = request_uri.strip_prefix("https://rp.example.com/request-objects/").unwrap();
+        assert!(store.get(id).is_some());
+        assert!(store.get("unknown").is_none());
+
+        let store = RequestObjectStore::new(None, Duration::from_secs(60));
+        let result = RequestObject::new(&key, &store).apply(RequestObjectMode::Reference, "https://example.com", &PARAMETERS);
+        assert!(matches!(result, Err(RequestObjectError::PublicUrlMissing)));
+    }
+
+    #[test]
+    fn test_store_evicts_oldest() {
+        let store = RequestObjectStore::new(Some("https://rp.example.com/request-objects".to_owned()), Duration::from_secs(60))
+            .with_max_entries(2);
+        let ids: Vec<String> = (1..=3)
+            .map(|n| {
+                let request_uri = store.insert(format!("request-object-{:02}", n)).unwrap();
+                std::thread::sleep(Duration::from_millis(1));
+                request_uri.rsplit('/').next().unwrap().to_owned()
+            })
+            .collect();
+
+        assert_eq!(2, store.entries.lock().unwrap().len());
+        assert_eq!(None, store.get(&ids[0]));
+        assert_eq!(Some("request-object-02".to_owned()), store.get(&ids[1]));
+        assert_eq!(Some("request-object-03".to_owned()), store.get(&ids[2]));
+    }
+}
@@ -0,0 +1,620 @@
+use async_trait::async_trait;
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+use url::Url;
+
+use crate::app::config::OidcConfig;
+use crate::app::models::client_auth::{ClientAuthError, ClientAuthMethod, ClientAuthentication};
+use crate::app::models::dpop::{self, DpopError, DpopKey};
+use crate::app::models::keys::SigningKey;
+use crate::app::models::mtls::{ClientCertificate, MtlsError};
+use crate::app::models::oidc::discovery::{DiscoveryError, JsonWebKey, OpenIdConfiguration, OpenIdConfigurationDiscovery};
+use crate::app::models::oidc::id_token::{IdTokenClaims, IdTokenError, IdTokenValidator, IdTokenVerifier, is_symmetric};
+use crate::app::models::oidc::jwks_store::JwksStore;
+use crate::app::models::oidc::logout_token::{LogoutTokenClaims, LogoutTokenReplayCache, LogoutTokenValidator, LogoutTokenVerification};
+use crate::app::models::oidc::par::{AuthorizationUrl, ParError};
+use crate::app::models::oidc::request_object::{RequestObject, RequestObjectError, authorization_parameters};
+use crate::app::models::oidc::userinfo::{StandardClaims, UserInfoError, UserInfoRequest};
+use crate::app::models::pkce::{PkceGenerator, PkceMethod};
+use crate::app::models::provider::{AuthProvider, AuthorizationRequest, EndSessionRequest, Identity, ProviderContext, ProviderError, ProviderSession, RequestAttributes};
+use crate::app::models::random::{RandomString, RandomStringGenerator};
+use crate::app::models::revocation::{LogoutReport, TokenRevocation};
+use crate::app::models::token::{TokenResponse, TokenSet};
+
+#[derive(Debug, Error)]
+pub enum OidcSigninError {
+    #[error("issuer in discovered configuration does not match {0}")]
+    IssuerMismatch(String),
+
+    #[error("no ID token in token response")]
+    IdTokenMissing,
+
+    #[error("no JWK found for ID token")]
+    JwkNotFound,
+
+    #[error("JWT error")]
+    JwtError(#[from] jsonwebtoken::errors::Error),
+
+    #[error("UserInfo request failed: {0}")]
+    UserInfoFailed(#[from] UserInfoError),
+
+    #[error("ID token validation failed: {0}")]
+    IdTokenInvalid(#[from] IdTokenError),
+
+    #[error("Discovery failed")]
+    DiscoveryFailed(#[from] DiscoveryError),
+
+    #[error("authorization request failed: {0}")]
+    AuthorizationRequestFailed(#[from] ParError),
+
+    #[error("request object failed: {0}")]
+    RequestObjectFailed(#[from] RequestObjectError),
+
+    #[error("client authentication failed: {0}")]
+    ClientAuthFailed(#[from] ClientAuthError),
+
+    #[error("certificate-bound access token check failed: {0}")]
+    CertificateBindingFailed(#[from] MtlsError),
+
+    #[error("DPoP failed: {0}")]
+    DpopFailed(#[from] DpopError),
+
+    #[error("token request failed")]
+    TokenRequestFailed(#[from] reqwest::Error),
+
+    #[error("invalid url")]
+    InvalidUrl(#[from] url::ParseError),
+}
+
+type Result<T> = std::result::Result<T, OidcSigninError>;
+
+pub async fn discover(config: &OidcConfig) -> Result<OpenIdConfiguration> {
+    let openid_config = OpenIdConfigurationDiscovery::new(&config.issuer).execute().await?;
+    if openid_config.issuer != config.issuer {
+        return Err(OidcSigninError::IssuerMismatch(config.issuer.to_owned()))
+    }
+    Ok(openid_config)
+}
+
+/// Discovers the provider, switching to its `mtls_endpoint_aliases` when a client certificate is presented.
+pub async fn discover_endpoints(config: &OidcConfig, client_certificate: Option<&ClientCertificate>) -> Result<OpenIdConfiguration> {
+    let openid_config = discover(config).await?;
+    match client_certificate {
+        Some(_) => Ok(openid_config.with_mtls_endpoint_aliases()),
+        None => Ok(openid_config),
+    }
+}
+
+/// Client authentication for the token, revocation and device authorization endpoints.
+pub fn client_authentication<'a>(config: &'a OidcConfig, openid_config: &OpenIdConfiguration, signing_key: Option<&'a SigningKey>, client_certificate: Option<&'a ClientCertificate>) -> Result<ClientAuthentication<'a>> {
+    let supported = &openid_config.token_endpoint_auth_methods_supported;
+    let method = ClientAuthMethod::negotiate(config.token_endpoint_auth_method, supported, signing_key.is_some())?;
+    let authentication = ClientAuthentication::new(method, &config.client_id, &config.client_secret)
+        .with_signing_key(signing_key)
+        .with_client_certificate(client_certificate);
+    Ok(authentication)
+}
+
+pub struct OidcAuthorization<'a> {
+    config: &'a OidcConfig,
+    request_object: Option<RequestObject<'a>>,
+    signing_key: Option<&'a SigningKey>,
+    client_certificate: Option<&'a ClientCertificate>,
+}
+
+impl<'a> OidcAuthorization<'a> {
+    pub fn new(config: &'a OidcConfig) -> Self {
+        Self {
+            config,
+            request_object: None,
+            signing_key: None,
+            client_certificate: None,
+        }
+    }
+
+    /// Enables signed request objects, used when the provider config asks for them.
+    pub fn with_request_object(self, request_object: Option<RequestObject<'a>>) -> Self {
+        Self { request_object, ..self }
+    }
+
+    /// Key for private_key_jwt client authentication on pushed authorization requests.
+    pub fn with_signing_key(self, signing_key: Option<&'a SigningKey>) -> Self {
+        Self { signing_key, ..self }
+    }
+
+    /// Certificate presented on pushed authorization requests (RFC 8705).
+    pub fn with_client_certificate(self, client_certificate: Option<&'a ClientCertificate>) -> Self {
+        Self { client_certificate, ..self }
+    }
+
+    pub async fn start(&self) -> Result<AuthorizationRequest> {
+        let config = self.config;
+        let openid_config = discover_endpoints(config, self.client_certificate).await?;
+
+        let pkce_method = PkceMethod::negotiate(config.pkce_method, &openid_config.code_challenge_methods_supported);
+        let pkce = PkceGenerator::default().generate_with_method(pkce_method, 32);
+        let attributes = RequestAttributes::new(pkce.code_verifier);
+        let mut parameters = vec![
+            ("response_type", "code"),
+            ("client_id", &config.client_id),
+            ("redirect_uri", &config.redirect_uri),
+            ("scope", &config.scope),
+            ("state", &attributes.state),
+            ("nonce", &attributes.nonce),
+            ("code_challenge_method", pkce.code_challenge_method.as_str()),
+            ("code_challenge", &pkce.code_challenge),
+        ];
+        let policy_parameters = config.id_token.authorization_parameters();
+        parameters.extend(policy_parameters.iter().map(|(name, value)| (*name, value.as_str())));
+        let parameters = authorization_parameters(config.request_object, self.request_object.as_ref(), &openid_config.issuer, &parameters)?;
+        let parameters: Vec<(&str, &str)> = parameters.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect();
+        let authentication = client_authentication(config, &openid_config, self.signing_key, self.client_certificate)?;
+        let url = AuthorizationUrl::new(&openid_config, config.par, &authentication)
+            .build(&parameters)
+            .await?;
+        let request = AuthorizationRequest {
+            request_uri: url.into(),
+            attributes,
+        };
+        Ok(request)
+    }
+}
+
+/// Exchanges the authorization code, checking certificate and DPoP bindings of the issued tokens.
+pub struct OidcCodeExchange<'a> {
+    config: &'a OidcConfig,
+    code: &'a str,
+    attributes: &'a RequestAttributes,
+    signing_key: Option<&'a SigningKey>,
+    client_certificate: Option<&'a ClientCertificate>,
+}
+
+impl<'a> OidcCodeExchange<'a> {
+    pub fn new(config: &'a OidcConfig, code: &'a str, attributes: &'a RequestAttributes) -> Self {
+        Self {
+            config,
+            code,
+            attributes,
+            signing_key: None,
+            client_certificate: None,
+        }
+    }
+
+    /// Key for private_key_jwt client authentication at the token endpoint.
+    pub fn with_signing_key(self, signing_key: Option<&'a SigningKey>) -> Self {
+        Self { signing_key, ..self }
+    }
+
+    /// Certificate presented to the token endpoint (RFC 8705); access tokens bound to it are checked.
+    pub fn with_client_certificate(self, client_certificate: Option<&'a ClientCertificate>) -> Self {
+        Self { client_certificate, ..self }
+    }
+
+    pub async fn execute(&self) -> Result<TokenSet> {
+        let openid_config = discover_endpoints(self.config, self.client_certificate).await?;
+        let authentication = client_authentication(self.config, &openid_config, self.signing_key, self.client_certificate)?;
+        let dpop_key = if self.config.dpop { Some(DpopKey::generate()?) } else { None };
+        let token_response = TokenRequest::new(self.config, &openid_config, &authentication, self.code, &self.attributes.code_verifier)
+            .with_dpop_key(dpop_key.as_ref())
+            .execute()
+            .await?;
+        if let Some(client_certificate) = self.client_certificate {
+            client_certificate.verify_binding(&token_response.access_token, openid_config.tls_client_certificate_bound_access_tokens)?;
+        }
+        Ok(TokenSet::new(token_response).with_dpop_key(dpop_key))
+    }
+}
+
+/// Verifies the ID token in a token response and builds the identity, merging UserInfo claims if configured.
+pub struct OidcIdentification<'a> {
+    config: &'a OidcConfig,
+    jwks_store: &'a JwksStore,
+    openid_config: &'a OpenIdConfiguration,
+    nonce: Option<&'a str>,
+    http_client: reqwest::Client,
+}
+
+impl<'a> OidcIdentification<'a> {
+    pub fn new(config: &'a OidcConfig, jwks_store: &'a JwksStore, openid_config: &'a OpenIdConfiguration) -> Self {
+        Self {
+            config,
+            jwks_store,
+            openid_config,
+            nonce: None,
+            http_client: reqwest::Client::new(),
+        }
+    }
+
+    /// Requires the nonce sent in the authorization request; grants without one, such as the device grant, skip it.
+    pub fn with_nonce(self, nonce: &'a str) -> Self {
+        Self { nonce: Some(nonce), ..self }
+    }
+
+    /// Client for the UserInfo request.
+    pub fn with_http_client(self, http_client: reqwest::Client) -> Self {
+        Self { http_client, ..self }
+    }
+
+    pub async fn execute(&self, tokens: &TokenSet) -> Result<OidcId> {
+        let openid_config = self.openid_config;
+        let id_token = tokens.id_token.as_deref().ok_or(OidcSigninError::IdTokenMissing)?;
+        let header = jsonwebtoken::decode_header(id_token)?;
+        let verifier = IdTokenVerifier::new(openid_config).with_client_secret(&self.config.client_secret);
+        let claims = if is_symmetric(header.alg) {
+            verifier.verify_with_client_secret::<IdTokenClaims<StandardClaims>>(id_token, &header)?
+        } else {
+            let jwk = self.find_jwk(header.kid.as_deref()).await?;
+            verifier.verify::<IdTokenClaims<StandardClaims>>(id_token, &header, &jwk)?
+        }.claims;
+        let validator = IdTokenValidator::new(&self.config.id_token, &openid_config.issuer, &self.config.client_id)
+            .with_access_token(Some(&tokens.access_token));
+        let validator = match self.nonce {
+            Some(nonce) => validator.with_nonce(nonce),
+            None => validator,
+        };
+        validator.validate(&claims, header.alg)?;
+
+        let mut id: OidcId = claims.into();
+        if self.config.userinfo {
+            let userinfo = UserInfoRequest::new(openid_config, self.jwks_store, &self.config.client_id, &tokens.access_token)
+                .with_client_secret(&self.config.client_secret)
+                .with_http_client(self.http_client.clone())
+                .with_dpop_key(tokens.dpop_key.as_ref())
+                .execute(&id.sub)
+                .await?;
+            id.claims = id.claims.merge(userinfo);
+        }
+        Ok(id)
+    }
+
+    async fn find_jwk(&self, kid: Option<&str>) -> Result<JsonWebKey> {
+        self.jwks_store.find(&self.openid_config.jwks_uri, kid).await?
+            .ok_or(OidcSigninError::JwkNotFound)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OidcId {
+    pub iss: String,
+    pub sub: String,
+    /// Changes with every sign-in, so it is not kept with the identity's claims.
+    #[serde(skip)]
+    pub sid: Option<String>,
+    #[serde(flatten)]
+    pub claims: StandardClaims,
+}
+
+impl From<IdTokenClaims<StandardClaims>> for OidcId {
+    fn from(claims: IdTokenClaims<StandardClaims>) -> Self {
+        Self {
+            iss: claims.iss,
+            sub: claims.sub,
+            sid: claims.sid,
+            claims: claims.additional,
+        }
+    }
+}
+
+struct TokenRequest<'a> {
+    config: &'a OidcConfig,
+    openid_config: &'a OpenIdConfiguration,
+    authentication: &'a ClientAuthentication<'a>,
+    code: &'a str,
+    code_verifier: &'a str,
+    dpop_key: Option<&'a DpopKey>,
+}
+
+impl<'a> TokenRequest<'a> {
+    fn new(config: &'a OidcConfig, openid_config: &'a OpenIdConfiguration, authentication: &'a ClientAuthentication<'a>, code: &'a str, code_verifier: &'a str) -> Self {
+        Self {
+            config,
+            openid_config,
+            authentication,
+            code,
+            code_verifier,
+            dpop_key: None,
+        }
+    }
+
+    fn with_dpop_key(self, dpop_key: Option<&'a DpopKey>) -> Self {
+        Self { dpop_key, ..self }
+    }
+
+    async fn execute(&self) -> Result<TokenResponse> {
+        let config = self.config;
+        let client = self.authentication.http_client();
+        let parameters = [
+            ("grant_type", "authorization_code"),
+            ("redirect_uri", &config.redirect_uri),
+            ("code", self.code),
+            ("code_verifier", self.code_verifier),
+        ];
+        let token_endpoint = &self.openid_config.token_endpoint;
+        let request = client.post(token_endpoint)
+            .header("Accept", "application/json");
+        let request = self.authentication.apply(request, token_endpoint, &parameters)?;
+        let response = dpop::send(self.dpop_key, &client, request, None)
+            .await?
+            .error_for_status()?;
+        let result = response.json::<TokenResponse>().await?;
+        if self.dpop_key.is_some() {
+            dpop::verify_token_type(&result)?;
+        }
+        Ok(result)
+    }
+}
+
+/// Revokes the tokens, authenticating the client as at the token endpoint.
+pub struct OidcSignout<'a> {
+    config: &'a OidcConfig,
+    signing_key: Option<&'a SigningKey>,
+    client_certificate: Option<&'a ClientCertificate>,
+}
+
+impl<'a> OidcSignout<'a> {
+    pub fn new(config: &'a OidcConfig) -> Self {
+        Self {
+            config,
+            signing_key: None,
+            client_certificate: None,
+        }
+    }
+
+    /// Key for private_key_jwt client authentication at the revocation endpoint.
+    pub fn with_signing_key(self, signing_key: Option<&'a SigningKey>) -> Self {
+        Self { signing_key, ..self }
+    }
+
+    /// Certificate presented to the revocation endpoint, which is then taken from `mtls_endpoint_aliases`.
+    pub fn with_client_certificate(self, client_certificate: Option<&'a ClientCertificate>) -> Self {
+        Self { client_certificate, ..self }
+    }
+
+    pub async fn execute(&self, tokens: &TokenSet) -> Result<LogoutReport> {
+        let openid_config = discover_endpoints(self.config, self.client_certificate).await?;
+        let report = match &openid_config.revocation_endpoint {
+            Some(endpoint) => {
+                let authentication = client_authentication(self.config, &openid_config, self.signing_key, self.client_certificate)?;
+                TokenRevocation::new(endpoint, &authentication).revoke_all(tokens).await
+            },
+            None => LogoutReport::unsupported(tokens),
+        };
+        Ok(report)
+    }
+}
+
+/// RP-Initiated Logout request from OpenID Connect RP-Initiated Logout 1.0 section 2.
+pub struct OidcEndSession<'a> {
+    config: &'a OidcConfig,
+}
+
+impl<'a> OidcEndSession<'a> {
+    pub fn new(config: &'a OidcConfig) -> Self {
+        Self {
+            config,
+        }
+    }
+
+    /// Builds the redirect to `end_session_endpoint`, or `None` when the provider does not advertise one.
+    pub async fn execute(&self, tokens: &TokenSet) -> Result<Option<EndSessionRequest>> {
+        let config = self.config;
+        let openid_config = discover(config).await?;
+        let endpoint = match &openid_config.end_session_endpoint {
+            Some(endpoint) => endpoint,
+            None => return Ok(None),
+        };
+
+        let state = config.post_logout_redirect_uri.as_ref().map(|_| RandomString::new().generate(32));
+        let mut parameters = vec![("client_id", config.client_id.as_str())];
+        if let Some(id_token) = &tokens.id_token {
+            parameters.push(("id_token_hint", id_token));
+        }
+        if let (Some(uri), Some(state)) = (&config.post_logout_redirect_uri, &state) {
+            parameters.push(("post_logout_redirect_uri", uri));
+            parameters.push(("state", state));
+        }
+        let url = Url::parse_with_params(endpoint, &parameters)?;
+        Ok(Some(EndSessionRequest {
+            request_uri: url.into(),
+            state,
+        }))
+    }
+}
+
+/// OpenID Connect provider found through discovery, such as Google or an `[[oidc]]` entry.
+pub struct OidcProvider {
+    config: OidcConfig,
+    client_certificate: Option<ClientCertificate>,
+    replay_cache: LogoutTokenReplayCache,
+}
+
+impl OidcProvider {
+    pub fn new(config: OidcConfig, client_certificate: Option<ClientCertificate>) -> Self {
+        Self {
+            config,
+            client_certificate,
+            replay_cache: LogoutTokenReplayCache::default(),
+        }
+    }
+
+    /// Loads the client certificate configured for mutual-TLS, if any.
+    pub async fn load(config: OidcConfig) -> std::result::Result<Self, MtlsError> {
+        let client_certificate = match &config.client_certificate {
+            Some(certificate_config) => Some(ClientCertificate::load(certificate_config).await?),
+            None => None,
+        };
+        Ok(Self::new(config, client_certificate))
+    }
+}
+
+#[async_trait(?Send)]
+impl AuthProvider for OidcProvider {
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    async fn authorization_request(&self, context: &ProviderContext<'_>) -> std::result::Result<AuthorizationRequest, ProviderError> {
+        let request_object = context.signing_key.map(|key| RequestObject::new(key, context.request_objects));
+        let request = OidcAuthorization::new(&self.config)
+            .with_request_object(request_object)
+            .with_signing_key(context.signing_key)
+            .with_client_certificate(self.client_certificate.as_ref())
+            .start()
+            .await?;
+        Ok(request)
+    }
+
+    async fn exchange_code(&self, context: &ProviderContext<'_>, code: &str, attributes: &RequestAttributes) -> std::result::Result<TokenSet, ProviderError> {
+        let tokens = OidcCodeExchange::new(&self.config, code, attributes)
+            .with_signing_key(context.signing_key)
+            .with_client_certificate(self.client_certificate.as_ref())
+            .execute()
+            .await?;
+        Ok(tokens)
+    }
+
+    async fn identity(&self, context: &ProviderContext<'_>, tokens: &TokenSet, attributes: &RequestAttributes) -> std::result::Result<Identity, ProviderError> {
+        let openid_config = discover_endpoints(&self.config, self.client_certificate.as_ref()).await?;
+        let http_client = self.client_certificate.as_ref().map(|certificate| certificate.client().clone()).unwrap_or_default();
+        let id = OidcIdentification::new(&self.config, context.jwks_store, &openid_config)
+            .with_nonce(&attributes.nonce)
+            .with_http_client(http_client)
+            .execute(tokens)
+            .await?;
+        let provider_session = ProviderSession {
+            issuer: id.iss.to_owned(),
+            subject: id.sub.to_owned(),
+            sid: id.sid.to_owned(),
+        };
+        Ok(Identity::new(self.name(), id.sub.to_owned(), &id).with_provider_session(provider_session))
+    }
+
+    async fn sign_out(&self, context: &ProviderContext<'_>, tokens: &TokenSet) -> std::result::Result<LogoutReport, ProviderError> {
+        let report = OidcSignout::new(&self.config)
+            .with_signing_key(context.signing_key)
+            .with_client_certificate(self.client_certificate.as_ref())
+            .execute(tokens)
+            .await?;
+        Ok(report)
+    }
+
+    async fn end_session(&self, tokens: &TokenSet) -> std::result::Result<Option<EndSessionRequest>, ProviderError> {
+        Ok(OidcEndSession::new(&self.config).execute(tokens).await?)
+    }
+
+    fn issuer(&self) -> Option<&str> {
+        Some(&self.config.issuer)
+    }
+
+    async fn verify_logout_token(&self, context: &ProviderContext<'_>, logout_token: &str) -> std::result::Result<LogoutTokenClaims, ProviderError> {
+        let config = &self.config;
+        let openid_config = discover_endpoints(config, self.client_certificate.as_ref()).await?;
+        let validator = LogoutTokenValidator::new(&config.id_token, &openid_config.issuer, &config.client_id, &self.replay_cache);
+        let claims = LogoutTokenVerification::new(context.jwks_store, &openid_config, validator)
+            .with_client_secret(&config.client_secret)
+            .execute(logout_token)
+            .await?;
+        Ok(claims)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use httpmock::MockServer;
+    use httpmock::Method::GET;
+    use serde_json::json;
+    use super::*;
+
+    pub(crate) fn create_config(issuer: &str) -> OidcConfig {
+        OidcConfig {
+            name: "test".to_owned(),
+            issuer: issuer.to_owned(),
+            client_id: "test-client".to_owned(),
+            client_secret: "test-secret".to_owned(),
+            redirect_uri: "http://localhost:8080/test/callback".to_owned(),
+            scope: "openid email".to_owned(),
+            id_token: Default::default(),
+            userinfo: false,
+            pkce_method: Default::default(),
+            par: Default::default(),
+            request_object: None,
+            token_endpoint_auth_method: None,
+            client_certificate: None,
+            dpop: false,
+            post_logout_redirect_uri: Some("http://localhost:8080/test/logout/callback".to_owned()),
+        }
+    }
+
+    fn mock_discovery(server: &MockServer, issuer: &str) {
+        let base = server.base_url();
+        server.mock(|when, then| {
+            when.method(GET).path("/.well-known/openid-configuration");
+            then.status(200).json_body(json!({
+                "issuer": issuer,
+                "authorization_endpoint": format!("{}/authorize", base),
+                "token_endpoint": format!("{}/token", base),
+                "jwks_uri": format!("{}/jwks", base),
+                "end_session_endpoint": format!("{}/logout", base),
+            }));
+        });
+    }
+
+    #[actix_rt::test]
+    async fn test_start_uses_discovered_authorization_endpoint() {
+        let server = MockServer::start();
+        let issuer = server.base_url();
+        mock_discovery(&server, &issuer);
+
+        let config = create_config(&issuer);
+        let request = OidcAuthorization::new(&config).start().await.unwrap();
+
+        let url = Url::parse(&request.request_uri).unwrap();
+        assert_eq!(format!("{}/authorize", issuer), format!("{}{}", url.origin().ascii_serialization(), url.path()));
+
+        let parameters: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        assert!(parameters.contains(&("client_id".to_owned(), "test-client".to_owned())));
+        assert!(parameters.contains(&("state".to_owned(), request.attributes.state)));
+        assert!(parameters.contains(&("nonce".to_owned(), request.attributes.nonce)));
+    }
+
+    #[actix_rt::test]
+    async fn test_start_rejects_issuer_mismatch() {
+        let server = MockServer::start();
+        mock_discovery(&server, "https://evil.example.com");
+
+        let config = create_config(&server.base_url());
+        let result = OidcAuthorization::new(&config).start().await;
+        assert!(matches!(result, Err(OidcSigninError::IssuerMismatch(_))));
+    }
+
+    #[actix_rt::test]
+    async fn test_end_session() {
+        let server = MockServer::start();
+        let issuer = server.base_url();
+        mock_discovery(&server, &issuer);
+
+        let config = create_config(&issuer);
+        let tokens = TokenSet {
+            access_token: "access-01".to_owned(),
+            refresh_token: None,
+            id_token: Some("id-token-01".to_owned()),
+            expires_at: None,
+            dpop_key: None,
+        };
+        let request = OidcEndSession::new(&config).execute(&tokens).await.unwrap().unwrap();
+        let state = request.state.unwrap();
+
+        let url = Url::parse(&request.request_uri).unwrap();
+        assert_eq!(format!("{}/logout", issuer), format!("{}{}", url.origin().ascii_serialization(), url.path()));
+        let parameters: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        assert!(parameters.contains(&("id_token_hint".to_owned(), "id-token-01".to_owned())));
+        assert!(parameters.contains(&("client_id".to_owned(), "test-client".to_owned())));
+        assert!(parameters.contains(&("post_logout_redirect_uri".to_owned(), "http://localhost:8080/test/logout/callback".to_owned())));
+        assert!(parameters.contains(&("state".to_owned(), state)));
+
+        let config = OidcConfig { post_logout_redirect_uri: None, ..config };
+        let request = OidcEndSession::new(&config).execute(&tokens).await.unwrap().unwrap();
+        assert!(request.state.is_none());
+        assert!(!request.request_uri.contains("state="));
+    }
+}
@@ -0,0 +1,362 @@
+use reqwest::header::CONTENT_TYPE;
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::app::models::dpop::{self, DpopError, DpopKey};
+use crate::app::models::oidc::discovery::{DiscoveryError, OpenIdConfiguration};
+use crate::app::models::oidc::id_token::{Audience, IdTokenError, IdTokenVerifier, is_symmetric};
+use crate::app::models::oidc::jwks_store::JwksStore;
+
+#[derive(Debug, Error)]
+pub enum UserInfoError {
+    #[error("provider has no userinfo_endpoint")]
+    EndpointMissing,
+
+    #[error("sub in UserInfo response does not match ID token")]
+    SubjectMismatch,
+
+    #[error("invalid issuer on signed UserInfo response")]
+    InvalidIssuer,
+
+    #[error("signed UserInfo response is not issued for this client")]
+    InvalidAudience,
+
+    #[error("unsupported UserInfo content type {0}")]
+    UnsupportedContentType(String),
+
+    #[error("no JWK found for signed UserInfo response")]
+    JwkNotFound,
+
+    #[error("UserInfo request failed")]
+    RequestFailed(#[from] reqwest::Error),
+
+    #[error("DPoP failed: {0}")]
+    DpopFailed(#[from] DpopError),
+
+    #[error("Discovery failed")]
+    DiscoveryFailed(#[from] DiscoveryError),
+
+    #[error("signed UserInfo response verification failed: {0}")]
+    VerificationFailed(#[from] IdTokenError),
+
+    #[error("JWT error")]
+    JwtError(#[from] jsonwebtoken::errors::Error),
+}
+
+type Result<T> = std::result::Result<T, UserInfoError>;
+
+/// Standard claims from OpenID Connect Core section 5.1.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct StandardClaims {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub given_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub family_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preferred_username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub picture: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phone_number: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phone_number_verified: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<Address>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Address {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub formatted: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub street_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locality: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub postal_code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+}
+
+impl StandardClaims {
+    /// Merges claims from the UserInfo endpoint, which take precedence over those in the ID token.
+    pub fn merge(self, userinfo: StandardClaims) -> Self {
+        Self {
+            name: userinfo.name.or(self.name),
+            given_name: userinfo.given_name.or(self.given_name),
+            family_name: userinfo.family_name.or(self.family_name),
+            preferred_username: userinfo.preferred_username.or(self.preferred_username),
+            picture: userinfo.picture.or(self.picture),
+            locale: userinfo.locale.or(self.locale),
+            email: userinfo.email.or(self.email),
+            email_verified: userinfo.email_verified.or(self.email_verified),
+            phone_number: userinfo.phone_number.or(self.phone_number),
+            phone_number_verified: userinfo.phone_number_verified.or(self.phone_number_verified),
+            address: userinfo.address.or(self.address),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UserInfoResponse {
+    sub: String,
+    iss: Option<String>,
+    aud: Option<Audience>,
+    #[serde(flatten)]
+    claims: StandardClaims,
+}
+
+pub struct UserInfoRequest<'a> {
+    openid_config: &'a OpenIdConfiguration,
+    jwks_store: &'a JwksStore,
+    client_id: &'a str,
+    client_secret: Option<&'a str>,
+    access_token: &'a str,
+    http_client: reqwest::Client,
+    dpop_key: Option<&'a DpopKey>,
+}
+
+impl<'a> UserInfoRequest<'a> {
+    pub fn new(openid_config: &'a OpenIdConfiguration, jwks_store: &'a JwksStore, client_id: &'a str, access_token: &'a str) -> Self {
+        Self {
+            openid_config,
+            jwks_store,
+            client_id,
+            client_secret: None,
+            access_token,
+            http_client: reqwest::Client::new(),
+            dpop_key: None,
+        }
+    }
+
+    /// Secret that responses signed with HS256, HS384 or HS512 are keyed with.
+    pub fn with_client_secret(self, client_secret: &'a str) -> Self {
+        Self { client_secret: Some(client_secret), ..self }
+    }
+
+    /// Key the access token is bound to, for DPoP-bound tokens (RFC 9449 section 7).
+    pub fn with_dpop_key(self, dpop_key: Option<&'a DpopKey>) -> Self {
+        Self { dpop_key, ..self }
+    }
+
+    /// Client to send the request with, e.g. one presenting a certificate for certificate-bound access tokens.
+    pub fn with_http_client(self, http_client: reqwest::Client) -> Self {
+        Self { http_client, ..self }
+    }
+
+    /// Fetches claims for the subject of an already validated ID token.
+    pub async fn execute(&self, sub: &str) -> Result<StandardClaims> {
+        let endpoint = self.openid_config.userinfo_endpoint.as_ref().ok_or(UserInfoError::EndpointMissing)?;
+        let request = self.http_client.get(endpoint)
+            .header("Accept", "application/json, application/jwt");
+        let response = dpop::send(self.dpop_key, &self.http_client, request, Some(self.access_token))
+            .await?
+            .error_for_status()?;
+
+        let content_type = response.headers().get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("application/json")
+            .to_owned();
+        let userinfo = match content_type.split(';').next().map(str::trim) {
+            Some("application/json") => response.json::<UserInfoResponse>().await?,
+            Some("application/jwt") => self.verify(&response.text().await?).await?,
+            _ => return Err(UserInfoError::UnsupportedContentType(content_type)),
+        };
+
+        if userinfo.sub != sub {
+            return Err(UserInfoError::SubjectMismatch)
+        }
+        Ok(userinfo.claims)
+    }
+
+    async fn verify(&self, jwt: &str) -> Result<UserInfoResponse> {
+        let header = jsonwebtoken::decode_header(jwt)?;
+        let verifier = IdTokenVerifier::for_userinfo(self.openid_config);
+        let verifier = match self.client_secret {
+            Some(client_secret) => verifier.with_client_secret(client_secret),
+            None => verifier,
+        };
+        let userinfo = if is_symmetric(header.alg) {
+            verifier.verify_with_client_secret::<UserInfoResponse>(jwt, &header)?
+        } else {
+            let jwk = self.jwks_store.find(&self.openid_config.jwks_uri, header.kid.as_deref()).await?
+                .ok_or(UserInfoError::JwkNotFound)?;
+            verifier.verify::<UserInfoResponse>(jwt, &header, &jwk)?
+        }.claims;
+
+        // OpenID Connect Core section 5.3.2: signed responses should carry iss and aud
+        if matches!(&userinfo.iss, Some(iss) if *iss != self.openid_config.issuer) {
+            return Err(UserInfoError::InvalidIssuer)
+        }
+        if matches!(&userinfo.aud, Some(aud) if !aud.contains(self.client_id)) {
+            return Err(UserInfoError::InvalidAudience)
+        }
+        Ok(userinfo)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::MockServer;
+    use httpmock::Method::GET;
+    use jsonwebtoken::{Algorithm, EncodingKey, Header};
+    use ring::rand::SystemRandom;
+    use ring::signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair};
+    use serde_json::{json, Value};
+    use crate::app::models::base64url;
+    use super::*;
+
+    fn create_openid_config(server: &MockServer) -> OpenIdConfiguration {
+        OpenIdConfiguration {
+            issuer: server.base_url(),
+            userinfo_endpoint: Some(server.url("/userinfo")),
+            jwks_uri: server.url("/jwks"),
+            userinfo_signing_alg_values_supported: vec!["ES256".to_owned(), "HS256".to_owned()],
+            ..Default::default()
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_json_userinfo() {
+        let server = MockServer::start();
+        let _mock = server.mock(|when, then| {
+            when.method(GET).path("/userinfo").header("Authorization", "Bearer access-token-01");
+            then.status(200).json_body(json!({
+                "sub": "user-01",
+                "name": "Test User",
+                "email_verified": true,
+                "address": { "country": "JP" },
+            }));
+        });
+
+        let openid_config = create_openid_config(&server);
+        let jwks_store = JwksStore::default();
+        let request = UserInfoRequest::new(&openid_config, &jwks_store, "client-01", "access-token-01");
+
+        let claims = request.execute("user-01").await.unwrap();
+        assert_eq!(Some("Test User".to_owned()), claims.name);
+        assert_eq!(Some(true), claims.email_verified);
+        assert_eq!(Some("JP".to_owned()), claims.address.unwrap().country);
+
+        let result = request.execute("user-02").await;
+        assert!(matches!(result, Err(UserInfoError::SubjectMismatch)));
+    }
+
+    fn signed_userinfo(server: &MockServer, header: &Header, key: &EncodingKey) {
+        let claims = json!({ "sub": "user-01", "iss": server.base_url(), "aud": "client-01", "locale": "ja-JP" });
+        let jwt = jsonwebtoken::encode(header, &claims, key).unwrap();
+        server.mock(|when, then| {
+            when.method(GET).path("/userinfo");
+            then.status(200).header("Content-Type", "application/jwt").body(&jwt);
+        });
+    }
+
+    fn jwks(server: &MockServer, keys: Vec<Value>) {
+        server.mock(|when, then| {
+            when.method(GET).path("/jwks");
+            then.status(200).json_body(json!({ "keys": keys }));
+        });
+    }
+
+    #[actix_rt::test]
+    async fn test_signed_userinfo() {
+        let server = MockServer::start();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new()).unwrap();
+        let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref()).unwrap();
+        let point = key_pair.public_key().as_ref();
+        jwks(&server, vec![json!({ "kty": "EC", "kid": "key-01", "crv": "P-256", "x": base64url::encode(&point[1..33]), "y": base64url::encode(&point[33..]) })]);
+
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some("key-01".to_owned());
+        signed_userinfo(&server, &header, &EncodingKey::from_ec_der(pkcs8.as_ref()));
+
+        let openid_config = create_openid_config(&server);
+        let jwks_store = JwksStore::default();
+        let claims = UserInfoRequest::new(&openid_config, &jwks_store, "client-01", "access-token-01")
+            .execute("user-01")
+            .await
+            .unwrap();
+        assert_eq!(Some("ja-JP".to_owned()), claims.locale);
+
+        let result = UserInfoRequest::new(&openid_config, &jwks_store, "client-02", "access-token-01")
+            .execute("user-01")
+            .await;
+        assert!(matches!(result, Err(UserInfoError::InvalidAudience)));
+    }
+
+    #[actix_rt::test]
+    async fn test_signed_userinfo_with_client_secret() {
+        let server = MockServer::start();
+        signed_userinfo(&server, &Header::new(Algorithm::HS256), &EncodingKey::from_secret(b"secret-01"));
+
+        let openid_config = create_openid_config(&server);
+        let jwks_store = JwksStore::default();
+        let claims = UserInfoRequest::new(&openid_config, &jwks_store, "client-01", "access-token-01")
+            .with_client_secret("secret-01")
+            .execute("user-01")
+            .await
+            .unwrap();
+        assert_eq!(Some("ja-JP".to_owned()), claims.locale);
+
+        let result = UserInfoRequest::new(&openid_config, &jwks_store, "client-01", "access-token-01")
+            .with_client_secret("secret-02")
+            .execute("user-01")
+            .await;
+        assert!(matches!(result, Err(UserInfoError::VerificationFailed(IdTokenError::JwtError(_)))));
+    }
+
+    #[actix_rt::test]
+    async fn test_signed_userinfo_rejects_jwks_oct_key() {
+        // Anyone can read a published symmetric key and sign with it
+        let server = MockServer::start();
+        let secret = b"published-secret";
+        jwks(&server, vec![json!({ "kty": "oct", "kid": "key-01", "k": base64url::encode(secret), "alg": "HS256" })]);
+
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("key-01".to_owned());
+        signed_userinfo(&server, &header, &EncodingKey::from_secret(secret));
+
+        let openid_config = create_openid_config(&server);
+        let jwks_store = JwksStore::default();
+        let result = UserInfoRequest::new(&openid_config, &jwks_store, "client-01", "access-token-01")
+            .execute("user-01")
+            .await;
+        assert!(matches!(result, Err(UserInfoError::VerificationFailed(IdTokenError::ClientSecretMissing(Algorithm::HS256)))));
+
+        let result = UserInfoRequest::new(&openid_config, &jwks_store, "client-01", "access-token-01")
+            .with_client_secret("secret-01")
+            .execute("user-01")
+            .await;
+        assert!(matches!(result, Err(UserInfoError::VerificationFailed(IdTokenError::JwtError(_)))));
+        assert_eq!(None, jwks_store.find(&openid_config.jwks_uri, Some("key-01")).await.unwrap());
+    }
+
+    #[test]
+    fn test_merge() {
+        let id_token_claims = StandardClaims {
+            name: Some("ID Token Name".to_owned()),
+            email: Some("user@example.com".to_owned()),
+            ..Default::default()
+        };
+        let userinfo_claims = StandardClaims {
+            name: Some("UserInfo Name".to_owned()),
+            picture: Some("https://example.com/picture.png".to_owned()),
+            ..Default::default()
+        };
+
+        let merged = id_token_claims.merge(userinfo_claims);
+        assert_eq!(Some("UserInfo Name".to_owned()), merged.name);
+        assert_eq!(Some("user@example.com".to_owned()), merged.email);
+        assert_eq!(Some("https://example.com/picture.png".to_owned()), merged.picture);
+    }
+}
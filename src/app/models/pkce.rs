@@ -1,12 +1,44 @@
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
+use crate::app::models::base64url;
 use crate::app::models::random::{RandomString, RandomStringGenerator};
 
+/// `code_challenge_method` from RFC 7636 section 4.3.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub enum PkceMethod {
+    #[default]
+    S256,
+    #[serde(rename = "plain")]
+    Plain,
+}
+
+impl PkceMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::S256 => "S256",
+            Self::Plain => "plain",
+        }
+    }
+
+    /// Uses `plain` only when it is both configured and advertised in `code_challenge_methods_supported`;
+    /// anything else falls back to S256, which every provider supporting PKCE has to implement.
+    pub fn negotiate(configured: PkceMethod, supported: &[String]) -> Self {
+        if configured == Self::Plain {
+            if supported.iter().any(|method| method == Self::Plain.as_str()) {
+                return Self::Plain
+            }
+            log::warn!("PKCE method plain is not advertised by the provider, using S256");
+        }
+        Self::S256
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Pkce {
     pub code_verifier: String,
     pub code_challenge: String,
+    pub code_challenge_method: PkceMethod,
 }
 
 pub struct PkceGenerator {
@@ -23,12 +55,20 @@ impl Default for PkceGenerator {
 
 impl PkceGenerator {
     pub fn generate(&self, size: usize) -> Pkce {
+        self.generate_with_method(PkceMethod::S256, size)
+    }
+
+    pub fn generate_with_method(&self, method: PkceMethod, size: usize) -> Pkce {
         let verifier = self.generate_verifier(size);
-        let challenge = self.generate_challenge(&verifier);
+        let challenge = match method {
+            PkceMethod::S256 => self.generate_challenge(&verifier),
+            PkceMethod::Plain => verifier.to_owned(),
+        };
 
         Pkce {
             code_verifier: verifier,
             code_challenge: challenge,
+            code_challenge_method: method,
         }
     }
 
@@ -38,7 +78,7 @@ impl PkceGenerator {
 
     fn generate_challenge(&self, verifier: &str) -> String {
         let digest = Sha256::digest(verifier.as_bytes());
-        base64::encode_config(digest, base64::URL_SAFE_NO_PAD)
+        base64url::encode(digest)
     }
 }
 
@@ -51,7 +91,7 @@ mod tests {
     impl RandomStringGenerator for DummyRSG {
         fn generate(&self, size: usize) -> String {
             let bytes: Vec<u8> = (0..size).map(|n| n as u8).collect();
-            base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+            base64url::encode(bytes)
         }
     }
 
@@ -63,5 +103,18 @@ mod tests {
         let pkce = generator.generate(16);
         assert_eq!("AAECAwQFBgcICQoLDA0ODw", pkce.code_verifier);
         assert_eq!("XSYR1aTdK0Cyd9_bXs9bSYGRUNfVxi9O75YMsMJNsgw", pkce.code_challenge);
+
+        let pkce = generator.generate_with_method(PkceMethod::Plain, 16);
+        assert_eq!(pkce.code_verifier, pkce.code_challenge);
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let both = vec!["plain".to_owned(), "S256".to_owned()];
+        let s256_only = vec!["S256".to_owned()];
+        assert_eq!(PkceMethod::S256, PkceMethod::negotiate(PkceMethod::S256, &both));
+        assert_eq!(PkceMethod::Plain, PkceMethod::negotiate(PkceMethod::Plain, &both));
+        assert_eq!(PkceMethod::S256, PkceMethod::negotiate(PkceMethod::Plain, &s256_only));
+        assert_eq!(PkceMethod::S256, PkceMethod::negotiate(PkceMethod::Plain, &[]));
     }
 }
@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use serde::Serialize;
+use serde_derive::Deserialize;
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+use crate::app::config::AppConfig;
+use crate::app::models::github::{GithubProvider, GithubSigninError};
+use crate::app::models::keys::SigningKey;
+use crate::app::models::mtls::MtlsError;
+use crate::app::models::oidc::jwks_store::JwksStore;
+use crate::app::models::oidc::logout_token::{LogoutTokenClaims, LogoutTokenError};
+use crate::app::models::oidc::request_object::RequestObjectStore;
+use crate::app::models::oidc::signin::{OidcProvider, OidcSigninError};
+use crate::app::models::random::{RandomString, RandomStringGenerator};
+use crate::app::models::revocation::LogoutReport;
+use crate::app::models::spotify::{SpotifyProvider, SpotifySigninError};
+use crate::app::models::token::{RefreshTokenRequest, TokenSet};
+
+#[derive(Debug, Error)]
+pub enum ProviderError {
+    #[error("no provider named {0}")]
+    UnknownProvider(String),
+
+    #[error("no saved request attributes")]
+    RequestAttributesMissing,
+
+    #[error("state mismatch")]
+    StateMismatch,
+
+    #[error("authorization failed: {0}")]
+    AuthorizationFailed(String),
+
+    #[error("no provider with issuer {0}")]
+    UnknownIssuer(String),
+
+    #[error("provider {0} is registered twice")]
+    DuplicateProvider(String),
+
+    #[error("provider name {0} is taken by a fixed route")]
+    ReservedProviderName(String),
+
+    #[error("loading client certificate failed: {0}")]
+    ClientCertificate(#[from] MtlsError),
+
+    #[error("{0} does not support back-channel logout")]
+    BackchannelLogoutUnsupported(String),
+
+    #[error("invalid logout token: {0}")]
+    LogoutToken(#[from] LogoutTokenError),
+
+    #[error("GitHub sign-in failed: {0}")]
+    Github(#[from] GithubSigninError),
+
+    #[error("Spotify sign-in failed: {0}")]
+    Spotify(#[from] SpotifySigninError),
+
+    #[error("OpenID Connect sign-in failed: {0}")]
+    Oidc(#[from] OidcSigninError),
+}
+
+type Result<T> = std::result::Result<T, ProviderError>;
+
+/// Shared services a provider may use while signing a user in or out.
+pub struct ProviderContext<'a> {
+    pub jwks_store: &'a JwksStore,
+    pub signing_key: Option<&'a SigningKey>,
+    pub request_objects: &'a RequestObjectStore,
+}
+
+/// Kept in the session between the authorization request and the callback.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RequestAttributes {
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+impl RequestAttributes {
+    pub fn new(code_verifier: String) -> Self {
+        let rsg = RandomString::new();
+        Self {
+            state: rsg.generate(32),
+            nonce: rsg.generate(32),
+            code_verifier,
+        }
+    }
+}
+
+pub struct AuthorizationRequest {
+    pub request_uri: String,
+    pub attributes: RequestAttributes,
+}
+
+/// Authorization response from RFC 6749 section 4.1.2, or the error response from section 4.1.2.1.
+#[derive(Debug, Default, Deserialize)]
+pub struct AuthorizationResponse {
+    pub state: Option<String>,
+    pub code: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+impl AuthorizationResponse {
+    /// Returns the authorization code once the state matches the one saved with the request.
+    pub fn code(&self, attributes: &RequestAttributes) -> Result<&str> {
+        if self.state.as_deref() != Some(attributes.state.as_str()) {
+            return Err(ProviderError::StateMismatch)
+        }
+        if let Some(error) = &self.error {
+            return Err(ProviderError::AuthorizationFailed(self.error_description.clone().unwrap_or_else(|| error.to_owned())))
+        }
+        self.code.as_deref().ok_or_else(|| ProviderError::AuthorizationFailed("no code in authorization response".to_owned()))
+    }
+}
+
+/// Redirect ending the user's session at the provider.
+pub struct EndSessionRequest {
+    pub request_uri: String,
+    /// Expected back at the post-logout callback; `None` when the provider does not redirect back.
+    pub state: Option<String>,
+}
+
+/// Session at an OpenID provider, which the provider may end through back-channel logout.
+#[derive(Debug)]
+pub struct ProviderSession {
+    pub issuer: String,
+    pub subject: String,
+    pub sid: Option<String>,
+}
+
+/// Signed-in user as reported by a provider.
+#[derive(Debug, Serialize)]
+pub struct Identity {
+    pub provider: String,
+    pub subject: String,
+    #[serde(flatten)]
+    pub claims: Map<String, Value>,
+    #[serde(skip)]
+    pub provider_session: Option<ProviderSession>,
+}
+
+impl Identity {
+    pub fn new<T: Serialize>(provider: &str, subject: String, claims: &T) -> Self {
+        let claims = match serde_json::to_value(claims) {
+            Ok(Value::Object(claims)) => claims,
+            _ => Map::new(),
+        };
+        Self {
+            provider: provider.to_owned(),
+            subject,
+            claims,
+            provider_session: None,
+        }
+    }
+
+    pub fn with_provider_session(self, provider_session: ProviderSession) -> Self {
+        Self { provider_session: Some(provider_session), ..self }
+    }
+}
+
+/// An authorization code flow provider; adding a provider only takes an implementation of this trait.
+#[async_trait(?Send)]
+pub trait AuthProvider: Send + Sync {
+    /// Path segment the provider is served under.
+    fn name(&self) -> &str;
+
+    async fn authorization_request(&self, context: &ProviderContext<'_>) -> Result<AuthorizationRequest>;
+
+    async fn exchange_code(&self, context: &ProviderContext<'_>, code: &str, attributes: &RequestAttributes) -> Result<TokenSet>;
+
+    async fn identity(&self, context: &ProviderContext<'_>, tokens: &TokenSet, attributes: &RequestAttributes) -> Result<Identity>;
+
+    /// Revokes the tokens where the provider supports it.
+    async fn sign_out(&self, _context: &ProviderContext<'_>, tokens: &TokenSet) -> Result<LogoutReport> {
+        Ok(LogoutReport::unsupported(tokens))
+    }
+
+    /// Logout redirect for providers supporting RP-initiated logout.
+    async fn end_session(&self, _tokens: &TokenSet) -> Result<Option<EndSessionRequest>> {
+        Ok(None)
+    }
+
+    /// Refresh grant for public clients, whose access tokens this app hands out on request.
+    fn refresh_token_request(&self) -> Option<RefreshTokenRequest<'_>> {
+        None
+    }
+
+    /// Issuer of the provider's logout tokens.
+    fn issuer(&self) -> Option<&str> {
+        None
+    }
+
+    /// Verifies a logout token from OpenID Connect Back-Channel Logout.
+    async fn verify_logout_token(&self, _context: &ProviderContext<'_>, _logout_token: &str) -> Result<LogoutTokenClaims> {
+        Err(ProviderError::BackchannelLogoutUnsupported(self.name().to_owned()))
+    }
+}
+
+/// First path segments the app serves itself; a provider of the same name would clash with them under `/{provider}`.
+const RESERVED_NAMES: &[&str] = &["oidc", "user", "webauthn", "jwks", "request-objects", "introspection"];
+
+#[derive(Default)]
+pub struct ProviderRegistry {
+    providers: HashMap<String, Box<dyn AuthProvider>>,
+}
+
+impl ProviderRegistry {
+    /// Registers GitHub, Google, Spotify and every `[[oidc]]` provider, loading their client certificates.
+    pub async fn from_config(config: &AppConfig) -> Result<Self> {
+        let mut registry = Self::default();
+        registry.register(GithubProvider::new(config.github.clone()))?;
+        registry.register(OidcProvider::load(config.google.to_oidc_config()).await?)?;
+        registry.register(SpotifyProvider::new(config.spotify.clone()))?;
+        for oidc in &config.oidc {
+            registry.register(OidcProvider::load(oidc.clone()).await?)?;
+        }
+        Ok(registry)
+    }
+
+    /// Adds a provider, refusing names that are taken by another provider or a fixed route.
+    pub fn register(&mut self, provider: impl AuthProvider + 'static) -> Result<()> {
+        let name = provider.name();
+        if RESERVED_NAMES.contains(&name) {
+            return Err(ProviderError::ReservedProviderName(name.to_owned()))
+        }
+        if self.providers.contains_key(name) {
+            return Err(ProviderError::DuplicateProvider(name.to_owned()))
+        }
+        self.providers.insert(name.to_owned(), Box::new(provider));
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Result<&dyn AuthProvider> {
+        self.providers.get(name)
+            .map(|provider| provider.as_ref())
+            .ok_or_else(|| ProviderError::UnknownProvider(name.to_owned()))
+    }
+
+    pub fn find_by_issuer(&self, issuer: &str) -> Result<&dyn AuthProvider> {
+        self.providers.values()
+            .find(|provider| provider.issuer() == Some(issuer))
+            .map(|provider| provider.as_ref())
+            .ok_or_else(|| ProviderError::UnknownIssuer(issuer.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::config::{OidcConfig, SpotifyConfig};
+    use crate::app::models::oidc::signin::tests::create_config;
+    use super::*;
+
+    fn create_attributes() -> RequestAttributes {
+        RequestAttributes {
+            state: "state-01".to_owned(),
+            nonce: "nonce-01".to_owned(),
+            code_verifier: "verifier-01".to_owned(),
+        }
+    }
+
+    fn create_response(state: Option<&str>, code: Option<&str>, error: Option<&str>) -> AuthorizationResponse {
+        AuthorizationResponse {
+            state: state.map(str::to_owned),
+            code: code.map(str::to_owned),
+            error: error.map(str::to_owned),
+            error_description: None,
+        }
+    }
+
+    #[test]
+    fn test_authorization_response_code() {
+        let attributes = create_attributes();
+        assert_eq!("code-01", create_response(Some("state-01"), Some("code-01"), None).code(&attributes).unwrap());
+
+        let response = create_response(Some("state-02"), Some("code-01"), None);
+        assert!(matches!(response.code(&attributes), Err(ProviderError::StateMismatch)));
+        let response = create_response(None, Some("code-01"), None);
+        assert!(matches!(response.code(&attributes), Err(ProviderError::StateMismatch)));
+
+        let response = create_response(Some("state-01"), None, Some("access_denied"));
+        assert!(matches!(response.code(&attributes), Err(ProviderError::AuthorizationFailed(message)) if message == "access_denied"));
+        let response = create_response(Some("state-01"), None, None);
+        assert!(matches!(response.code(&attributes), Err(ProviderError::AuthorizationFailed(_))));
+    }
+
+    fn spotify_provider() -> SpotifyProvider {
+        SpotifyProvider::new(SpotifyConfig {
+            client_id: "client-01".to_owned(),
+            redirect_uri: "http://localhost:8080/spotify/callback".to_owned(),
+            scope: "user-read-email".to_owned(),
+            dpop: false,
+        })
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = ProviderRegistry::default();
+        registry.register(spotify_provider()).unwrap();
+        assert!(matches!(registry.register(spotify_provider()), Err(ProviderError::DuplicateProvider(name)) if name == "spotify"));
+        let config = OidcConfig { name: "jwks".to_owned(), ..create_config("https://issuer.example.com") };
+        let result = registry.register(OidcProvider::new(config, None));
+        assert!(matches!(result, Err(ProviderError::ReservedProviderName(name)) if name == "jwks"));
+
+        let provider = registry.get("spotify").unwrap();
+        assert_eq!("spotify", provider.name());
+        assert!(provider.refresh_token_request().is_some());
+        assert!(matches!(registry.get("jwks"), Err(ProviderError::UnknownProvider(name)) if name == "jwks"));
+        assert!(matches!(registry.find_by_issuer("https://accounts.spotify.com"), Err(ProviderError::UnknownIssuer(_))));
+    }
+}
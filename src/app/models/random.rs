@@ -1,5 +1,7 @@
 use rand::{RngCore, SeedableRng, rngs::StdRng};
 
+use crate::app::models::base64url;
+
 pub trait RandomStringGenerator {
     fn generate(&self, size: usize) -> String;
 }
@@ -23,6 +25,6 @@ impl RandomStringGenerator for RandomString {
         let mut rng = StdRng::from_entropy();
         let mut rs = vec![0; size];
         rng.fill_bytes(&mut rs);
-        base64::encode_config(rs, base64::URL_SAFE_NO_PAD)
+        base64url::encode(rs)
      }
 }
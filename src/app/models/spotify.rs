@@ -1,137 +1,103 @@
+use async_trait::async_trait;
 use serde_derive::{Deserialize, Serialize};
 use thiserror::Error;
 use url::Url;
 
 use crate::app::config::SpotifyConfig;
+use crate::app::models::dpop::{self, DpopError, DpopKey};
 use crate::app::models::pkce::PkceGenerator;
-
-use super::random::{RandomString, RandomStringGenerator};
+use crate::app::models::provider::{AuthProvider, AuthorizationRequest, Identity, ProviderContext, ProviderError, RequestAttributes};
+use crate::app::models::revocation::LogoutReport;
+use crate::app::models::token::{RefreshTokenRequest, TokenResponse, TokenSet};
 
 #[derive(Debug, Error)]
 pub enum SpotifySigninError {
-    #[error("missing request attributes")]
-    RequestAttributesMissing,
-
-    #[error("state mismatch")]
-    StateMismatch,
-
     #[error("token request failed")]
     TokenRequestFailed(#[from] reqwest::Error),
 
+    #[error("DPoP failed: {0}")]
+    DpopFailed(#[from] DpopError),
+
     #[error("invalid url")]
     InvalidUrl(#[from] url::ParseError),
 }
 
 type Result<T> = std::result::Result<T, SpotifySigninError>;
 
-pub struct SpotifyAuthorization<'a> {
-    config: &'a SpotifyConfig,
+const TOKEN_ENDPOINT: &str = "https://accounts.spotify.com/api/token";
+
+const AUTHORIZATION_ENDPOINT: &str = "https://accounts.spotify.com/authorize";
+
+pub struct SpotifyProvider {
+    config: SpotifyConfig,
 }
 
-impl<'a> SpotifyAuthorization<'a> {
-    pub fn new(config: &'a SpotifyConfig) -> Self {
+impl SpotifyProvider {
+    pub fn new(config: SpotifyConfig) -> Self {
         Self {
             config,
         }
     }
+}
 
-    pub fn start(&self) -> Result<AuthRequest> {
-        let config = self.config;
+#[async_trait(?Send)]
+impl AuthProvider for SpotifyProvider {
+    fn name(&self) -> &str {
+        "spotify"
+    }
 
+    async fn authorization_request(&self, _context: &ProviderContext<'_>) -> std::result::Result<AuthorizationRequest, ProviderError> {
+        let config = &self.config;
         let pkce = PkceGenerator::default().generate(32);
-        let state = self.generate_state();
-
-        let base = "https://accounts.spotify.com/authorize";
+        let attributes = RequestAttributes::new(pkce.code_verifier);
         let parameters = vec![
             ("response_type", "code"),
             ("client_id", &config.client_id),
             ("redirect_uri", &config.redirect_uri),
             ("scope", &config.scope),
-            ("state", &state),
-            ("code_challenge_method", "S256"),
+            ("state", &attributes.state),
+            ("code_challenge_method", pkce.code_challenge_method.as_str()),
             ("code_challenge", &pkce.code_challenge),
         ];
-        let uri = Url::parse_with_params(base, &parameters)?;
-
-        let request = AuthRequest {
+        let uri = Url::parse_with_params(AUTHORIZATION_ENDPOINT, &parameters).map_err(SpotifySigninError::from)?;
+        Ok(AuthorizationRequest {
             request_uri: uri.into(),
-            attributes: RequestAttributes {
-                state,
-                code_verifier: pkce.code_verifier,
-            },
-        };
-        Ok(request)
+            attributes,
+        })
     }
 
-    fn generate_state(&self) -> String {
-        RandomString::new().generate(32)
+    async fn exchange_code(&self, _context: &ProviderContext<'_>, code: &str, attributes: &RequestAttributes) -> std::result::Result<TokenSet, ProviderError> {
+        let dpop_key = if self.config.dpop { Some(DpopKey::generate().map_err(SpotifySigninError::from)?) } else { None };
+        let token = TokenRequest::new(&self.config, code, &attributes.code_verifier)
+            .with_dpop_key(dpop_key.as_ref())
+            .execute()
+            .await?;
+        Ok(TokenSet::new(token).with_dpop_key(dpop_key))
     }
-}
-
-pub struct AuthRequest {
-    pub request_uri: String,
-    pub attributes: RequestAttributes,
-}
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct RequestAttributes {
-    pub state: String,
-    pub code_verifier: String,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct AuthResponse {
-    pub state: String,
-    pub code: String,
-}
-
-pub struct SpotifySignin<'a> {
-    config: &'a SpotifyConfig,
-    response: &'a AuthResponse,
-    attributes: &'a Option<RequestAttributes>,
-}
-
-impl<'a> SpotifySignin<'a> {
-    pub fn new(config: &'a SpotifyConfig, response: &'a AuthResponse, attributes: &'a Option<RequestAttributes>) -> Self {
-        Self {
-            config,
-            response,
-            attributes,
-        }
+    async fn identity(&self, _context: &ProviderContext<'_>, tokens: &TokenSet, _attributes: &RequestAttributes) -> std::result::Result<Identity, ProviderError> {
+        let user = UserRequest::new(&tokens.access_token)
+            .with_dpop_key(tokens.dpop_key.as_ref())
+            .execute()
+            .await?;
+        Ok(Identity::new(self.name(), user.id.to_owned(), &user))
     }
 
-    pub async fn execute(&self) -> Result<SigninResult> {
-        let attrs = self.attributes.as_ref().ok_or(SpotifySigninError::RequestAttributesMissing)?;
-        if self.response.state != attrs.state {
-            return Err(SpotifySigninError::StateMismatch)
-        }
-
-        let token = TokenRequest::new(self.config, &self.response.code, &attrs.code_verifier).execute().await?;
-        let user = UserRequest::new(&token.access_token).execute().await?;
-        let result = SigninResult {
-            id: user.id,
-            display_name: user.display_name,
-            email: user.email,
-            access_token: token.access_token,
-            refresh_token: token.refresh_token,
-        };
-        Ok(result)
+    async fn sign_out(&self, _context: &ProviderContext<'_>, tokens: &TokenSet) -> std::result::Result<LogoutReport, ProviderError> {
+        // Spotify offers no revocation endpoint; users have to remove the app from their account page
+        Ok(LogoutReport::unsupported(tokens))
     }
-}
 
-#[derive(Debug, Serialize)]
-pub struct SigninResult {
-    id: String,
-    display_name: String,
-    email: String,
-    access_token: String,
-    refresh_token: String,
+    fn refresh_token_request(&self) -> Option<RefreshTokenRequest<'_>> {
+        Some(RefreshTokenRequest::new(TOKEN_ENDPOINT, &self.config.client_id))
+    }
 }
 
 struct TokenRequest<'a> {
     config: &'a SpotifyConfig,
     code: &'a str,
     code_verifier: &'a str,
+    dpop_key: Option<&'a DpopKey>,
 }
 
 impl<'a> TokenRequest<'a> {
@@ -140,10 +106,15 @@ impl<'a> TokenRequest<'a> {
             config,
             code,
             code_verifier,
+            dpop_key: None,
         }
     }
 
-    async fn execute(&self) -> Result<AccessToken> {
+    fn with_dpop_key(self, dpop_key: Option<&'a DpopKey>) -> Self {
+        Self { dpop_key, ..self }
+    }
+
+    async fn execute(&self) -> Result<TokenResponse> {
         let config = self.config;
 
         let client = reqwest::Client::new();
@@ -154,40 +125,42 @@ impl<'a> TokenRequest<'a> {
             ("redirect_uri", &config.redirect_uri),
             ("code_verifier", self.code_verifier),
         ];
-        let result = client.post("https://accounts.spotify.com/api/token")
+        let request = client.post(TOKEN_ENDPOINT)
             .header("Accept", "application/json")
-            .form(&parameters)
-            .send()
+            .form(&parameters);
+        let result = dpop::send(self.dpop_key, &client, request, None)
             .await?
-            .json::<AccessToken>().await?;
+            .json::<TokenResponse>().await?;
+        if self.dpop_key.is_some() {
+            dpop::verify_token_type(&result)?;
+        }
 
         Ok(result)
     }
 }
 
-#[derive(Debug, Deserialize)]
-struct AccessToken {
-    access_token: String,
-    refresh_token: String,
-}
-
 struct UserRequest<'a> {
     access_token: &'a str,
+    dpop_key: Option<&'a DpopKey>,
 }
 
 impl<'a> UserRequest<'a> {
     fn new(access_token: &'a str) -> Self {
         Self {
             access_token,
+            dpop_key: None,
         }
     }
 
+    fn with_dpop_key(self, dpop_key: Option<&'a DpopKey>) -> Self {
+        Self { dpop_key, ..self }
+    }
+
     async fn execute(&self) -> Result<User> {
         let client = reqwest::Client::new();
-        let result = client.get("https://api.spotify.com/v1/me")
-            .header("Accept", "application/json")
-            .header("Authorization", format!("Bearer {}", self.access_token))
-            .send()
+        let request = client.get("https://api.spotify.com/v1/me")
+            .header("Accept", "application/json");
+        let result = dpop::send(self.dpop_key, &client, request, Some(self.access_token))
             .await?
             .json::<User>().await?;
 
@@ -195,7 +168,7 @@ impl<'a> UserRequest<'a> {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 struct User {
     id: String,
     display_name: String,
@@ -0,0 +1,445 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use actix_web::{HttpResponse, ResponseError};
+use reqwest::StatusCode;
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::app::models::base64url;
+use crate::app::models::dpop::{self, DpopError, DpopKey};
+use crate::app::models::random::{RandomString, RandomStringGenerator};
+
+#[derive(Debug, Error)]
+pub enum TokenError {
+    #[error("no stored token")]
+    NotFound,
+
+    #[error("access token expired and no refresh token is available")]
+    Expired,
+
+    #[error("refresh token was rejected: {0}")]
+    RefreshRejected(String),
+
+    #[error("token request failed")]
+    RequestFailed(#[from] reqwest::Error),
+
+    #[error("DPoP failed: {0}")]
+    DpopFailed(#[from] DpopError),
+}
+
+impl ResponseError for TokenError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::RequestFailed(_) | Self::DpopFailed(_) => StatusCode::BAD_GATEWAY,
+            _ => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).finish()
+    }
+}
+
+type Result<T> = std::result::Result<T, TokenError>;
+
+/// Successful token response from RFC 6749 section 5.1.
+#[derive(Debug, Deserialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: Option<String>,
+    pub expires_in: Option<u64>,
+    pub refresh_token: Option<String>,
+    pub scope: Option<String>,
+    pub id_token: Option<String>,
+}
+
+/// Confirmation claim from RFC 7800 section 3.1, binding a token to a key or certificate.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Confirmation {
+    /// Certificate thumbprint from RFC 8705 section 3.1.
+    #[serde(rename = "x5t#S256", skip_serializing_if = "Option::is_none")]
+    pub x5t_s256: Option<String>,
+}
+
+impl Confirmation {
+    /// The `cnf` claim of a JWT access token, or `None` for opaque tokens.
+    ///
+    /// The signature is not checked: this is only used on tokens received straight from the token endpoint.
+    pub fn from_access_token(access_token: &str) -> Option<Self> {
+        #[derive(Deserialize)]
+        struct Claims {
+            #[serde(default)]
+            cnf: Confirmation,
+        }
+
+        let payload = match access_token.split('.').collect::<Vec<_>>().as_slice() {
+            [_, payload, _] => base64url::decode(payload).ok()?,
+            _ => return None,
+        };
+        serde_json::from_slice::<Claims>(&payload).ok().map(|claims| claims.cnf)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct TokenSet {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub id_token: Option<String>,
+    pub expires_at: Option<SystemTime>,
+    /// Key the tokens are bound to when they were requested with DPoP.
+    pub dpop_key: Option<DpopKey>,
+}
+
+impl TokenSet {
+    pub fn new(response: TokenResponse) -> Self {
+        let expires_at = response.expires_in.map(|seconds| SystemTime::now() + Duration::from_secs(seconds));
+        Self {
+            access_token: response.access_token,
+            refresh_token: response.refresh_token,
+            id_token: response.id_token,
+            expires_at,
+            dpop_key: None,
+        }
+    }
+
+    pub fn with_dpop_key(self, dpop_key: Option<DpopKey>) -> Self {
+        Self { dpop_key, ..self }
+    }
+
+    pub fn expires_in(&self) -> Option<u64> {
+        self.expires_at
+            .map(|expires_at| expires_at.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO).as_secs())
+    }
+
+    fn expires_within(&self, margin: Duration) -> bool {
+        matches!(self.expires_at, Some(expires_at) if expires_at <= SystemTime::now() + margin)
+    }
+
+    fn update(&mut self, response: TokenResponse) {
+        let previous = std::mem::replace(self, Self::new(response));
+        // Providers that do not rotate refresh tokens omit refresh_token from the response
+        if self.refresh_token.is_none() {
+            self.refresh_token = previous.refresh_token;
+        }
+        if self.id_token.is_none() {
+            self.id_token = previous.id_token;
+        }
+        self.dpop_key = previous.dpop_key;
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccessTokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_in: Option<u64>,
+}
+
+impl From<&TokenSet> for AccessTokenResponse {
+    fn from(tokens: &TokenSet) -> Self {
+        Self {
+            access_token: tokens.access_token.to_owned(),
+            token_type: if tokens.dpop_key.is_some() { "DPoP" } else { "Bearer" }.to_owned(),
+            expires_in: tokens.expires_in(),
+        }
+    }
+}
+
+/// Refresh token grant (RFC 6749 section 6) for public clients that authenticate with `client_id` only.
+pub struct RefreshTokenRequest<'a> {
+    token_endpoint: &'a str,
+    client_id: &'a str,
+}
+
+impl<'a> RefreshTokenRequest<'a> {
+    pub fn new(token_endpoint: &'a str, client_id: &'a str) -> Self {
+        Self {
+            token_endpoint,
+            client_id,
+        }
+    }
+
+    /// Refreshes with a proof from `dpop_key` when the tokens are DPoP-bound, as RFC 9449 section 5 requires for public clients.
+    pub async fn execute(&self, refresh_token: &str, dpop_key: Option<&DpopKey>) -> Result<TokenResponse> {
+        let client = reqwest::Client::new();
+        let parameters = [
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+            ("client_id", self.client_id),
+        ];
+        let request = client.post(self.token_endpoint)
+            .header("Accept", "application/json")
+            .form(&parameters);
+        let response = dpop::send(dpop_key, &client, request, None).await?;
+
+        if response.status().is_client_error() {
+            let error = response.json::<ErrorResponse>().await?;
+            return Err(TokenError::RefreshRejected(error.error))
+        }
+        let result = response.error_for_status()?
+            .json::<TokenResponse>()
+            .await?;
+        if dpop_key.is_some() {
+            dpop::verify_token_type(&result)?;
+        }
+        Ok(result)
+    }
+}
+
+/// Server-side store of tokens, referenced from sessions by an opaque handle.
+///
+/// Entries expire `ttl` after they were last touched, which the session holding the handle does on every request,
+/// so they go away with the session even when nobody removes them.
+pub struct TokenStore {
+    entries: Mutex<HashMap<String, TokenEntry>>,
+    refresh_margin: Duration,
+    ttl: Duration,
+}
+
+struct TokenEntry {
+    tokens: Arc<tokio::sync::Mutex<TokenSet>>,
+    expires_at: Instant,
+}
+
+impl Default for TokenStore {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(60), Duration::from_secs(60 * 60))
+    }
+}
+
+impl TokenStore {
+    pub fn new(refresh_margin: Duration, ttl: Duration) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            refresh_margin,
+            ttl,
+        }
+    }
+
+    pub fn insert(&self, tokens: TokenSet) -> String {
+        let handle = RandomString::new().generate(32);
+        let entry = TokenEntry {
+            tokens: Arc::new(tokio::sync::Mutex::new(tokens)),
+            expires_at: Instant::now() + self.ttl,
+        };
+        self.entries.lock().unwrap().insert(handle.to_owned(), entry);
+        handle
+    }
+
+    pub async fn remove(&self, handle: &str) -> Option<TokenSet> {
+        let entry = self.entries.lock().unwrap().remove(handle)?;
+        let tokens = entry.tokens.lock().await;
+        Some(tokens.clone())
+    }
+
+    /// Drops the tokens without waiting for a refresh in progress, returning whether there were any.
+    pub fn discard(&self, handle: &str) -> bool {
+        self.entries.lock().unwrap().remove(handle).is_some()
+    }
+
+    /// Keeps the tokens for `ttl` from now, returning whether the handle is known.
+    pub fn touch(&self, handle: &str, ttl: Duration) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get_mut(handle) {
+            Some(entry) => {
+                entry.expires_at = entry.expires_at.max(Instant::now() + ttl);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Removes expired entries, returning how many were removed.
+    pub fn sweep(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        let now = Instant::now();
+        entries.retain(|_, entry| entry.expires_at > now);
+        before - entries.len()
+    }
+
+    /// Returns an access token that is valid for at least the refresh margin, refreshing it first if necessary.
+    pub async fn access_token(&self, handle: &str, refresh: &RefreshTokenRequest<'_>) -> Result<TokenSet> {
+        let entry = self.entries.lock().unwrap()
+            .get(handle)
+            .filter(|entry| entry.expires_at > Instant::now())
+            .map(|entry| entry.tokens.clone())
+            .ok_or(TokenError::NotFound)?;
+        // Holding the entry lock while refreshing keeps concurrent requests from spending a rotated refresh token twice
+        let mut tokens = entry.lock().await;
+        if !tokens.expires_within(self.refresh_margin) {
+            return Ok(tokens.clone())
+        }
+
+        let refresh_token = tokens.refresh_token.to_owned().ok_or(TokenError::Expired)?;
+        match refresh.execute(&refresh_token, tokens.dpop_key.as_ref()).await {
+            Ok(response) => {
+                tokens.update(response);
+                Ok(tokens.clone())
+            },
+            Err(TokenError::RefreshRejected(error)) => {
+                drop(tokens);
+                self.entries.lock().unwrap().remove(handle);
+                Err(TokenError::RefreshRejected(error))
+            },
+            Err(error) => Err(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::MockServer;
+    use httpmock::Method::POST;
+    use serde_json::json;
+    use super::*;
+
+    fn token_set(expires_in: u64) -> TokenSet {
+        TokenSet::new(TokenResponse {
+            access_token: "access-token-01".to_owned(),
+            token_type: Some("Bearer".to_owned()),
+            expires_in: Some(expires_in),
+            refresh_token: Some("refresh-token-01".to_owned()),
+            scope: None,
+            id_token: None,
+        })
+    }
+
+    #[actix_rt::test]
+    async fn test_access_token_not_expiring() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST).path("/token");
+            then.status(500);
+        });
+
+        let store = TokenStore::default();
+        let handle = store.insert(token_set(3600));
+        let token_endpoint = server.url("/token");
+        let refresh = RefreshTokenRequest::new(&token_endpoint, "client-01");
+
+        let tokens = store.access_token(&handle, &refresh).await.unwrap();
+        assert_eq!("access-token-01", tokens.access_token);
+        assert_eq!(0, mock.hits());
+    }
+
+    #[actix_rt::test]
+    async fn test_access_token_refresh_with_rotation() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/token")
+                .body_contains("grant_type=refresh_token")
+                .body_contains("refresh_token=refresh-token-01")
+                .body_contains("client_id=client-01");
+            then.status(200).json_body(json!({
+                "access_token": "access-token-02",
+                "token_type": "Bearer",
+                "expires_in": 3600,
+                "refresh_token": "refresh-token-02",
+            }));
+        });
+
+        let store = TokenStore::default();
+        let handle = store.insert(token_set(30));
+        let token_endpoint = server.url("/token");
+        let refresh = RefreshTokenRequest::new(&token_endpoint, "client-01");
+
+        let tokens = store.access_token(&handle, &refresh).await.unwrap();
+        assert_eq!("access-token-02", tokens.access_token);
+        assert_eq!(Some("refresh-token-02".to_owned()), tokens.refresh_token);
+
+        let tokens = store.access_token(&handle, &refresh).await.unwrap();
+        assert_eq!("access-token-02", tokens.access_token);
+        assert_eq!(1, mock.hits());
+    }
+
+    #[actix_rt::test]
+    async fn test_access_token_refresh_keeps_refresh_token() {
+        let server = MockServer::start();
+        let _mock = server.mock(|when, then| {
+            when.method(POST).path("/token");
+            then.status(200).json_body(json!({
+                "access_token": "access-token-02",
+                "token_type": "Bearer",
+                "expires_in": 3600,
+            }));
+        });
+
+        let store = TokenStore::default();
+        let handle = store.insert(token_set(0));
+        let token_endpoint = server.url("/token");
+        let refresh = RefreshTokenRequest::new(&token_endpoint, "client-01");
+
+        let tokens = store.access_token(&handle, &refresh).await.unwrap();
+        assert_eq!(Some("refresh-token-01".to_owned()), tokens.refresh_token);
+    }
+
+    #[actix_rt::test]
+    async fn test_access_token_refresh_with_dpop() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST).path("/token").header_exists("DPoP");
+            then.status(200).json_body(json!({
+                "access_token": "access-token-02",
+                "token_type": "Bearer",
+                "expires_in": 3600,
+            }));
+        });
+
+        let store = TokenStore::default();
+        let handle = store.insert(token_set(0).with_dpop_key(Some(DpopKey::generate().unwrap())));
+        let token_endpoint = server.url("/token");
+        let refresh = RefreshTokenRequest::new(&token_endpoint, "client-01");
+
+        // A bearer token in exchange for a DPoP-bound refresh token means the binding was dropped
+        let result = store.access_token(&handle, &refresh).await;
+        assert!(matches!(result, Err(TokenError::DpopFailed(DpopError::NotDpopBound(_)))));
+        assert_eq!(1, mock.hits());
+    }
+
+    #[actix_rt::test]
+    async fn test_access_token_refresh_rejected() {
+        let server = MockServer::start();
+        let _mock = server.mock(|when, then| {
+            when.method(POST).path("/token");
+            then.status(400).json_body(json!({ "error": "invalid_grant" }));
+        });
+
+        let store = TokenStore::default();
+        let handle = store.insert(token_set(0));
+        let token_endpoint = server.url("/token");
+        let refresh = RefreshTokenRequest::new(&token_endpoint, "client-01");
+
+        let result = store.access_token(&handle, &refresh).await;
+        assert!(matches!(result, Err(TokenError::RefreshRejected(error)) if error == "invalid_grant"));
+
+        let result = store.access_token(&handle, &refresh).await;
+        assert!(matches!(result, Err(TokenError::NotFound)));
+    }
+
+    #[actix_rt::test]
+    async fn test_expiry() {
+        let store = TokenStore::new(Duration::from_secs(60), Duration::ZERO);
+        let handle = store.insert(token_set(3600));
+        let refresh = RefreshTokenRequest::new("http://localhost/token", "client-01");
+        assert!(matches!(store.access_token(&handle, &refresh).await, Err(TokenError::NotFound)));
+
+        assert!(store.touch(&handle, Duration::from_secs(60)));
+        assert!(store.access_token(&handle, &refresh).await.is_ok());
+        let expired = store.insert(token_set(3600));
+        assert_eq!(1, store.sweep());
+        assert!(!store.touch(&expired, Duration::from_secs(60)));
+
+        assert!(store.discard(&handle));
+        assert!(!store.discard(&handle));
+    }
+}
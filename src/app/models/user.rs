@@ -0,0 +1,383 @@
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::{Connection, OptionalExtension, params};
+use serde_derive::Serialize;
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::app::models::provider::Identity;
+use crate::app::models::random::{RandomString, RandomStringGenerator};
+
+#[derive(Debug, Error)]
+pub enum UserStoreError {
+    #[error("user database error: {0}")]
+    Database(#[from] rusqlite::Error),
+
+    #[error("invalid stored claims: {0}")]
+    InvalidClaims(#[from] serde_json::Error),
+
+    #[error("{0} identity {1} is already linked to another user")]
+    AlreadyLinked(String, String),
+
+    #[error("user {0} not found")]
+    UserNotFound(String),
+
+    #[error("credential {0} is already registered")]
+    CredentialExists(String),
+}
+
+type Result<T> = std::result::Result<T, UserStoreError>;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS users (
+        id TEXT PRIMARY KEY,
+        created_at INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS identities (
+        provider TEXT NOT NULL,
+        subject TEXT NOT NULL,
+        user_id TEXT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+        claims TEXT NOT NULL,
+        linked_at INTEGER NOT NULL,
+        PRIMARY KEY (provider, subject)
+    );
+    CREATE INDEX IF NOT EXISTS identities_user_id ON identities (user_id);
+    CREATE TABLE IF NOT EXISTS credentials (
+        id TEXT PRIMARY KEY,
+        user_id TEXT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+        public_key BLOB NOT NULL,
+        sign_count INTEGER NOT NULL,
+        transports TEXT NOT NULL,
+        backup_eligible INTEGER NOT NULL,
+        backed_up INTEGER NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS credentials_user_id ON credentials (user_id);
+";
+
+/// Local account, stable across the external identities linked to it.
+#[derive(Debug, Serialize)]
+pub struct User {
+    pub id: String,
+    pub created_at: u64,
+    pub identities: Vec<LinkedIdentity>,
+    pub credentials: Vec<Credential>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LinkedIdentity {
+    pub provider: String,
+    pub subject: String,
+    /// Claims from the latest sign-in with this identity.
+    pub claims: Value,
+    pub linked_at: u64,
+}
+
+/// WebAuthn credential registered to a user.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Credential {
+    /// Credential ID, base64url encoded.
+    pub id: String,
+    /// COSE_Key as registered.
+    #[serde(skip)]
+    pub public_key: Vec<u8>,
+    pub sign_count: u32,
+    pub transports: Vec<String>,
+    pub backup_eligible: bool,
+    pub backed_up: bool,
+    pub created_at: u64,
+}
+
+/// Users and their linked identities, keyed by (provider, subject), in a SQLite database.
+pub struct UserStore {
+    connection: Mutex<Connection>,
+}
+
+impl UserStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(connection: Connection) -> Result<Self> {
+        connection.execute_batch("PRAGMA foreign_keys = ON;")?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Returns the user the identity is linked to, creating one on the first sign-in.
+    pub fn sign_in(&self, identity: &Identity) -> Result<String> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        let claims = serde_json::to_string(&identity.claims)?;
+        let now = now();
+
+        let user_id = match find_user_id(&transaction, identity)? {
+            Some(user_id) => {
+                transaction.execute(
+                    "UPDATE identities SET claims = ?3 WHERE provider = ?1 AND subject = ?2",
+                    params![identity.provider, identity.subject, claims],
+                )?;
+                user_id
+            },
+            None => {
+                let user_id = RandomString::new().generate(16);
+                transaction.execute("INSERT INTO users (id, created_at) VALUES (?1, ?2)", params![user_id, now])?;
+                insert_identity(&transaction, &user_id, identity, &claims, now)?;
+                user_id
+            },
+        };
+        transaction.commit()?;
+        Ok(user_id)
+    }
+
+    /// Links the identity to an existing user; an identity can only belong to one user.
+    pub fn link(&self, user_id: &str, identity: &Identity) -> Result<()> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        let claims = serde_json::to_string(&identity.claims)?;
+
+        match find_user_id(&transaction, identity)? {
+            Some(linked) if linked == user_id => {
+                transaction.execute(
+                    "UPDATE identities SET claims = ?3 WHERE provider = ?1 AND subject = ?2",
+                    params![identity.provider, identity.subject, claims],
+                )?;
+            },
+            Some(_) => return Err(UserStoreError::AlreadyLinked(identity.provider.to_owned(), identity.subject.to_owned())),
+            None => {
+                let exists = transaction.query_row("SELECT 1 FROM users WHERE id = ?1", [user_id], |_| Ok(()))
+                    .optional()?
+                    .is_some();
+                if !exists {
+                    return Err(UserStoreError::UserNotFound(user_id.to_owned()))
+                }
+                insert_identity(&transaction, user_id, identity, &claims, now())?;
+            },
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Registers a WebAuthn credential; a credential ID can only be registered once.
+    pub fn add_credential(&self, user_id: &str, credential: &Credential) -> Result<()> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        let exists = transaction.query_row("SELECT 1 FROM users WHERE id = ?1", [user_id], |_| Ok(()))
+            .optional()?
+            .is_some();
+        if !exists {
+            return Err(UserStoreError::UserNotFound(user_id.to_owned()))
+        }
+        let registered = transaction.query_row("SELECT 1 FROM credentials WHERE id = ?1", [&credential.id], |_| Ok(()))
+            .optional()?
+            .is_some();
+        if registered {
+            return Err(UserStoreError::CredentialExists(credential.id.to_owned()))
+        }
+        transaction.execute(
+            "INSERT INTO credentials (id, user_id, public_key, sign_count, transports, backup_eligible, backed_up, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                credential.id,
+                user_id,
+                credential.public_key,
+                credential.sign_count,
+                serde_json::to_string(&credential.transports)?,
+                credential.backup_eligible,
+                credential.backed_up,
+                credential.created_at,
+            ],
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    pub fn credentials(&self, user_id: &str) -> Result<Vec<Credential>> {
+        let connection = self.connection.lock().unwrap();
+        find_credentials(&connection, user_id)
+    }
+
+    /// The credential with its user, for signing in with it.
+    pub fn find_credential(&self, credential_id: &str) -> Result<Option<(String, Credential)>> {
+        let connection = self.connection.lock().unwrap();
+        let user_id = connection.query_row("SELECT user_id FROM credentials WHERE id = ?1", [credential_id], |row| row.get::<_, String>(0))
+            .optional()?;
+        let user_id = match user_id {
+            Some(user_id) => user_id,
+            None => return Ok(None),
+        };
+        let credential = find_credentials(&connection, &user_id)?.into_iter()
+            .find(|credential| credential.id == credential_id);
+        Ok(credential.map(|credential| (user_id, credential)))
+    }
+
+    /// Records the signature counter and backup state of the latest sign-in.
+    pub fn update_credential(&self, credential_id: &str, sign_count: u32, backed_up: bool) -> Result<()> {
+        self.connection.lock().unwrap().execute(
+            "UPDATE credentials SET sign_count = ?2, backed_up = ?3 WHERE id = ?1",
+            params![credential_id, sign_count, backed_up],
+        )?;
+        Ok(())
+    }
+
+    pub fn find(&self, user_id: &str) -> Result<Option<User>> {
+        let connection = self.connection.lock().unwrap();
+        let created_at = connection.query_row("SELECT created_at FROM users WHERE id = ?1", [user_id], |row| row.get::<_, u64>(0))
+            .optional()?;
+        let created_at = match created_at {
+            Some(created_at) => created_at,
+            None => return Ok(None),
+        };
+
+        let mut statement = connection.prepare(
+            "SELECT provider, subject, claims, linked_at FROM identities WHERE user_id = ?1 ORDER BY linked_at, provider",
+        )?;
+        let rows = statement.query_map([user_id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?, row.get::<_, u64>(3)?))
+        })?;
+        let mut identities = vec![];
+        for row in rows {
+            let (provider, subject, claims, linked_at) = row?;
+            identities.push(LinkedIdentity {
+                provider,
+                subject,
+                claims: serde_json::from_str(&claims)?,
+                linked_at,
+            });
+        }
+        Ok(Some(User {
+            id: user_id.to_owned(),
+            created_at,
+            identities,
+            credentials: find_credentials(&connection, user_id)?,
+        }))
+    }
+}
+
+fn find_user_id(connection: &Connection, identity: &Identity) -> Result<Option<String>> {
+    let user_id = connection.query_row(
+        "SELECT user_id FROM identities WHERE provider = ?1 AND subject = ?2",
+        params![identity.provider, identity.subject],
+        |row| row.get(0),
+    ).optional()?;
+    Ok(user_id)
+}
+
+fn find_credentials(connection: &Connection, user_id: &str) -> Result<Vec<Credential>> {
+    let mut statement = connection.prepare(
+        "SELECT id, public_key, sign_count, transports, backup_eligible, backed_up, created_at
+            FROM credentials WHERE user_id = ?1 ORDER BY created_at, id",
+    )?;
+    let rows = statement.query_map([user_id], |row| {
+        Ok((
+            Credential {
+                id: row.get(0)?,
+                public_key: row.get(1)?,
+                sign_count: row.get(2)?,
+                transports: vec![],
+                backup_eligible: row.get(4)?,
+                backed_up: row.get(5)?,
+                created_at: row.get(6)?,
+            },
+            row.get::<_, String>(3)?,
+        ))
+    })?;
+    let mut credentials = vec![];
+    for row in rows {
+        let (credential, transports) = row?;
+        credentials.push(Credential {
+            transports: serde_json::from_str(&transports)?,
+            ..credential
+        });
+    }
+    Ok(credentials)
+}
+
+fn insert_identity(connection: &Connection, user_id: &str, identity: &Identity, claims: &str, linked_at: u64) -> Result<()> {
+    connection.execute(
+        "INSERT INTO identities (provider, subject, user_id, claims, linked_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![identity.provider, identity.subject, user_id, claims, linked_at],
+    )?;
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    fn identity(provider: &str, subject: &str, name: &str) -> Identity {
+        Identity::new(provider, subject.to_owned(), &json!({ "name": name }))
+    }
+
+    #[test]
+    fn test_sign_in_creates_user_once() {
+        let store = UserStore::open_in_memory().unwrap();
+        let user_id = store.sign_in(&identity("github", "1001", "Alice")).unwrap();
+        assert_eq!(user_id, store.sign_in(&identity("github", "1001", "Alice Liddell")).unwrap());
+        assert_ne!(user_id, store.sign_in(&identity("google", "1001", "Bob")).unwrap());
+
+        let user = store.find(&user_id).unwrap().unwrap();
+        assert_eq!(1, user.identities.len());
+        assert_eq!("Alice Liddell", user.identities[0].claims["name"]);
+        assert!(store.find("unknown").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_link() {
+        let store = UserStore::open_in_memory().unwrap();
+        let alice = store.sign_in(&identity("github", "1001", "Alice")).unwrap();
+        store.link(&alice, &identity("google", "g-1001", "Alice")).unwrap();
+        assert_eq!(alice, store.sign_in(&identity("google", "g-1001", "Alice")).unwrap());
+
+        let providers: Vec<String> = store.find(&alice).unwrap().unwrap().identities.into_iter().map(|identity| identity.provider).collect();
+        assert_eq!(vec!["github", "google"], providers);
+
+        let bob = store.sign_in(&identity("spotify", "bob", "Bob")).unwrap();
+        let result = store.link(&bob, &identity("github", "1001", "Alice"));
+        assert!(matches!(result, Err(UserStoreError::AlreadyLinked(provider, _)) if provider == "github"));
+        let result = store.link("unknown", &identity("spotify", "carol", "Carol"));
+        assert!(matches!(result, Err(UserStoreError::UserNotFound(_))));
+    }
+
+    #[test]
+    fn test_credentials() {
+        let store = UserStore::open_in_memory().unwrap();
+        let alice = store.sign_in(&identity("github", "1001", "Alice")).unwrap();
+        let credential = Credential {
+            id: "credential-01".to_owned(),
+            public_key: vec![0xa1, 0x01, 0x02],
+            sign_count: 0,
+            transports: vec!["internal".to_owned(), "hybrid".to_owned()],
+            backup_eligible: true,
+            backed_up: true,
+            created_at: 1_600_000_000,
+        };
+        store.add_credential(&alice, &credential).unwrap();
+        assert_eq!(vec![credential], store.credentials(&alice).unwrap());
+        assert_eq!(1, store.find(&alice).unwrap().unwrap().credentials.len());
+
+        let bob = store.sign_in(&identity("spotify", "bob", "Bob")).unwrap();
+        let credential = store.credentials(&alice).unwrap().remove(0);
+        assert!(matches!(store.add_credential(&bob, &credential), Err(UserStoreError::CredentialExists(_))));
+        assert!(matches!(store.add_credential("unknown", &credential), Err(UserStoreError::UserNotFound(_))));
+
+        store.update_credential("credential-01", 5, false).unwrap();
+        let (user_id, credential) = store.find_credential("credential-01").unwrap().unwrap();
+        assert_eq!(alice, user_id);
+        assert_eq!((5, false), (credential.sign_count, credential.backed_up));
+        assert!(store.find_credential("unknown").unwrap().is_none());
+    }
+}
@@ -0,0 +1,7 @@
+pub mod authentication;
+pub mod authenticator_data;
+pub mod cbor;
+pub mod client_data;
+pub mod cose;
+pub mod options;
+pub mod registration;
@@ -0,0 +1,257 @@
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::app::config::WebauthnConfig;
+use crate::app::models::base64url;
+use crate::app::models::random::{RandomString, RandomStringGenerator};
+use crate::app::models::user::Credential;
+use crate::app::models::webauthn::authenticator_data::{AuthenticatorData, AuthenticatorDataError};
+use crate::app::models::webauthn::client_data::{self, ClientDataError, CollectedClientData};
+use crate::app::models::webauthn::cose::{CoseError, CoseKey};
+use crate::app::models::webauthn::options::{PUBLIC_KEY, PublicKeyCredentialRequestOptions};
+
+#[derive(Debug, Error)]
+pub enum AuthenticationError {
+    #[error("no sign-in in progress")]
+    CeremonyNotStarted,
+
+    #[error("invalid base64url: {0}")]
+    InvalidEncoding(#[from] base64::DecodeError),
+
+    #[error("credential type {0} is not public-key")]
+    InvalidCredentialType(String),
+
+    #[error("id does not match rawId")]
+    CredentialIdMismatch,
+
+    #[error("credential is not registered")]
+    UnknownCredential,
+
+    #[error("userHandle is missing")]
+    UserHandleMissing,
+
+    #[error("userHandle does not belong to the credential")]
+    UserHandleMismatch,
+
+    #[error("{0}")]
+    ClientData(#[from] ClientDataError),
+
+    #[error("{0}")]
+    AuthenticatorData(#[from] AuthenticatorDataError),
+
+    #[error("invalid stored public key or signature: {0}")]
+    Signature(#[from] CoseError),
+
+    #[error("signature counter went from {0} to {1}; the authenticator may be cloned")]
+    CounterRegression(u32, u32),
+}
+
+type Result<T> = std::result::Result<T, AuthenticationError>;
+
+/// Kept in the session between the request options and the assertion; taken out before verifying, so a challenge works once.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AuthenticationState {
+    pub challenge: String,
+}
+
+/// Request options for signing in with a discoverable credential, from WebAuthn section 7.2 steps 1 and 2.
+pub struct AuthenticationOptions<'a> {
+    config: &'a WebauthnConfig,
+}
+
+impl<'a> AuthenticationOptions<'a> {
+    pub fn new(config: &'a WebauthnConfig) -> Self {
+        Self {
+            config,
+        }
+    }
+
+    pub fn start(&self) -> (PublicKeyCredentialRequestOptions, AuthenticationState) {
+        let config = self.config;
+        let challenge = RandomString::new().generate(32);
+        let options = PublicKeyCredentialRequestOptions {
+            challenge: challenge.to_owned(),
+            timeout: config.timeout,
+            rp_id: config.rp_id.to_owned(),
+            allow_credentials: vec![],
+            user_verification: config.user_verification,
+        };
+        (options, AuthenticationState { challenge })
+    }
+}
+
+/// `AuthenticationResponseJSON` from WebAuthn section 5.1, as `PublicKeyCredential.toJSON()` produces it.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticationResponse {
+    pub id: String,
+    pub raw_id: String,
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    pub response: AssertionResponse,
+}
+
+impl AuthenticationResponse {
+    /// Credential ID in the form credentials are stored under.
+    pub fn credential_id(&self) -> Result<String> {
+        let raw_id = base64url::decode(&self.raw_id)?;
+        if base64url::decode(&self.id)? != raw_id {
+            return Err(AuthenticationError::CredentialIdMismatch)
+        }
+        Ok(base64url::encode(&raw_id))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    pub user_handle: Option<String>,
+}
+
+/// Counter and backup state to store after a successful sign-in.
+#[derive(Debug, PartialEq)]
+pub struct AssertionResult {
+    pub sign_count: u32,
+    pub backed_up: bool,
+}
+
+/// Verifies an assertion following WebAuthn section 7.2, against the stored credential it names.
+pub struct AuthenticationVerification<'a> {
+    config: &'a WebauthnConfig,
+    state: &'a AuthenticationState,
+}
+
+impl<'a> AuthenticationVerification<'a> {
+    pub fn new(config: &'a WebauthnConfig, state: &'a AuthenticationState) -> Self {
+        Self {
+            config,
+            state,
+        }
+    }
+
+    pub fn verify(&self, response: &AuthenticationResponse, user_id: &str, credential: &Credential) -> Result<AssertionResult> {
+        let config = self.config;
+        if response.credential_type != PUBLIC_KEY {
+            return Err(AuthenticationError::InvalidCredentialType(response.credential_type.to_owned()))
+        }
+        if response.credential_id()? != credential.id {
+            return Err(AuthenticationError::CredentialIdMismatch)
+        }
+        // Options leave allowCredentials empty, so the credential is discoverable and has to name its user (step 6)
+        let user_handle = response.response.user_handle.as_ref().ok_or(AuthenticationError::UserHandleMissing)?;
+        if base64url::decode(user_handle)? != user_id.as_bytes() {
+            return Err(AuthenticationError::UserHandleMismatch)
+        }
+
+        let client_data_json = base64url::decode(&response.response.client_data_json)?;
+        let client_data = CollectedClientData::parse(&client_data_json)?;
+        client_data.verify(client_data::GET, &self.state.challenge, &config.origins)?;
+
+        let raw_authenticator_data = base64url::decode(&response.response.authenticator_data)?;
+        let authenticator_data = AuthenticatorData::parse(&raw_authenticator_data)?;
+        authenticator_data.verify(&config.rp_id, config.user_verification)?;
+
+        let message = [raw_authenticator_data.as_slice(), &Sha256::digest(&client_data_json)].concat();
+        let signature = base64url::decode(&response.response.signature)?;
+        CoseKey::from_bytes(&credential.public_key)?.verify(&message, &signature)?;
+
+        // Authenticators without a counter always report 0; any other value has to grow
+        let sign_count = authenticator_data.sign_count;
+        if (sign_count != 0 || credential.sign_count != 0) && sign_count <= credential.sign_count {
+            return Err(AuthenticationError::CounterRegression(credential.sign_count, sign_count))
+        }
+
+        Ok(AssertionResult {
+            sign_count,
+            backed_up: authenticator_data.backed_up(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::rand::SystemRandom;
+    use ring::signature::{ECDSA_P256_SHA256_ASN1_SIGNING, EcdsaKeyPair, KeyPair};
+    use crate::app::models::webauthn::authenticator_data::{USER_PRESENT, USER_VERIFIED, tests::authenticator_data};
+    use crate::app::models::webauthn::options::UserVerification;
+    use crate::app::models::webauthn::registration::tests::es256_cose_key;
+    use super::*;
+
+    struct Authenticator {
+        key_pair: EcdsaKeyPair,
+    }
+
+    impl Authenticator {
+        fn new() -> Self {
+            let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &SystemRandom::new()).unwrap();
+            Self {
+                key_pair: EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref()).unwrap(),
+            }
+        }
+
+        fn credential(&self, sign_count: u32) -> Credential {
+            let point = self.key_pair.public_key().as_ref();
+            Credential {
+                id: base64url::encode(b"credential-01"),
+                public_key: es256_cose_key(&point[1..33], &point[33..]),
+                sign_count,
+                transports: vec![],
+                backup_eligible: false,
+                backed_up: false,
+                created_at: 0,
+            }
+        }
+
+        fn assert(&self, challenge: &str, flags: u8, sign_count: u32) -> AuthenticationResponse {
+            let client_data_json = format!(r#"{{"type":"webauthn.get","challenge":"{}","origin":"http://localhost:8080"}}"#, challenge);
+            let data = authenticator_data(flags, sign_count, None);
+            let message = [data.as_slice(), &Sha256::digest(client_data_json.as_bytes())].concat();
+            let signature = self.key_pair.sign(&SystemRandom::new(), &message).unwrap();
+            AuthenticationResponse {
+                id: base64url::encode(b"credential-01"),
+                raw_id: base64url::encode(b"credential-01"),
+                credential_type: PUBLIC_KEY.to_owned(),
+                response: AssertionResponse {
+                    client_data_json: base64url::encode(client_data_json.as_bytes()),
+                    authenticator_data: base64url::encode(&data),
+                    signature: base64url::encode(signature.as_ref()),
+                    user_handle: Some(base64url::encode(b"user-01")),
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let config = WebauthnConfig::default();
+        let (_, state) = AuthenticationOptions::new(&config).start();
+        let authenticator = Authenticator::new();
+        let verification = AuthenticationVerification::new(&config, &state);
+
+        let response = authenticator.assert(&state.challenge, USER_PRESENT | USER_VERIFIED, 5);
+        let result = verification.verify(&response, "user-01", &authenticator.credential(4)).unwrap();
+        assert_eq!(AssertionResult { sign_count: 5, backed_up: false }, result);
+        assert!(matches!(verification.verify(&response, "user-02", &authenticator.credential(4)), Err(AuthenticationError::UserHandleMismatch)));
+        let mut without_user_handle = authenticator.assert(&state.challenge, USER_PRESENT | USER_VERIFIED, 5);
+        without_user_handle.response.user_handle = None;
+        assert!(matches!(verification.verify(&without_user_handle, "user-01", &authenticator.credential(4)), Err(AuthenticationError::UserHandleMissing)));
+        assert!(matches!(verification.verify(&response, "user-01", &authenticator.credential(5)), Err(AuthenticationError::CounterRegression(5, 5))));
+        assert!(matches!(verification.verify(&response, "user-01", &Authenticator::new().credential(4)), Err(AuthenticationError::Signature(CoseError::InvalidSignature))));
+
+        let response = authenticator.assert("challenge-02", USER_PRESENT, 0);
+        assert!(matches!(verification.verify(&response, "user-01", &authenticator.credential(0)), Err(AuthenticationError::ClientData(_))));
+
+        // Counters stuck at 0 are fine
+        let response = authenticator.assert(&state.challenge, USER_PRESENT, 0);
+        assert!(verification.verify(&response, "user-01", &authenticator.credential(0)).is_ok());
+
+        let config = WebauthnConfig { user_verification: UserVerification::Required, ..WebauthnConfig::default() };
+        let result = AuthenticationVerification::new(&config, &state).verify(&response, "user-01", &authenticator.credential(0));
+        assert!(matches!(result, Err(AuthenticationError::AuthenticatorData(AuthenticatorDataError::UserNotVerified))));
+    }
+}
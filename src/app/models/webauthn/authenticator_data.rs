@@ -0,0 +1,189 @@
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::app::models::webauthn::cbor::{self, CborError, Value};
+use crate::app::models::webauthn::options::UserVerification;
+
+// Flags from WebAuthn section 6.1
+pub const USER_PRESENT: u8 = 0x01;
+pub const USER_VERIFIED: u8 = 0x04;
+pub const BACKUP_ELIGIBLE: u8 = 0x08;
+pub const BACKED_UP: u8 = 0x10;
+pub const ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+pub const EXTENSION_DATA: u8 = 0x80;
+
+#[derive(Debug, Error)]
+pub enum AuthenticatorDataError {
+    #[error("authenticator data ends unexpectedly")]
+    UnexpectedEnd,
+
+    #[error("{0} bytes left after the authenticator data")]
+    TrailingBytes(usize),
+
+    #[error("invalid CBOR in authenticator data: {0}")]
+    InvalidCbor(#[from] CborError),
+
+    #[error("rpIdHash does not match the RP ID")]
+    RpIdMismatch,
+
+    #[error("user is not present")]
+    UserNotPresent,
+
+    #[error("user is not verified")]
+    UserNotVerified,
+
+    #[error("credential is backed up but not backup eligible")]
+    InvalidBackupState,
+}
+
+type Result<T> = std::result::Result<T, AuthenticatorDataError>;
+
+/// Authenticator data from WebAuthn section 6.1.
+#[derive(Debug)]
+pub struct AuthenticatorData {
+    pub rp_id_hash: [u8; 32],
+    pub flags: u8,
+    pub sign_count: u32,
+    pub attested_credential: Option<AttestedCredentialData>,
+    pub extensions: Option<Value>,
+}
+
+/// Attested credential data from WebAuthn section 6.5.1.
+#[derive(Debug)]
+pub struct AttestedCredentialData {
+    pub aaguid: [u8; 16],
+    pub credential_id: Vec<u8>,
+    /// COSE_Key as the authenticator encoded it, kept verbatim for storage.
+    pub public_key: Vec<u8>,
+}
+
+impl AuthenticatorData {
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes };
+        let rp_id_hash = reader.take(32)?.try_into().unwrap();
+        let flags = reader.take(1)?[0];
+        let sign_count = u32::from_be_bytes(reader.take(4)?.try_into().unwrap());
+
+        let attested_credential = if flags & ATTESTED_CREDENTIAL_DATA != 0 {
+            let aaguid = reader.take(16)?.try_into().unwrap();
+            let length = u16::from_be_bytes(reader.take(2)?.try_into().unwrap());
+            let credential_id = reader.take(length as usize)?.to_vec();
+            let (_, key_length) = cbor::decode_prefix(reader.bytes)?;
+            let public_key = reader.take(key_length)?.to_vec();
+            Some(AttestedCredentialData { aaguid, credential_id, public_key })
+        } else {
+            None
+        };
+        let extensions = if flags & EXTENSION_DATA != 0 {
+            let (extensions, length) = cbor::decode_prefix(reader.bytes)?;
+            reader.take(length)?;
+            Some(extensions)
+        } else {
+            None
+        };
+        if !reader.bytes.is_empty() {
+            return Err(AuthenticatorDataError::TrailingBytes(reader.bytes.len()))
+        }
+
+        Ok(Self {
+            rp_id_hash,
+            flags,
+            sign_count,
+            attested_credential,
+            extensions,
+        })
+    }
+
+    pub fn user_present(&self) -> bool {
+        self.flags & USER_PRESENT != 0
+    }
+
+    pub fn user_verified(&self) -> bool {
+        self.flags & USER_VERIFIED != 0
+    }
+
+    pub fn backup_eligible(&self) -> bool {
+        self.flags & BACKUP_ELIGIBLE != 0
+    }
+
+    pub fn backed_up(&self) -> bool {
+        self.flags & BACKED_UP != 0
+    }
+
+    /// Checks the RP ID hash and the flags both ceremonies share, from WebAuthn section 7.1 steps 13 to 16.
+    pub fn verify(&self, rp_id: &str, user_verification: UserVerification) -> Result<()> {
+        if self.rp_id_hash[..] != Sha256::digest(rp_id.as_bytes())[..] {
+            return Err(AuthenticatorDataError::RpIdMismatch)
+        }
+        if !self.user_present() {
+            return Err(AuthenticatorDataError::UserNotPresent)
+        }
+        if user_verification == UserVerification::Required && !self.user_verified() {
+            return Err(AuthenticatorDataError::UserNotVerified)
+        }
+        if self.backed_up() && !self.backup_eligible() {
+            return Err(AuthenticatorDataError::InvalidBackupState)
+        }
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < length {
+            return Err(AuthenticatorDataError::UnexpectedEnd)
+        }
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Authenticator data for `localhost` with the given flags, sign count and attested credential.
+    pub fn authenticator_data(flags: u8, sign_count: u32, credential: Option<(&[u8], &[u8])>) -> Vec<u8> {
+        let mut bytes = Sha256::digest(b"localhost").to_vec();
+        bytes.push(flags);
+        bytes.extend_from_slice(&sign_count.to_be_bytes());
+        if let Some((credential_id, public_key)) = credential {
+            bytes.extend_from_slice(&[0; 16]);
+            bytes.extend_from_slice(&(credential_id.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(credential_id);
+            bytes.extend_from_slice(public_key);
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_parse() {
+        // COSE key stand-in: any single CBOR item, here {1: 2}
+        let public_key = [0xa1, 0x01, 0x02];
+        let flags = USER_PRESENT | ATTESTED_CREDENTIAL_DATA;
+        let bytes = authenticator_data(flags, 7, Some((b"credential-01", &public_key)));
+        let data = AuthenticatorData::parse(&bytes).unwrap();
+        assert_eq!(7, data.sign_count);
+        let credential = data.attested_credential.as_ref().unwrap();
+        assert_eq!(b"credential-01".to_vec(), credential.credential_id);
+        assert_eq!(public_key.to_vec(), credential.public_key);
+        assert!(data.verify("localhost", UserVerification::Preferred).is_ok());
+        assert!(matches!(data.verify("example.com", UserVerification::Preferred), Err(AuthenticatorDataError::RpIdMismatch)));
+        assert!(matches!(data.verify("localhost", UserVerification::Required), Err(AuthenticatorDataError::UserNotVerified)));
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(AuthenticatorData::parse(&trailing), Err(AuthenticatorDataError::TrailingBytes(1))));
+        assert!(matches!(AuthenticatorData::parse(&bytes[..40]), Err(AuthenticatorDataError::UnexpectedEnd)));
+
+        let data = AuthenticatorData::parse(&authenticator_data(BACKED_UP, 0, None)).unwrap();
+        assert!(matches!(data.verify("localhost", UserVerification::Discouraged), Err(AuthenticatorDataError::UserNotPresent)));
+        let data = AuthenticatorData::parse(&authenticator_data(USER_PRESENT | BACKED_UP, 0, None)).unwrap();
+        assert!(matches!(data.verify("localhost", UserVerification::Discouraged), Err(AuthenticatorDataError::InvalidBackupState)));
+    }
+}
@@ -0,0 +1,374 @@
+use std::cmp::Ordering;
+
+use thiserror::Error;
+
+/// Nesting allowed before decoding gives up; WebAuthn structures are a few levels deep at most.
+const MAX_DEPTH: usize = 16;
+
+/// Most items in one array or map; WebAuthn structures hold a handful, certificate chains a few more.
+const MAX_ITEMS: usize = 256;
+
+/// Largest input accepted; attestation objects with certificate chains stay well below this.
+const MAX_INPUT_LENGTH: usize = 64 * 1024;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum CborError {
+    #[error("CBOR input ends unexpectedly")]
+    UnexpectedEnd,
+
+    #[error("{0} bytes left after the CBOR item")]
+    TrailingBytes(usize),
+
+    #[error("CBOR item with major type {0} and additional information {1} is not supported")]
+    Unsupported(u8, u8),
+
+    #[error("indefinite-length CBOR items are not allowed")]
+    IndefiniteLength,
+
+    #[error("CBOR text is not UTF-8")]
+    InvalidUtf8,
+
+    #[error("CBOR items are nested too deeply")]
+    TooDeep,
+
+    #[error("CBOR input of {0} bytes is too long")]
+    TooLong(usize),
+
+    #[error("CBOR argument is not in its shortest form")]
+    NonCanonical,
+
+    #[error("CBOR map has a duplicate key")]
+    DuplicateKey,
+
+    #[error("CBOR map keys are not in canonical order")]
+    UnsortedKeys,
+
+    #[error("CBOR array or map with {0} items is too long")]
+    TooManyItems(usize),
+}
+
+type Result<T> = std::result::Result<T, CborError>;
+
+/// Data items of RFC 8949 that WebAuthn uses; tags and floating point numbers are rejected.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Integer(i128),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+    Bool(bool),
+    Null,
+}
+
+impl Value {
+    pub fn as_integer(&self) -> Option<i128> {
+        match self {
+            Self::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::Bytes(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            Self::Text(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_map(&self) -> Option<&[(Value, Value)]> {
+        match self {
+            Self::Map(entries) => Some(entries),
+            _ => None,
+        }
+    }
+
+    /// Value of a map entry with a text key, as attestation objects use.
+    pub fn get_text(&self, key: &str) -> Option<&Value> {
+        self.as_map()?.iter()
+            .find(|(entry_key, _)| entry_key.as_text() == Some(key))
+            .map(|(_, value)| value)
+    }
+
+    /// Value of a map entry with an integer key, as COSE keys use.
+    pub fn get_integer(&self, key: i128) -> Option<&Value> {
+        self.as_map()?.iter()
+            .find(|(entry_key, _)| entry_key.as_integer() == Some(key))
+            .map(|(_, value)| value)
+    }
+}
+
+/// Decodes a single item that has to span the whole input.
+pub fn decode(input: &[u8]) -> Result<Value> {
+    let (value, length) = decode_prefix(input)?;
+    if length != input.len() {
+        return Err(CborError::TrailingBytes(input.len() - length))
+    }
+    Ok(value)
+}
+
+/// Decodes the item at the start of the input, returning it with its encoded length.
+///
+/// Follows the CTAP2 canonical form: shortest encoding, definite lengths and map keys sorted by their
+/// encoding, length first, so a duplicate key shows up as equal to the one before it.
+pub fn decode_prefix(input: &[u8]) -> Result<(Value, usize)> {
+    if input.len() > MAX_INPUT_LENGTH {
+        return Err(CborError::TooLong(input.len()))
+    }
+    let mut decoder = Decoder {
+        input,
+        position: 0,
+    };
+    let value = decoder.item(0)?;
+    Ok((value, decoder.position))
+}
+
+struct Decoder<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn item(&mut self, depth: usize) -> Result<Value> {
+        if depth > MAX_DEPTH {
+            return Err(CborError::TooDeep)
+        }
+        let initial = self.take(1)?[0];
+        let (major, info) = (initial >> 5, initial & 0x1f);
+        match major {
+            0 => Ok(Value::Integer(self.argument(major, info)? as i128)),
+            1 => Ok(Value::Integer(-1 - self.argument(major, info)? as i128)),
+            2 => {
+                let length = self.length(major, info)?;
+                Ok(Value::Bytes(self.take(length)?.to_vec()))
+            },
+            3 => {
+                let length = self.length(major, info)?;
+                let text = std::str::from_utf8(self.take(length)?).map_err(|_| CborError::InvalidUtf8)?;
+                Ok(Value::Text(text.to_owned()))
+            },
+            4 => {
+                let count = self.count(major, info)?;
+                let mut items = vec![];
+                for _ in 0..count {
+                    items.push(self.item(depth + 1)?);
+                }
+                Ok(Value::Array(items))
+            },
+            5 => {
+                let count = self.count(major, info)?;
+                let mut entries = vec![];
+                let mut previous_key: Option<&[u8]> = None;
+                for _ in 0..count {
+                    let start = self.position;
+                    let key = self.item(depth + 1)?;
+                    let encoded_key = &self.input[start..self.position];
+                    if let Some(previous_key) = previous_key {
+                        match compare_keys(previous_key, encoded_key) {
+                            Ordering::Less => {},
+                            Ordering::Equal => return Err(CborError::DuplicateKey),
+                            Ordering::Greater => return Err(CborError::UnsortedKeys),
+                        }
+                    }
+                    previous_key = Some(encoded_key);
+                    let value = self.item(depth + 1)?;
+                    entries.push((key, value));
+                }
+                Ok(Value::Map(entries))
+            },
+            7 => match info {
+                20 => Ok(Value::Bool(false)),
+                21 => Ok(Value::Bool(true)),
+                22 => Ok(Value::Null),
+                _ => Err(CborError::Unsupported(major, info)),
+            },
+            _ => Err(CborError::Unsupported(major, info)),
+        }
+    }
+
+    fn argument(&mut self, major: u8, info: u8) -> Result<u64> {
+        let value = match info {
+            0..=23 => info as u64,
+            24 => self.take(1)?[0] as u64,
+            25 => u16::from_be_bytes(self.take(2)?.try_into().unwrap()) as u64,
+            26 => u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as u64,
+            27 => u64::from_be_bytes(self.take(8)?.try_into().unwrap()),
+            31 => return Err(CborError::IndefiniteLength),
+            _ => return Err(CborError::Unsupported(major, info)),
+        };
+        if info > 23 && info != argument_info(value) {
+            return Err(CborError::NonCanonical)
+        }
+        Ok(value)
+    }
+
+    /// Length of a string or item count of a container; every byte or item takes at least one input byte,
+    /// so anything beyond the remaining input is truncated rather than allocated.
+    fn length(&mut self, major: u8, info: u8) -> Result<usize> {
+        let length = self.argument(major, info)?;
+        let remaining = self.input.len() - self.position;
+        match usize::try_from(length) {
+            Ok(length) if length <= remaining => Ok(length),
+            _ => Err(CborError::UnexpectedEnd),
+        }
+    }
+
+    fn count(&mut self, major: u8, info: u8) -> Result<usize> {
+        let count = self.length(major, info)?;
+        if count > MAX_ITEMS {
+            return Err(CborError::TooManyItems(count))
+        }
+        Ok(count)
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        let end = self.position.checked_add(length).ok_or(CborError::UnexpectedEnd)?;
+        let bytes = self.input.get(self.position..end).ok_or(CborError::UnexpectedEnd)?;
+        self.position = end;
+        Ok(bytes)
+    }
+}
+
+/// Encodes an item in the CTAP2 canonical form, with map keys sorted by their encoding.
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut output = vec![];
+    encode_into(value, &mut output);
+    output
+}
+
+fn encode_into(value: &Value, output: &mut Vec<u8>) {
+    match value {
+        Value::Integer(value) if *value >= 0 => encode_head(0, *value as u64, output),
+        Value::Integer(value) => encode_head(1, (-1 - *value) as u64, output),
+        Value::Bytes(bytes) => {
+            encode_head(2, bytes.len() as u64, output);
+            output.extend_from_slice(bytes);
+        },
+        Value::Text(text) => {
+            encode_head(3, text.len() as u64, output);
+            output.extend_from_slice(text.as_bytes());
+        },
+        Value::Array(items) => {
+            encode_head(4, items.len() as u64, output);
+            items.iter().for_each(|item| encode_into(item, output));
+        },
+        Value::Map(entries) => {
+            let mut entries: Vec<_> = entries.iter()
+                .map(|(key, value)| (encode(key), value))
+                .collect();
+            entries.sort_by(|(a, _), (b, _)| compare_keys(a, b));
+            encode_head(5, entries.len() as u64, output);
+            for (key, value) in entries {
+                output.extend_from_slice(&key);
+                encode_into(value, output);
+            }
+        },
+        Value::Bool(false) => output.push(0xf4),
+        Value::Bool(true) => output.push(0xf5),
+        Value::Null => output.push(0xf6),
+    }
+}
+
+/// Canonical order of encoded map keys: shorter keys first, then bytewise.
+fn compare_keys(a: &[u8], b: &[u8]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+fn encode_head(major: u8, argument: u64, output: &mut Vec<u8>) {
+    let info = argument_info(argument);
+    output.push(major << 5 | info);
+    match info {
+        24 => output.push(argument as u8),
+        25 => output.extend_from_slice(&(argument as u16).to_be_bytes()),
+        26 => output.extend_from_slice(&(argument as u32).to_be_bytes()),
+        27 => output.extend_from_slice(&argument.to_be_bytes()),
+        _ => {},
+    }
+}
+
+/// Additional information of the shortest head for an argument, from RFC 8949 section 4.2.1.
+fn argument_info(argument: u64) -> u8 {
+    match argument {
+        0..=23 => argument as u8,
+        24..=0xff => 24,
+        0x100..=0xffff => 25,
+        0x1_0000..=0xffff_ffff => 26,
+        _ => 27,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        // {"n": [1, -2, h'0102', true, null], "fmt": "none", "attStmt": {}}
+        let input = [
+            0xa3,
+            0x61, b'n', 0x85, 0x01, 0x21, 0x42, 0x01, 0x02, 0xf5, 0xf6,
+            0x63, b'f', b'm', b't', 0x64, b'n', b'o', b'n', b'e',
+            0x67, b'a', b't', b't', b'S', b't', b'm', b't', 0xa0,
+        ];
+        let value = decode(&input).unwrap();
+        assert_eq!(Some("none"), value.get_text("fmt").and_then(Value::as_text));
+        assert_eq!(Some(&Value::Map(vec![])), value.get_text("attStmt"));
+        let expected = Value::Array(vec![
+            Value::Integer(1),
+            Value::Integer(-2),
+            Value::Bytes(vec![1, 2]),
+            Value::Bool(true),
+            Value::Null,
+        ]);
+        assert_eq!(Some(&expected), value.get_text("n"));
+
+        assert_eq!(Ok((Value::Integer(500), 3)), decode_prefix(&[0x19, 0x01, 0xf4, 0xff]));
+        assert_eq!(Err(CborError::TrailingBytes(1)), decode(&[0x19, 0x01, 0xf4, 0xff]));
+    }
+
+    #[test]
+    fn test_decode_rejects_malformed_input() {
+        assert_eq!(Err(CborError::UnexpectedEnd), decode(&[0x44, 0x01, 0x02]));
+        // A huge declared length must not be trusted
+        assert_eq!(Err(CborError::UnexpectedEnd), decode(&[0x9b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]));
+        assert_eq!(Err(CborError::IndefiniteLength), decode(&[0x5f, 0x41, 0x00, 0xff]));
+        assert_eq!(Err(CborError::InvalidUtf8), decode(&[0x62, 0xc3, 0x28]));
+        assert_eq!(Err(CborError::Unsupported(6, 0)), decode(&[0xc0, 0x00]));
+        assert_eq!(Err(CborError::TooDeep), decode(&[0x81; 32]));
+        assert_eq!(Err(CborError::TooLong(MAX_INPUT_LENGTH + 1)), decode(&vec![0x00; MAX_INPUT_LENGTH + 1]));
+        // 1 in two bytes, and a map with the key 1 twice
+        assert_eq!(Err(CborError::NonCanonical), decode(&[0x18, 0x01]));
+        assert_eq!(Err(CborError::DuplicateKey), decode(&[0xa2, 0x01, 0x00, 0x01, 0x00]));
+        // {3: 0, 1: 0}, and {-1: 0, 24: 0} where the key 24 takes two bytes
+        assert_eq!(Err(CborError::UnsortedKeys), decode(&[0xa2, 0x03, 0x00, 0x01, 0x00]));
+        assert_eq!(Err(CborError::UnsortedKeys), decode(&[0xa2, 0x18, 0x18, 0x00, 0x20, 0x00]));
+        assert!(decode(&[0xa2, 0x20, 0x00, 0x18, 0x18, 0x00]).is_ok());
+        let mut input = vec![0x99, 0x01, 0x01];
+        input.extend_from_slice(&[0x00; MAX_ITEMS + 1]);
+        assert_eq!(Err(CborError::TooManyItems(MAX_ITEMS + 1)), decode(&input));
+    }
+
+    #[test]
+    fn test_encode() {
+        let value = Value::Map(vec![
+            (Value::Integer(-2), Value::Bytes(vec![0; 32])),
+            (Value::Integer(3), Value::Integer(-7)),
+            (Value::Integer(1), Value::Integer(2)),
+            (Value::Text("n".to_owned()), Value::Array(vec![Value::Integer(500), Value::Bool(true), Value::Null])),
+        ]);
+        let encoded = encode(&value);
+        assert_eq!([0xa4, 0x01, 0x02, 0x03, 0x26, 0x21, 0x58, 0x20], encoded[..8]);
+        assert_eq!([0x61, b'n', 0x83, 0x19, 0x01, 0xf4, 0xf5, 0xf6], encoded[40..]);
+
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(Some(&Value::Integer(-7)), decoded.get_integer(3));
+        assert_eq!(encoded, encode(&decoded));
+    }
+}
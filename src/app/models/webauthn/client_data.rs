@@ -0,0 +1,81 @@
+use serde_derive::Deserialize;
+use thiserror::Error;
+
+pub const CREATE: &str = "webauthn.create";
+pub const GET: &str = "webauthn.get";
+
+#[derive(Debug, Error)]
+pub enum ClientDataError {
+    #[error("invalid clientDataJSON: {0}")]
+    InvalidJson(#[from] serde_json::Error),
+
+    #[error("clientDataJSON has type {0}")]
+    TypeMismatch(String),
+
+    #[error("challenge mismatch")]
+    ChallengeMismatch,
+
+    #[error("origin {0} is not allowed")]
+    OriginNotAllowed(String),
+
+    #[error("cross-origin ceremonies are not allowed")]
+    CrossOrigin,
+}
+
+type Result<T> = std::result::Result<T, ClientDataError>;
+
+/// `CollectedClientData` from WebAuthn section 5.8.1.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectedClientData {
+    #[serde(rename = "type")]
+    pub ceremony_type: String,
+    pub challenge: String,
+    pub origin: String,
+    pub cross_origin: Option<bool>,
+}
+
+impl CollectedClientData {
+    pub fn parse(json: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice(json)?)
+    }
+
+    /// Checks type, challenge and origin, as in WebAuthn section 7.1 steps 7 to 9 and section 7.2 steps 11 to 13.
+    pub fn verify(&self, ceremony_type: &str, challenge: &str, origins: &[String]) -> Result<()> {
+        if self.ceremony_type != ceremony_type {
+            return Err(ClientDataError::TypeMismatch(self.ceremony_type.to_owned()))
+        }
+        if self.challenge != challenge {
+            return Err(ClientDataError::ChallengeMismatch)
+        }
+        if !origins.iter().any(|origin| origin == &self.origin) {
+            return Err(ClientDataError::OriginNotAllowed(self.origin.to_owned()))
+        }
+        // Ceremonies from iframes on other sites are never expected
+        if self.cross_origin == Some(true) {
+            return Err(ClientDataError::CrossOrigin)
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let origins = vec!["http://localhost:8080".to_owned()];
+        let json = br#"{"type":"webauthn.create","challenge":"challenge-01","origin":"http://localhost:8080","crossOrigin":false}"#;
+        let client_data = CollectedClientData::parse(json).unwrap();
+        assert!(client_data.verify(CREATE, "challenge-01", &origins).is_ok());
+        assert!(matches!(client_data.verify(GET, "challenge-01", &origins), Err(ClientDataError::TypeMismatch(_))));
+        assert!(matches!(client_data.verify(CREATE, "challenge-02", &origins), Err(ClientDataError::ChallengeMismatch)));
+        let other = vec!["https://rp.example.com".to_owned()];
+        assert!(matches!(client_data.verify(CREATE, "challenge-01", &other), Err(ClientDataError::OriginNotAllowed(_))));
+
+        let json = br#"{"type":"webauthn.create","challenge":"challenge-01","origin":"http://localhost:8080","crossOrigin":true}"#;
+        let client_data = CollectedClientData::parse(json).unwrap();
+        assert!(matches!(client_data.verify(CREATE, "challenge-01", &origins), Err(ClientDataError::CrossOrigin)));
+    }
+}
@@ -0,0 +1,323 @@
+use jsonwebtoken::Algorithm;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use thiserror::Error;
+
+use crate::app::models::base64url;
+use crate::app::models::oidc::discovery::{EdwardsCurve, EllipticCurve, JsonWebKey, KeyParameters};
+use crate::app::models::webauthn::cbor::{self, CborError, Value};
+
+#[derive(Debug, Error)]
+pub enum CoseError {
+    #[error("invalid COSE key encoding: {0}")]
+    InvalidCbor(#[from] CborError),
+
+    #[error("COSE key is not a map")]
+    NotAMap,
+
+    #[error("COSE key parameter {0} is missing or has the wrong type")]
+    InvalidParameter(i128),
+
+    #[error("COSE key type {0} is not supported")]
+    UnsupportedKeyType(i128),
+
+    #[error("COSE algorithm {0} is not supported")]
+    UnsupportedAlgorithm(i128),
+
+    #[error("COSE curve {0} is not supported")]
+    UnsupportedCurve(i128),
+
+    #[error("COSE key does not fit algorithm {0:?}")]
+    AlgorithmKeyMismatch(CoseAlgorithm),
+
+    #[error("signature verification failed")]
+    InvalidSignature,
+
+    #[error("invalid JWK parameter: {0}")]
+    InvalidJwkParameter(#[from] base64::DecodeError),
+
+    #[error("JWK has no algorithm that COSE keys support")]
+    UnsupportedJwk,
+}
+
+type Result<T> = std::result::Result<T, CoseError>;
+
+// Key parameters from RFC 9052 section 7.1 and RFC 9053 section 7
+const KEY_TYPE: i128 = 1;
+const ALGORITHM: i128 = 3;
+const CURVE: i128 = -1;
+const X: i128 = -2;
+const Y: i128 = -3;
+const RSA_N: i128 = -1;
+const RSA_E: i128 = -2;
+
+const KEY_TYPE_OKP: i128 = 1;
+const KEY_TYPE_EC2: i128 = 2;
+const KEY_TYPE_RSA: i128 = 3;
+
+const CURVE_P256: i128 = 1;
+const CURVE_P384: i128 = 2;
+const CURVE_ED25519: i128 = 6;
+
+/// Signature algorithms accepted for credentials, by their IANA COSE identifiers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CoseAlgorithm {
+    Es256,
+    EdDsa,
+    Es384,
+    Rs256,
+}
+
+impl CoseAlgorithm {
+    /// In order of preference, as offered in `pubKeyCredParams`.
+    pub const SUPPORTED: [Self; 4] = [Self::Es256, Self::EdDsa, Self::Es384, Self::Rs256];
+
+    pub fn id(self) -> i64 {
+        match self {
+            Self::Es256 => -7,
+            Self::EdDsa => -8,
+            Self::Es384 => -35,
+            Self::Rs256 => -257,
+        }
+    }
+
+    pub fn from_id(id: i128) -> Option<Self> {
+        Self::SUPPORTED.into_iter().find(|algorithm| algorithm.id() as i128 == id)
+    }
+
+    /// The same algorithm as JWA names it, per RFC 9053 sections 2.1 and 2.2 and RFC 8812 section 2.
+    pub fn jws_algorithm(self) -> Algorithm {
+        match self {
+            Self::Es256 => Algorithm::ES256,
+            Self::EdDsa => Algorithm::EdDSA,
+            Self::Es384 => Algorithm::ES384,
+            Self::Rs256 => Algorithm::RS256,
+        }
+    }
+
+    pub fn from_jws_algorithm(algorithm: Algorithm) -> Option<Self> {
+        Self::SUPPORTED.into_iter().find(|supported| supported.jws_algorithm() == algorithm)
+    }
+}
+
+/// Credential public key from the attested credential data, as in WebAuthn section 6.5.1.1.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CoseKey {
+    Ec2 { alg: CoseAlgorithm, crv: EllipticCurve, x: Vec<u8>, y: Vec<u8> },
+    Okp { alg: CoseAlgorithm, crv: EdwardsCurve, x: Vec<u8> },
+    Rsa { alg: CoseAlgorithm, n: Vec<u8>, e: Vec<u8> },
+}
+
+impl CoseKey {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::from_value(&cbor::decode(bytes)?)
+    }
+
+    pub fn from_value(value: &Value) -> Result<Self> {
+        if value.as_map().is_none() {
+            return Err(CoseError::NotAMap)
+        }
+        let integer = |label| value.get_integer(label).and_then(Value::as_integer).ok_or(CoseError::InvalidParameter(label));
+        let bytes = |label| value.get_integer(label).and_then(Value::as_bytes).map(<[u8]>::to_vec).ok_or(CoseError::InvalidParameter(label));
+
+        // WebAuthn requires alg on credential public keys
+        let alg_id = integer(ALGORITHM)?;
+        let alg = CoseAlgorithm::from_id(alg_id).ok_or(CoseError::UnsupportedAlgorithm(alg_id))?;
+        let key = match integer(KEY_TYPE)? {
+            KEY_TYPE_EC2 => {
+                let crv = match integer(CURVE)? {
+                    CURVE_P256 => EllipticCurve::P256,
+                    CURVE_P384 => EllipticCurve::P384,
+                    crv => return Err(CoseError::UnsupportedCurve(crv)),
+                };
+                Self::Ec2 { alg, crv, x: bytes(X)?, y: bytes(Y)? }
+            },
+            KEY_TYPE_OKP => {
+                let crv = match integer(CURVE)? {
+                    CURVE_ED25519 => EdwardsCurve::Ed25519,
+                    crv => return Err(CoseError::UnsupportedCurve(crv)),
+                };
+                Self::Okp { alg, crv, x: bytes(X)? }
+            },
+            KEY_TYPE_RSA => Self::Rsa { alg, n: bytes(RSA_N)?, e: bytes(RSA_E)? },
+            kty => return Err(CoseError::UnsupportedKeyType(kty)),
+        };
+        key.check_algorithm()?;
+        Ok(key)
+    }
+
+    /// Converts a signing JWK, taking the first algorithm it allows that COSE keys support; RSA keys
+    /// without "alg" become RS256.
+    pub fn from_jwk(jwk: &JsonWebKey) -> Result<Self> {
+        let alg = jwk.algorithms().into_iter()
+            .find_map(CoseAlgorithm::from_jws_algorithm)
+            .ok_or(CoseError::UnsupportedJwk)?;
+        let key = match &jwk.key {
+            KeyParameters::Ec { crv, x, y } => Self::Ec2 { alg, crv: *crv, x: base64url::decode(x)?, y: base64url::decode(y)? },
+            KeyParameters::Okp { crv, x } => Self::Okp { alg, crv: *crv, x: base64url::decode(x)? },
+            KeyParameters::Rsa { n, e } => Self::Rsa { alg, n: base64url::decode(n)?, e: base64url::decode(e)? },
+        };
+        key.check_algorithm()?;
+        Ok(key)
+    }
+
+    /// The key as a JWK with "alg" set, so it works with `JsonWebKey::decoding_key()`.
+    pub fn to_jwk(&self) -> JsonWebKey {
+        let key = match self {
+            Self::Ec2 { crv, x, y, .. } => KeyParameters::Ec { crv: *crv, x: base64url::encode(x), y: base64url::encode(y) },
+            Self::Okp { crv, x, .. } => KeyParameters::Okp { crv: *crv, x: base64url::encode(x) },
+            Self::Rsa { n, e, .. } => KeyParameters::Rsa { n: base64url::encode(n), e: base64url::encode(e) },
+        };
+        JsonWebKey {
+            kid: None,
+            alg: Some(format!("{:?}", self.algorithm().jws_algorithm())),
+            key_use: Some("sig".to_owned()),
+            key_ops: None,
+            x5c: None,
+            key,
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        let alg = (Value::Integer(ALGORITHM), Value::Integer(self.algorithm().id() as i128));
+        let entries = match self {
+            Self::Ec2 { crv, x, y, .. } => {
+                let crv = match crv {
+                    EllipticCurve::P256 => CURVE_P256,
+                    EllipticCurve::P384 => CURVE_P384,
+                };
+                vec![
+                    (Value::Integer(KEY_TYPE), Value::Integer(KEY_TYPE_EC2)),
+                    alg,
+                    (Value::Integer(CURVE), Value::Integer(crv)),
+                    (Value::Integer(X), Value::Bytes(x.to_vec())),
+                    (Value::Integer(Y), Value::Bytes(y.to_vec())),
+                ]
+            },
+            Self::Okp { crv: EdwardsCurve::Ed25519, x, .. } => vec![
+                (Value::Integer(KEY_TYPE), Value::Integer(KEY_TYPE_OKP)),
+                alg,
+                (Value::Integer(CURVE), Value::Integer(CURVE_ED25519)),
+                (Value::Integer(X), Value::Bytes(x.to_vec())),
+            ],
+            Self::Rsa { n, e, .. } => vec![
+                (Value::Integer(KEY_TYPE), Value::Integer(KEY_TYPE_RSA)),
+                alg,
+                (Value::Integer(RSA_N), Value::Bytes(n.to_vec())),
+                (Value::Integer(RSA_E), Value::Bytes(e.to_vec())),
+            ],
+        };
+        Value::Map(entries)
+    }
+
+    /// The key in the canonical COSE_Key encoding credentials are stored in.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        cbor::encode(&self.to_value())
+    }
+
+    pub fn algorithm(&self) -> CoseAlgorithm {
+        match self {
+            Self::Ec2 { alg, .. } | Self::Okp { alg, .. } | Self::Rsa { alg, .. } => *alg,
+        }
+    }
+
+    /// Verifies a signature in the format WebAuthn section 6.5.6 defines for the key's algorithm.
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> Result<()> {
+        let result = match self {
+            Self::Ec2 { crv, x, y, .. } => {
+                let algorithm = match crv {
+                    EllipticCurve::P256 => &signature::ECDSA_P256_SHA256_ASN1,
+                    EllipticCurve::P384 => &signature::ECDSA_P384_SHA384_ASN1,
+                };
+                let point = [&[0x04], x.as_slice(), y.as_slice()].concat();
+                UnparsedPublicKey::new(algorithm, point).verify(message, signature)
+            },
+            Self::Okp { x, .. } => UnparsedPublicKey::new(&signature::ED25519, x).verify(message, signature),
+            Self::Rsa { n, e, .. } => RsaPublicKeyComponents { n, e }.verify(&signature::RSA_PKCS1_2048_8192_SHA256, message, signature),
+        };
+        result.map_err(|_| CoseError::InvalidSignature)
+    }
+
+    fn check_algorithm(&self) -> Result<()> {
+        let fits = match self {
+            Self::Ec2 { alg: CoseAlgorithm::Es256, crv: EllipticCurve::P256, x, y } => x.len() == 32 && y.len() == 32,
+            Self::Ec2 { alg: CoseAlgorithm::Es384, crv: EllipticCurve::P384, x, y } => x.len() == 48 && y.len() == 48,
+            Self::Okp { alg: CoseAlgorithm::EdDsa, crv: EdwardsCurve::Ed25519, x } => x.len() == 32,
+            Self::Rsa { alg: CoseAlgorithm::Rs256, n, e } => !n.is_empty() && !e.is_empty(),
+            _ => false,
+        };
+        if !fits {
+            return Err(CoseError::AlgorithmKeyMismatch(self.algorithm()))
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use super::*;
+
+    fn ec2_key(alg: i128, crv: i128, size: usize) -> Value {
+        Value::Map(vec![
+            (Value::Integer(KEY_TYPE), Value::Integer(KEY_TYPE_EC2)),
+            (Value::Integer(ALGORITHM), Value::Integer(alg)),
+            (Value::Integer(CURVE), Value::Integer(crv)),
+            (Value::Integer(X), Value::Bytes(vec![1; size])),
+            (Value::Integer(Y), Value::Bytes(vec![2; size])),
+        ])
+    }
+
+    #[test]
+    fn test_from_value() {
+        let key = CoseKey::from_value(&ec2_key(-7, CURVE_P256, 32)).unwrap();
+        assert_eq!(CoseAlgorithm::Es256, key.algorithm());
+        assert!(matches!(key, CoseKey::Ec2 { crv: EllipticCurve::P256, .. }));
+
+        assert!(matches!(CoseKey::from_value(&ec2_key(-35, CURVE_P256, 32)), Err(CoseError::AlgorithmKeyMismatch(CoseAlgorithm::Es384))));
+        assert!(matches!(CoseKey::from_value(&ec2_key(-7, CURVE_P256, 31)), Err(CoseError::AlgorithmKeyMismatch(_))));
+        assert!(matches!(CoseKey::from_value(&ec2_key(-7, 3, 66)), Err(CoseError::UnsupportedCurve(3))));
+        assert!(matches!(CoseKey::from_value(&ec2_key(-36, CURVE_P256, 32)), Err(CoseError::UnsupportedAlgorithm(-36))));
+        assert!(matches!(CoseKey::from_value(&Value::Array(vec![])), Err(CoseError::NotAMap)));
+
+        let okp = Value::Map(vec![
+            (Value::Integer(KEY_TYPE), Value::Integer(KEY_TYPE_OKP)),
+            (Value::Integer(ALGORITHM), Value::Integer(-8)),
+            (Value::Integer(CURVE), Value::Integer(CURVE_ED25519)),
+        ]);
+        assert!(matches!(CoseKey::from_value(&okp), Err(CoseError::InvalidParameter(X))));
+    }
+
+    #[test]
+    fn test_verify() {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let key = CoseKey::Okp {
+            alg: CoseAlgorithm::EdDsa,
+            crv: EdwardsCurve::Ed25519,
+            x: key_pair.public_key().as_ref().to_vec(),
+        };
+        let signature = key_pair.sign(b"message-01");
+        assert!(key.verify(b"message-01", signature.as_ref()).is_ok());
+        assert!(matches!(key.verify(b"message-02", signature.as_ref()), Err(CoseError::InvalidSignature)));
+    }
+
+    #[test]
+    fn test_jwk() {
+        let key = CoseKey::from_value(&ec2_key(-7, CURVE_P256, 32)).unwrap();
+        let jwk = key.to_jwk();
+        assert_eq!(Some("ES256"), jwk.alg.as_deref());
+        assert_eq!(vec![Algorithm::ES256], jwk.algorithms());
+        assert!(jwk.decoding_key().is_ok());
+        assert_eq!(key, CoseKey::from_jwk(&jwk).unwrap());
+        assert_eq!(key, CoseKey::from_bytes(&key.to_bytes()).unwrap());
+
+        let rsa = JsonWebKey {
+            alg: None,
+            ..CoseKey::Rsa { alg: CoseAlgorithm::Rs256, n: vec![0xc5; 256], e: vec![1, 0, 1] }.to_jwk()
+        };
+        assert_eq!(CoseAlgorithm::Rs256, CoseKey::from_jwk(&rsa).unwrap().algorithm());
+        let ps256 = JsonWebKey { alg: Some("PS256".to_owned()), ..rsa };
+        assert!(matches!(CoseKey::from_jwk(&ps256), Err(CoseError::UnsupportedJwk)));
+    }
+}
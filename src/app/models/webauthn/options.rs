@@ -0,0 +1,104 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::app::models::webauthn::cose::CoseAlgorithm;
+
+pub const PUBLIC_KEY: &str = "public-key";
+
+/// `UserVerificationRequirement` from WebAuthn section 5.8.6.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UserVerification {
+    Required,
+    #[default]
+    Preferred,
+    Discouraged,
+}
+
+/// `ResidentKeyRequirement` from WebAuthn section 5.4.6; discoverable credentials are what makes a passkey.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResidentKey {
+    Required,
+    #[default]
+    Preferred,
+    Discouraged,
+}
+
+/// `PublicKeyCredentialCreationOptions` from WebAuthn section 5.4, in the JSON form
+/// `PublicKeyCredential.parseCreationOptionsFromJSON()` takes.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicKeyCredentialCreationOptions {
+    pub rp: RelyingParty,
+    pub user: UserEntity,
+    pub challenge: String,
+    pub pub_key_cred_params: Vec<CredentialParameters>,
+    pub timeout: u64,
+    pub exclude_credentials: Vec<CredentialDescriptor>,
+    pub authenticator_selection: AuthenticatorSelection,
+    pub attestation: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RelyingParty {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserEntity {
+    /// User handle, base64url encoded.
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CredentialParameters {
+    #[serde(rename = "type")]
+    pub credential_type: &'static str,
+    pub alg: i64,
+}
+
+impl CredentialParameters {
+    pub fn supported() -> Vec<Self> {
+        CoseAlgorithm::SUPPORTED.iter()
+            .map(|algorithm| Self {
+                credential_type: PUBLIC_KEY,
+                alg: algorithm.id(),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub credential_type: &'static str,
+    /// Credential ID, base64url encoded.
+    pub id: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub transports: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    pub resident_key: ResidentKey,
+    pub require_resident_key: bool,
+    pub user_verification: UserVerification,
+}
+
+/// `PublicKeyCredentialRequestOptions` from WebAuthn section 5.5, in the JSON form
+/// `PublicKeyCredential.parseRequestOptionsFromJSON()` takes.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicKeyCredentialRequestOptions {
+    pub challenge: String,
+    pub timeout: u64,
+    pub rp_id: String,
+    /// Empty, so the authenticator offers its discoverable credentials and no user name is needed.
+    pub allow_credentials: Vec<CredentialDescriptor>,
+    pub user_verification: UserVerification,
+}
@@ -0,0 +1,318 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::app::config::WebauthnConfig;
+use crate::app::models::base64url;
+use crate::app::models::random::{RandomString, RandomStringGenerator};
+use crate::app::models::user::{Credential, User};
+use crate::app::models::webauthn::authenticator_data::{AuthenticatorData, AuthenticatorDataError};
+use crate::app::models::webauthn::cbor::{self, CborError, Value};
+use crate::app::models::webauthn::client_data::{self, ClientDataError, CollectedClientData};
+use crate::app::models::webauthn::cose::{CoseError, CoseKey};
+use crate::app::models::webauthn::options::{
+    AuthenticatorSelection, CredentialDescriptor, CredentialParameters, PUBLIC_KEY, PublicKeyCredentialCreationOptions,
+    RelyingParty, ResidentKey, UserEntity,
+};
+
+/// Longest credential ID WebAuthn section 7.1 step 23 allows.
+const MAX_CREDENTIAL_ID_LENGTH: usize = 1023;
+
+#[derive(Debug, Error)]
+pub enum RegistrationError {
+    #[error("no registration in progress")]
+    CeremonyNotStarted,
+
+    #[error("invalid base64url: {0}")]
+    InvalidEncoding(#[from] base64::DecodeError),
+
+    #[error("credential type {0} is not public-key")]
+    InvalidCredentialType(String),
+
+    #[error("id does not match rawId")]
+    CredentialIdMismatch,
+
+    #[error("{0}")]
+    ClientData(#[from] ClientDataError),
+
+    #[error("invalid attestation object: {0}")]
+    InvalidAttestationObject(#[from] CborError),
+
+    #[error("attestation object has no valid {0}")]
+    AttestationObjectIncomplete(&'static str),
+
+    #[error("{0}")]
+    AuthenticatorData(#[from] AuthenticatorDataError),
+
+    #[error("authenticator data has no attested credential")]
+    AttestedCredentialMissing,
+
+    #[error("credential ID of {0} bytes is too long")]
+    CredentialIdTooLong(usize),
+
+    #[error("invalid credential public key: {0}")]
+    InvalidPublicKey(#[from] CoseError),
+
+    #[error("attestation statement of format none is not empty")]
+    InvalidAttestationStatement,
+}
+
+type Result<T> = std::result::Result<T, RegistrationError>;
+
+/// Kept in the session between the creation options and the registration response.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RegistrationState {
+    pub challenge: String,
+    pub user_id: String,
+}
+
+/// Creation options for registering another credential to a user, from WebAuthn section 7.1 steps 1 and 2.
+pub struct RegistrationOptions<'a> {
+    config: &'a WebauthnConfig,
+    user: &'a User,
+}
+
+impl<'a> RegistrationOptions<'a> {
+    pub fn new(config: &'a WebauthnConfig, user: &'a User) -> Self {
+        Self {
+            config,
+            user,
+        }
+    }
+
+    pub fn start(&self) -> (PublicKeyCredentialCreationOptions, RegistrationState) {
+        let config = self.config;
+        let user = self.user;
+        let challenge = RandomString::new().generate(32);
+        // The local user ID is random, so it doubles as a user handle without revealing anything about the user
+        let claim = |name: &str| user.identities.iter().find_map(|identity| identity.claims[name].as_str().map(str::to_owned));
+        let name = claim("email").unwrap_or_else(|| user.id.to_owned());
+        let display_name = claim("name").unwrap_or_else(|| name.to_owned());
+
+        let options = PublicKeyCredentialCreationOptions {
+            rp: RelyingParty {
+                id: config.rp_id.to_owned(),
+                name: config.rp_name.to_owned(),
+            },
+            user: UserEntity {
+                id: base64url::encode(user.id.as_bytes()),
+                name,
+                display_name,
+            },
+            challenge: challenge.to_owned(),
+            pub_key_cred_params: CredentialParameters::supported(),
+            timeout: config.timeout,
+            exclude_credentials: user.credentials.iter()
+                .map(|credential| CredentialDescriptor {
+                    credential_type: PUBLIC_KEY,
+                    id: credential.id.to_owned(),
+                    transports: credential.transports.clone(),
+                })
+                .collect(),
+            authenticator_selection: AuthenticatorSelection {
+                resident_key: config.resident_key,
+                require_resident_key: config.resident_key == ResidentKey::Required,
+                user_verification: config.user_verification,
+            },
+            attestation: "none",
+        };
+        let state = RegistrationState {
+            challenge,
+            user_id: user.id.to_owned(),
+        };
+        (options, state)
+    }
+}
+
+/// `RegistrationResponseJSON` from WebAuthn section 5.1, as `PublicKeyCredential.toJSON()` produces it.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationResponse {
+    pub id: String,
+    pub raw_id: String,
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub attestation_object: String,
+    #[serde(default)]
+    pub transports: Vec<String>,
+}
+
+/// Verifies a registration response following WebAuthn section 7.1.
+pub struct RegistrationVerification<'a> {
+    config: &'a WebauthnConfig,
+    state: &'a RegistrationState,
+}
+
+impl<'a> RegistrationVerification<'a> {
+    pub fn new(config: &'a WebauthnConfig, state: &'a RegistrationState) -> Self {
+        Self {
+            config,
+            state,
+        }
+    }
+
+    pub fn verify(&self, response: &RegistrationResponse) -> Result<Credential> {
+        let config = self.config;
+        if response.credential_type != PUBLIC_KEY {
+            return Err(RegistrationError::InvalidCredentialType(response.credential_type.to_owned()))
+        }
+        let raw_id = base64url::decode(&response.raw_id)?;
+        if base64url::decode(&response.id)? != raw_id {
+            return Err(RegistrationError::CredentialIdMismatch)
+        }
+
+        let client_data = CollectedClientData::parse(&base64url::decode(&response.response.client_data_json)?)?;
+        client_data.verify(client_data::CREATE, &self.state.challenge, &config.origins)?;
+
+        let attestation_object = cbor::decode(&base64url::decode(&response.response.attestation_object)?)?;
+        let format = attestation_object.get_text("fmt").and_then(Value::as_text)
+            .ok_or(RegistrationError::AttestationObjectIncomplete("fmt"))?;
+        let statement = attestation_object.get_text("attStmt").and_then(Value::as_map)
+            .ok_or(RegistrationError::AttestationObjectIncomplete("attStmt"))?;
+        let authenticator_data = attestation_object.get_text("authData").and_then(Value::as_bytes)
+            .ok_or(RegistrationError::AttestationObjectIncomplete("authData"))?;
+
+        let authenticator_data = AuthenticatorData::parse(authenticator_data)?;
+        authenticator_data.verify(&config.rp_id, config.user_verification)?;
+        let attested = authenticator_data.attested_credential.as_ref().ok_or(RegistrationError::AttestedCredentialMissing)?;
+        if attested.credential_id.len() > MAX_CREDENTIAL_ID_LENGTH {
+            return Err(RegistrationError::CredentialIdTooLong(attested.credential_id.len()))
+        }
+        if attested.credential_id != raw_id {
+            return Err(RegistrationError::CredentialIdMismatch)
+        }
+        // Only algorithms offered in pubKeyCredParams parse
+        CoseKey::from_bytes(&attested.public_key)?;
+
+        // Attestation is not requested, so statements of other formats are not verified and carry no trust
+        match format {
+            "none" if !statement.is_empty() => return Err(RegistrationError::InvalidAttestationStatement),
+            "none" => {},
+            format => log::info!("ignoring {} attestation statement", format),
+        }
+
+        Ok(Credential {
+            id: base64url::encode(&raw_id),
+            public_key: attested.public_key.clone(),
+            sign_count: authenticator_data.sign_count,
+            transports: response.response.transports.clone(),
+            backup_eligible: authenticator_data.backup_eligible(),
+            backed_up: authenticator_data.backed_up(),
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::app::models::webauthn::authenticator_data::{ATTESTED_CREDENTIAL_DATA, USER_PRESENT, tests::authenticator_data};
+    use super::*;
+
+    /// CBOR head for a string of `length` bytes with the given major type.
+    fn cbor_head(major: u8, length: usize) -> Vec<u8> {
+        match length {
+            0..=23 => vec![major << 5 | length as u8],
+            24..=255 => vec![major << 5 | 24, length as u8],
+            _ => [vec![major << 5 | 25], (length as u16).to_be_bytes().to_vec()].concat(),
+        }
+    }
+
+    fn cbor_text(text: &str) -> Vec<u8> {
+        [cbor_head(3, text.len()), text.as_bytes().to_vec()].concat()
+    }
+
+    fn cbor_bytes(bytes: &[u8]) -> Vec<u8> {
+        [cbor_head(2, bytes.len()), bytes.to_vec()].concat()
+    }
+
+    /// COSE_Key of an ES256 credential with the given public point.
+    pub fn es256_cose_key(x: &[u8], y: &[u8]) -> Vec<u8> {
+        [vec![0xa5, 0x01, 0x02, 0x03, 0x26, 0x20, 0x01, 0x21], cbor_bytes(x), vec![0x22], cbor_bytes(y)].concat()
+    }
+
+    fn attestation_object(format: &str, authenticator_data: &[u8]) -> Vec<u8> {
+        [
+            vec![0xa3],
+            cbor_text("fmt"), cbor_text(format),
+            cbor_text("attStmt"), vec![0xa0],
+            cbor_text("authData"), cbor_bytes(authenticator_data),
+        ].concat()
+    }
+
+    fn client_data_json(ceremony_type: &str, challenge: &str) -> String {
+        let json = format!(r#"{{"type":"{}","challenge":"{}","origin":"http://localhost:8080"}}"#, ceremony_type, challenge);
+        base64url::encode(json.as_bytes())
+    }
+
+    fn registration_response(credential_id: &[u8], attestation_object: &[u8]) -> RegistrationResponse {
+        RegistrationResponse {
+            id: base64url::encode(credential_id),
+            raw_id: base64url::encode(credential_id),
+            credential_type: PUBLIC_KEY.to_owned(),
+            response: AttestationResponse {
+                client_data_json: client_data_json(client_data::CREATE, "challenge-01"),
+                attestation_object: base64url::encode(attestation_object),
+                transports: vec!["internal".to_owned()],
+            },
+        }
+    }
+
+    #[test]
+    fn test_options() {
+        let config = WebauthnConfig::default();
+        let user = User {
+            id: "user-01".to_owned(),
+            created_at: 0,
+            identities: vec![],
+            credentials: vec![],
+        };
+        let (options, state) = RegistrationOptions::new(&config, &user).start();
+        assert_eq!(options.challenge, state.challenge);
+        assert_eq!("user-01", state.user_id);
+        assert_eq!(b"user-01".to_vec(), base64url::decode(&options.user.id).unwrap());
+        assert_eq!(-7, options.pub_key_cred_params[0].alg);
+    }
+
+    #[test]
+    fn test_verify() {
+        let config = WebauthnConfig::default();
+        let state = RegistrationState {
+            challenge: "challenge-01".to_owned(),
+            user_id: "user-01".to_owned(),
+        };
+        let public_key = es256_cose_key(&[1; 32], &[2; 32]);
+        let data = authenticator_data(USER_PRESENT | ATTESTED_CREDENTIAL_DATA, 0, Some((b"credential-01", &public_key)));
+        let response = registration_response(b"credential-01", &attestation_object("none", &data));
+        let credential = RegistrationVerification::new(&config, &state).verify(&response).unwrap();
+        assert_eq!(base64url::encode(b"credential-01"), credential.id);
+        assert_eq!(public_key, credential.public_key);
+        assert_eq!(vec!["internal".to_owned()], credential.transports);
+
+        let other = RegistrationState { challenge: "challenge-02".to_owned(), ..state };
+        let result = RegistrationVerification::new(&config, &other).verify(&response);
+        assert!(matches!(result, Err(RegistrationError::ClientData(ClientDataError::ChallengeMismatch))));
+
+        let response = registration_response(b"credential-02", &attestation_object("none", &data));
+        assert!(matches!(RegistrationVerification::new(&config, &other).verify(&response), Err(RegistrationError::ClientData(_))));
+        let state = RegistrationState { challenge: "challenge-01".to_owned(), ..other };
+        assert!(matches!(RegistrationVerification::new(&config, &state).verify(&response), Err(RegistrationError::CredentialIdMismatch)));
+
+        let data = authenticator_data(USER_PRESENT, 0, None);
+        let response = registration_response(b"credential-01", &attestation_object("none", &data));
+        assert!(matches!(RegistrationVerification::new(&config, &state).verify(&response), Err(RegistrationError::AttestedCredentialMissing)));
+
+        let rsa_key = [0xa3, 0x01, 0x03, 0x03, 0x39, 0x01, 0x00, 0x20, 0x40];
+        let data = authenticator_data(USER_PRESENT | ATTESTED_CREDENTIAL_DATA, 0, Some((b"credential-01", &rsa_key)));
+        let response = registration_response(b"credential-01", &attestation_object("none", &data));
+        assert!(matches!(RegistrationVerification::new(&config, &state).verify(&response), Err(RegistrationError::InvalidPublicKey(_))));
+    }
+}
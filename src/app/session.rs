@@ -0,0 +1,39 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::app::config::{SessionConfig, SessionStoreConfig};
+use crate::app::session::sqlite::SqliteSessionStore;
+use crate::app::session::store::{MemorySessionStore, Result, SessionResources, SessionStore};
+
+pub mod cookie;
+pub mod middleware;
+pub mod sqlite;
+pub mod store;
+
+/// Opens the store chosen in `[session]`.
+pub fn open_store(config: &SessionConfig) -> Result<Arc<dyn SessionStore>> {
+    let store: Arc<dyn SessionStore> = match &config.store {
+        SessionStoreConfig::Memory => Arc::new(MemorySessionStore::default()),
+        SessionStoreConfig::Sqlite { database } => Arc::new(SqliteSessionStore::open(database)?),
+    };
+    Ok(store)
+}
+
+/// Removes expired sessions, and the resources they no longer keep, every `interval` for as long as the runtime is up.
+pub fn spawn_sweeper(store: Arc<dyn SessionStore>, resources: Option<Arc<dyn SessionResources>>, interval: Duration) {
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(interval);
+        loop {
+            interval.tick().await;
+            match store.sweep() {
+                Ok(0) => {},
+                Ok(removed) => log::debug!("removed {} expired sessions", removed),
+                Err(error) => log::warn!("sweeping expired sessions failed: {}", error),
+            }
+            match resources.as_ref().map(|resources| resources.sweep()) {
+                None | Some(0) => {},
+                Some(removed) => log::debug!("removed {} expired session resources", removed),
+            }
+        }
+    });
+}
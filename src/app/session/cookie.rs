@@ -0,0 +1,213 @@
+use std::time::Duration;
+
+use actix_web::cookie::{Cookie, CookieJar, Key, SameSite};
+use actix_web::cookie::time::Duration as CookieDuration;
+use actix_web::dev::ServiceRequest;
+use thiserror::Error;
+use tokio::{fs::File, io::AsyncReadExt};
+
+use crate::app::config::{CookieMode, SameSiteConfig, SessionConfig, SessionCookieConfig};
+use crate::app::models::base64url;
+use crate::app::models::random::{RandomString, RandomStringGenerator};
+
+#[derive(Debug, Error)]
+pub enum SessionKeyError {
+    #[error("failed to read session key file: {0}")]
+    ReadFailed(#[from] std::io::Error),
+
+    #[error("session key is not base64url: {0}")]
+    InvalidEncoding(#[from] base64::DecodeError),
+
+    #[error("session key has {0} bytes, at least {MIN_KEY_LENGTH} are needed")]
+    TooShort(usize),
+}
+
+type Result<T> = std::result::Result<T, SessionKeyError>;
+
+const MIN_KEY_LENGTH: usize = 64;
+
+/// Random key in the format `[session]` expects.
+pub fn generate_key() -> String {
+    RandomString::new().generate(MIN_KEY_LENGTH)
+}
+
+fn decode_key(encoded: &str) -> Result<Key> {
+    let key = base64url::decode(encoded.trim())?;
+    if key.len() < MIN_KEY_LENGTH {
+        return Err(SessionKeyError::TooShort(key.len()))
+    }
+    Ok(Key::from(&key))
+}
+
+/// Builds the session ID cookie and reads it back, signing or encrypting it with the current key.
+pub struct SessionCookie {
+    config: SessionCookieConfig,
+    mode: CookieMode,
+    key: Key,
+    previous_keys: Vec<Key>,
+    max_age: Option<Duration>,
+}
+
+impl SessionCookie {
+    pub fn new(key: Key, ttl: Duration) -> Self {
+        Self {
+            config: SessionCookieConfig::default(),
+            mode: CookieMode::default(),
+            key,
+            previous_keys: vec![],
+            max_age: Some(ttl),
+        }
+    }
+
+    pub async fn from_config(config: &SessionConfig) -> Result<Self> {
+        let key = match (&config.key, &config.key_file) {
+            (Some(key), _) => decode_key(key)?,
+            (None, Some(path)) => {
+                let mut file = File::open(path).await?;
+                let mut content = String::new();
+                file.read_to_string(&mut content).await?;
+                decode_key(&content)?
+            },
+            (None, None) => {
+                log::warn!("no session key configured, sessions will not survive a restart");
+                Key::generate()
+            },
+        };
+        let previous_keys = config.previous_keys.iter()
+            .map(|key| decode_key(key))
+            .collect::<Result<Vec<_>>>()?;
+        let max_age = match config.cookie.max_age {
+            Some(0) => None,
+            Some(max_age) => Some(Duration::from_secs(max_age)),
+            None => Some(config.ttl()),
+        };
+        Ok(Self {
+            config: config.cookie.clone(),
+            mode: config.cookie_mode,
+            key,
+            previous_keys,
+            max_age,
+        })
+    }
+
+    pub fn with_mode(self, mode: CookieMode) -> Self {
+        Self { mode, ..self }
+    }
+
+    pub fn with_previous_keys(self, previous_keys: Vec<Key>) -> Self {
+        Self { previous_keys, ..self }
+    }
+
+    pub fn with_config(self, config: SessionCookieConfig) -> Self {
+        Self { config, ..self }
+    }
+
+    pub fn name(&self) -> String {
+        self.config.name()
+    }
+
+    /// Session ID from the request, if its cookie verifies under the current or a previous key.
+    pub fn session_id(&self, req: &ServiceRequest) -> Option<String> {
+        let cookie = req.cookie(&self.name())?;
+        let mut jar = CookieJar::new();
+        jar.add_original(cookie);
+        std::iter::once(&self.key)
+            .chain(&self.previous_keys)
+            .find_map(|key| match self.mode {
+                CookieMode::Signed => jar.signed(key).get(&self.name()),
+                CookieMode::Private => jar.private(key).get(&self.name()),
+            })
+            .map(|cookie| cookie.value().to_owned())
+    }
+
+    /// Cookie carrying the session ID, protected with the current key.
+    pub fn build(&self, id: String) -> Cookie<'static> {
+        let mut jar = CookieJar::new();
+        let cookie = self.attributes(Cookie::new(self.name(), id));
+        match self.mode {
+            CookieMode::Signed => jar.signed_mut(&self.key).add(cookie),
+            CookieMode::Private => jar.private_mut(&self.key).add(cookie),
+        }
+        jar.get(&self.name()).cloned().expect("cookie was just added")
+    }
+
+    pub fn removal(&self) -> Cookie<'static> {
+        let mut cookie = self.attributes(Cookie::new(self.name(), ""));
+        cookie.make_removal();
+        cookie
+    }
+
+    fn attributes(&self, mut cookie: Cookie<'static>) -> Cookie<'static> {
+        let config = &self.config;
+        cookie.set_http_only(true);
+        cookie.set_secure(config.secure);
+        cookie.set_path(config.path.to_owned());
+        cookie.set_same_site(match config.same_site {
+            SameSiteConfig::Strict => SameSite::Strict,
+            SameSiteConfig::Lax => SameSite::Lax,
+            SameSiteConfig::None => SameSite::None,
+        });
+        if let Some(domain) = &config.domain {
+            cookie.set_domain(domain.to_owned());
+        }
+        if let Some(max_age) = self.max_age {
+            cookie.set_max_age(CookieDuration::seconds(max_age.as_secs() as i64));
+        }
+        cookie
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use super::*;
+
+    fn session_id(session_cookie: &SessionCookie, cookie: Cookie<'static>) -> Option<String> {
+        session_cookie.session_id(&TestRequest::default().cookie(cookie).to_srv_request())
+    }
+
+    #[test]
+    fn test_signed_and_private_cookies() {
+        let key = decode_key(&generate_key()).unwrap();
+        let signed = SessionCookie::new(key.clone(), Duration::from_secs(60));
+        let cookie = signed.build("session-01".to_owned());
+        assert!(cookie.value().ends_with("session-01"));
+        assert_eq!(Some("session-01".to_owned()), session_id(&signed, cookie));
+        let forged = Cookie::new("webauthexp-session", "session-02");
+        assert_eq!(None, session_id(&signed, forged));
+
+        let private = SessionCookie::new(key, Duration::from_secs(60)).with_mode(CookieMode::Private);
+        let cookie = private.build("session-01".to_owned());
+        assert!(!cookie.value().contains("session-01"));
+        assert_eq!(Some("session-01".to_owned()), session_id(&private, cookie.clone()));
+        assert_eq!(None, session_id(&signed, cookie));
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let old_key = Key::generate();
+        let cookie = SessionCookie::new(old_key.clone(), Duration::from_secs(60)).build("session-01".to_owned());
+
+        let rotated = SessionCookie::new(Key::generate(), Duration::from_secs(60));
+        assert_eq!(None, session_id(&rotated, cookie.clone()));
+        let rotated = rotated.with_previous_keys(vec![old_key]);
+        assert_eq!(Some("session-01".to_owned()), session_id(&rotated, cookie));
+
+        assert!(matches!(decode_key("c2hvcnQ"), Err(SessionKeyError::TooShort(5))));
+    }
+
+    #[test]
+    fn test_host_prefix() {
+        let config = SessionCookieConfig {
+            host_prefix: true,
+            ..Default::default()
+        };
+        let session_cookie = SessionCookie::new(Key::generate(), Duration::from_secs(60)).with_config(config);
+        let cookie = session_cookie.build("session-01".to_owned());
+        assert_eq!("__Host-webauthexp-session", cookie.name());
+        assert_eq!(Some(true), cookie.secure());
+        assert_eq!(Some("/"), cookie.path());
+        assert_eq!(None, cookie.domain());
+        assert_eq!(Some("session-01".to_owned()), session_id(&session_cookie, cookie));
+    }
+}
@@ -0,0 +1,316 @@
+use std::future::{Future, Ready, ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_session::{Session, SessionStatus};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::cookie::Cookie;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready};
+use actix_web::error::ErrorInternalServerError;
+
+use crate::app::models::random::{RandomString, RandomStringGenerator};
+use crate::app::session::cookie::SessionCookie;
+use crate::app::session::store::{self, SessionResources, SessionState, SessionStore};
+
+/// Keeps `Session` state in a `SessionStore`; the browser only holds an opaque, random session ID
+/// in a signed or encrypted cookie.
+///
+/// Sessions expire after `ttl` without requests. A renewed session gets a new ID, so IDs seen
+/// before sign-in are worthless afterwards.
+///
+/// With `resources`, whatever the state refers to expires along with the session and is released when it is purged.
+#[derive(Clone)]
+pub struct ServerSession {
+    store: Arc<dyn SessionStore>,
+    cookie: Arc<SessionCookie>,
+    ttl: Duration,
+    resources: Option<Arc<dyn SessionResources>>,
+}
+
+impl ServerSession {
+    pub fn new(store: Arc<dyn SessionStore>, cookie: Arc<SessionCookie>, ttl: Duration) -> Self {
+        Self {
+            store,
+            cookie,
+            ttl,
+            resources: None,
+        }
+    }
+
+    pub fn with_resources(self, resources: Arc<dyn SessionResources>) -> Self {
+        Self { resources: Some(resources), ..self }
+    }
+
+    /// Persists the state after a request, returning the cookie to set, if any. `loaded` is the state the request started with.
+    fn persist(&self, id: Option<String>, status: SessionStatus, state: SessionState, loaded: SessionState) -> store::Result<Option<Cookie<'static>>> {
+        match (status, id) {
+            (SessionStatus::Purged, Some(id)) => {
+                self.store.delete(&id)?;
+                if let Some(resources) = &self.resources {
+                    resources.release(&loaded);
+                }
+                Ok(Some(self.cookie.removal()))
+            },
+            (SessionStatus::Purged, None) | (SessionStatus::Unchanged, None) => Ok(None),
+            // Sliding expiry: every request pushes the expiry back and refreshes the cookie. A session deleted
+            // while the request was running, like by back-channel logout, stays deleted.
+            (SessionStatus::Unchanged, Some(id)) => {
+                let live = self.store.touch(&id, self.ttl)?;
+                Ok(Some(self.refreshed(id, live, &state)))
+            },
+            (SessionStatus::Changed, Some(id)) => {
+                let live = self.store.update(&id, &state, self.ttl)?;
+                Ok(Some(self.refreshed(id, live, &state)))
+            },
+            (SessionStatus::Renewed, Some(id)) => {
+                self.store.delete(&id)?;
+                self.save_new(&state)
+            },
+            (SessionStatus::Changed, None) | (SessionStatus::Renewed, None) => self.save_new(&state),
+        }
+    }
+
+    /// Cookie after refreshing a session, which removes it if the session is gone.
+    fn refreshed(&self, id: String, live: bool, state: &SessionState) -> Cookie<'static> {
+        if !live {
+            return self.cookie.removal()
+        }
+        self.retain(state);
+        self.cookie.build(id)
+    }
+
+    fn save_new(&self, state: &SessionState) -> store::Result<Option<Cookie<'static>>> {
+        let id = RandomString::new().generate(32);
+        self.save(&id, state)?;
+        Ok(Some(self.cookie.build(id)))
+    }
+
+    fn save(&self, id: &str, state: &SessionState) -> store::Result<()> {
+        self.store.save(id, state, self.ttl)?;
+        self.retain(state);
+        Ok(())
+    }
+
+    fn retain(&self, state: &SessionState) {
+        if let Some(resources) = &self.resources {
+            resources.retain(state, self.ttl);
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ServerSession
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = ServerSessionMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ServerSessionMiddleware {
+            service: Rc::new(service),
+            session: self.clone(),
+        }))
+    }
+}
+
+pub struct ServerSessionMiddleware<S> {
+    service: Rc<S>,
+    session: ServerSession,
+}
+
+impl<S, B> Service<ServiceRequest> for ServerSessionMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let session = self.session.clone();
+
+        Box::pin(async move {
+            let requested_id = session.cookie.session_id(&req);
+            // Unknown or expired IDs are dropped, so a client cannot choose its own session ID
+            let (id, loaded) = match requested_id {
+                Some(id) => match session.store.load(&id).map_err(ErrorInternalServerError)? {
+                    Some(state) => {
+                        let loaded = match session.resources {
+                            Some(_) => state.clone(),
+                            None => SessionState::new(),
+                        };
+                        Session::set_session(&mut req, state);
+                        (Some(id), loaded)
+                    },
+                    None => (None, SessionState::new()),
+                },
+                None => (None, SessionState::new()),
+            };
+
+            let mut res = service.call(req).await?;
+            let (status, state) = Session::get_changes(&mut res);
+            let result = session.persist(id, status, state.collect(), loaded)
+                .map_err(ErrorInternalServerError)
+                .and_then(|cookie| match cookie {
+                    Some(cookie) => res.response_mut().add_cookie(&cookie).map_err(Into::into),
+                    None => Ok(()),
+                });
+            match result {
+                Ok(()) => Ok(res.map_into_left_body()),
+                Err(error) => Ok(res.error_response(error).map_into_right_body()),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{App, HttpResponse, test, web};
+    use std::sync::Mutex;
+    use actix_web::cookie::Key;
+    use crate::app::session::store::MemorySessionStore;
+    use super::*;
+
+    /// Records which user IDs were retained or released.
+    #[derive(Default)]
+    struct Resources {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl SessionResources for Resources {
+        fn retain(&self, state: &SessionState, _ttl: Duration) {
+            self.events.lock().unwrap().extend(state.get("user-id").map(|user_id| format!("retain {}", user_id)));
+        }
+
+        fn release(&self, state: &SessionState) {
+            self.events.lock().unwrap().extend(state.get("user-id").map(|user_id| format!("release {}", user_id)));
+        }
+
+        fn sweep(&self) -> usize {
+            0
+        }
+    }
+
+    async fn set(session: Session) -> HttpResponse {
+        session.insert("user-id", "user-01").unwrap();
+        HttpResponse::Ok().finish()
+    }
+
+    async fn get(session: Session) -> HttpResponse {
+        let user_id = session.get::<String>("user-id").unwrap().unwrap_or_default();
+        HttpResponse::Ok().body(user_id)
+    }
+
+    async fn renew(session: Session) -> HttpResponse {
+        session.renew();
+        HttpResponse::Ok().finish()
+    }
+
+    async fn purge(session: Session) -> HttpResponse {
+        session.purge();
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_rt::test]
+    async fn test_server_session() {
+        let store = Arc::new(MemorySessionStore::default());
+        let ttl = Duration::from_secs(60);
+        let session_cookie = Arc::new(SessionCookie::new(Key::generate(), ttl));
+        let app = test::init_service(
+            App::new()
+                .wrap(ServerSession::new(store.clone(), session_cookie.clone(), ttl))
+                .route("/set", web::get().to(set))
+                .route("/get", web::get().to(get))
+                .route("/renew", web::get().to(renew))
+                .route("/purge", web::get().to(purge))
+        ).await;
+
+        let response = test::call_service(&app, test::TestRequest::get().uri("/get").to_request()).await;
+        assert!(response.response().cookies().next().is_none());
+
+        let response = test::call_service(&app, test::TestRequest::get().uri("/set").to_request()).await;
+        let cookie = response.response().cookies().find(|cookie| cookie.name() == session_cookie.name()).unwrap().into_owned();
+        assert!(cookie.http_only().unwrap_or(false));
+        // Only the ID goes to the browser
+        assert!(!cookie.value().contains("user-01"));
+        let request = test::TestRequest::default().cookie(cookie.clone()).to_srv_request();
+        let id = session_cookie.session_id(&request).unwrap();
+        assert!(store.load(&id).unwrap().is_some());
+
+        let request = test::TestRequest::get().uri("/get").cookie(cookie.clone()).to_request();
+        assert_eq!("user-01", test::call_and_read_body(&app, request).await);
+
+        let request = test::TestRequest::get().uri("/renew").cookie(cookie.clone()).to_request();
+        let response = test::call_service(&app, request).await;
+        let renewed = response.response().cookies().find(|cookie| cookie.name() == session_cookie.name()).unwrap().into_owned();
+        assert_ne!(cookie.value(), renewed.value());
+        assert!(store.load(&id).unwrap().is_none());
+
+        let request = test::TestRequest::get().uri("/get").cookie(cookie).to_request();
+        assert_eq!("", test::call_and_read_body(&app, request).await);
+
+        let request = test::TestRequest::default().cookie(renewed.clone()).to_srv_request();
+        let renewed_id = session_cookie.session_id(&request).unwrap();
+        let request = test::TestRequest::get().uri("/purge").cookie(renewed).to_request();
+        test::call_service(&app, request).await;
+        assert!(store.load(&renewed_id).unwrap().is_none());
+    }
+
+    #[actix_rt::test]
+    async fn test_deleted_session_stays_deleted() {
+        let store = Arc::new(MemorySessionStore::default());
+        let ttl = Duration::from_secs(60);
+        let session_cookie = Arc::new(SessionCookie::new(Key::generate(), ttl));
+        let session = ServerSession::new(store.clone(), session_cookie.clone(), ttl);
+        let state: SessionState = [("user-id".to_owned(), "\"user-01\"".to_owned())].into_iter().collect();
+
+        // Loaded by a request, then deleted by back-channel logout before the request finishes
+        for status in [SessionStatus::Unchanged, SessionStatus::Changed] {
+            store.save("session-01", &state, ttl).unwrap();
+            store.delete("session-01").unwrap();
+            let cookie = session.persist(Some("session-01".to_owned()), status, state.clone(), SessionState::new()).unwrap().unwrap();
+            assert_eq!("", cookie.value());
+            assert_eq!(None, store.load("session-01").unwrap());
+        }
+
+        store.save("session-01", &state, ttl).unwrap();
+        let cookie = session.persist(Some("session-01".to_owned()), SessionStatus::Unchanged, state.clone(), SessionState::new()).unwrap().unwrap();
+        assert_ne!("", cookie.value());
+        assert_eq!(Some(state), store.load("session-01").unwrap());
+    }
+
+    #[actix_rt::test]
+    async fn test_server_session_resources() {
+        let store = Arc::new(MemorySessionStore::default());
+        let resources = Arc::new(Resources::default());
+        let ttl = Duration::from_secs(60);
+        let session_cookie = Arc::new(SessionCookie::new(Key::generate(), ttl));
+        let app = test::init_service(
+            App::new()
+                .wrap(ServerSession::new(store.clone(), session_cookie.clone(), ttl).with_resources(resources.clone()))
+                .route("/set", web::get().to(set))
+                .route("/get", web::get().to(get))
+                .route("/purge", web::get().to(purge))
+        ).await;
+
+        let response = test::call_service(&app, test::TestRequest::get().uri("/set").to_request()).await;
+        let cookie = response.response().cookies().find(|cookie| cookie.name() == session_cookie.name()).unwrap().into_owned();
+        let request = test::TestRequest::get().uri("/get").cookie(cookie.clone()).to_request();
+        test::call_service(&app, request).await;
+        let request = test::TestRequest::get().uri("/purge").cookie(cookie).to_request();
+        test::call_service(&app, request).await;
+
+        let expected = vec!["retain \"user-01\"", "retain \"user-01\"", "release \"user-01\""];
+        assert_eq!(expected, *resources.events.lock().unwrap());
+    }
+}
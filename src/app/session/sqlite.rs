@@ -0,0 +1,129 @@
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rusqlite::{Connection, OptionalExtension, params};
+
+use crate::app::session::store::{Result, SessionState, SessionStore};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS sessions (
+        id TEXT PRIMARY KEY,
+        state TEXT NOT NULL,
+        expires_at INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS sessions_expires_at ON sessions (expires_at);
+";
+
+/// Sessions in a SQLite database, so they survive restarts.
+pub struct SqliteSessionStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteSessionStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(connection: Connection) -> Result<Self> {
+        connection.execute_batch(SCHEMA)?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+impl SessionStore for SqliteSessionStore {
+    fn load(&self, id: &str) -> Result<Option<SessionState>> {
+        let connection = self.connection.lock().unwrap();
+        let state = connection.query_row(
+            "SELECT state FROM sessions WHERE id = ?1 AND expires_at > ?2",
+            params![id, now()],
+            |row| row.get::<_, String>(0),
+        ).optional()?;
+        match state {
+            Some(state) => Ok(Some(serde_json::from_str(&state)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self, id: &str, state: &SessionState, ttl: Duration) -> Result<()> {
+        let state = serde_json::to_string(state)?;
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO sessions (id, state, expires_at) VALUES (?1, ?2, ?3)
+                ON CONFLICT (id) DO UPDATE SET state = excluded.state, expires_at = excluded.expires_at",
+            params![id, state, now() + ttl.as_secs()],
+        )?;
+        Ok(())
+    }
+
+    fn update(&self, id: &str, state: &SessionState, ttl: Duration) -> Result<bool> {
+        let state = serde_json::to_string(state)?;
+        let now = now();
+        let updated = self.connection.lock().unwrap().execute(
+            "UPDATE sessions SET state = ?2, expires_at = ?3 WHERE id = ?1 AND expires_at > ?4",
+            params![id, state, now + ttl.as_secs(), now],
+        )?;
+        Ok(updated > 0)
+    }
+
+    fn touch(&self, id: &str, ttl: Duration) -> Result<bool> {
+        let now = now();
+        let updated = self.connection.lock().unwrap().execute(
+            "UPDATE sessions SET expires_at = ?2 WHERE id = ?1 AND expires_at > ?3",
+            params![id, now + ttl.as_secs(), now],
+        )?;
+        Ok(updated > 0)
+    }
+
+    fn delete(&self, id: &str) -> Result<()> {
+        self.connection.lock().unwrap().execute("DELETE FROM sessions WHERE id = ?1", [id])?;
+        Ok(())
+    }
+
+    fn delete_matching(&self, key: &str, value: &str) -> Result<Vec<SessionState>> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        let mut deleted = vec![];
+        {
+            let mut statement = transaction.prepare("SELECT id, state FROM sessions")?;
+            let rows = statement.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+            for row in rows {
+                let (id, state) = row?;
+                let state: SessionState = serde_json::from_str(&state)?;
+                if state.get(key).map(String::as_str) == Some(value) {
+                    deleted.push((id, state));
+                }
+            }
+        }
+        for (id, _) in &deleted {
+            transaction.execute("DELETE FROM sessions WHERE id = ?1", [id])?;
+        }
+        transaction.commit()?;
+        Ok(deleted.into_iter().map(|(_, state)| state).collect())
+    }
+
+    fn sweep(&self) -> Result<usize> {
+        let removed = self.connection.lock().unwrap().execute("DELETE FROM sessions WHERE expires_at <= ?1", [now()])?;
+        Ok(removed)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::session::store::tests::check_store;
+    use super::*;
+
+    #[test]
+    fn test_sqlite_store() {
+        check_store(&SqliteSessionStore::open_in_memory().unwrap());
+    }
+}
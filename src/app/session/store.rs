@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum SessionStoreError {
+    #[error("session database error: {0}")]
+    Database(#[from] rusqlite::Error),
+
+    #[error("invalid stored session: {0}")]
+    InvalidState(#[from] serde_json::Error),
+}
+
+pub type Result<T> = std::result::Result<T, SessionStoreError>;
+
+/// Session values as actix-session hands them over: JSON-encoded, keyed by name.
+pub type SessionState = HashMap<String, String>;
+
+/// Backend keeping session state server-side under an opaque session ID.
+pub trait SessionStore: Send + Sync {
+    /// Returns the state unless the session is unknown or expired.
+    fn load(&self, id: &str) -> Result<Option<SessionState>>;
+
+    /// Stores the state, expiring it `ttl` from now.
+    fn save(&self, id: &str, state: &SessionState, ttl: Duration) -> Result<()>;
+
+    /// Replaces the state of a live session, expiring it `ttl` from now; returns false if the session is gone.
+    fn update(&self, id: &str, state: &SessionState, ttl: Duration) -> Result<bool>;
+
+    /// Expires a live session `ttl` from now without rewriting its state; returns false if the session is gone.
+    fn touch(&self, id: &str, ttl: Duration) -> Result<bool>;
+
+    fn delete(&self, id: &str) -> Result<()>;
+
+    /// Deletes every session holding the JSON-encoded `value` under `key`, returning the state of each deleted session.
+    fn delete_matching(&self, key: &str, value: &str) -> Result<Vec<SessionState>>;
+
+    /// Removes expired sessions, returning how many were removed.
+    fn sweep(&self) -> Result<usize>;
+}
+
+/// Server-side data that session values refer to, like provider tokens, living and dying with the session.
+pub trait SessionResources: Send + Sync {
+    /// Keeps whatever the state refers to for `ttl` from now, as long as the session itself.
+    fn retain(&self, state: &SessionState, ttl: Duration);
+
+    /// Drops whatever the state of a deleted session refers to.
+    fn release(&self, state: &SessionState);
+
+    /// Drops whatever no live session kept, returning how much was dropped.
+    fn sweep(&self) -> usize;
+}
+
+#[derive(Default)]
+pub struct MemorySessionStore {
+    sessions: Mutex<HashMap<String, (SessionState, Instant)>>,
+}
+
+impl SessionStore for MemorySessionStore {
+    fn load(&self, id: &str) -> Result<Option<SessionState>> {
+        let sessions = self.sessions.lock().unwrap();
+        let state = sessions.get(id)
+            .filter(|(_, expires_at)| *expires_at > Instant::now())
+            .map(|(state, _)| state.clone());
+        Ok(state)
+    }
+
+    fn save(&self, id: &str, state: &SessionState, ttl: Duration) -> Result<()> {
+        self.sessions.lock().unwrap().insert(id.to_owned(), (state.clone(), Instant::now() + ttl));
+        Ok(())
+    }
+
+    fn update(&self, id: &str, state: &SessionState, ttl: Duration) -> Result<bool> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = Instant::now();
+        match sessions.get_mut(id).filter(|(_, expires_at)| *expires_at > now) {
+            Some(session) => {
+                *session = (state.clone(), now + ttl);
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
+    fn touch(&self, id: &str, ttl: Duration) -> Result<bool> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = Instant::now();
+        match sessions.get_mut(id).filter(|(_, expires_at)| *expires_at > now) {
+            Some((_, expires_at)) => {
+                *expires_at = now + ttl;
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
+    fn delete(&self, id: &str) -> Result<()> {
+        self.sessions.lock().unwrap().remove(id);
+        Ok(())
+    }
+
+    fn delete_matching(&self, key: &str, value: &str) -> Result<Vec<SessionState>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let ids: Vec<String> = sessions.iter()
+            .filter(|(_, (state, _))| state.get(key).map(String::as_str) == Some(value))
+            .map(|(id, _)| id.to_owned())
+            .collect();
+        let deleted = ids.iter()
+            .filter_map(|id| sessions.remove(id))
+            .map(|(state, _)| state)
+            .collect();
+        Ok(deleted)
+    }
+
+    fn sweep(&self) -> Result<usize> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let before = sessions.len();
+        let now = Instant::now();
+        sessions.retain(|_, (_, expires_at)| *expires_at > now);
+        Ok(before - sessions.len())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Checks the expiry contract every store has to keep.
+    pub fn check_store(store: &dyn SessionStore) {
+        let state: SessionState = [("user-id".to_owned(), "\"user-01\"".to_owned())].into_iter().collect();
+        store.save("session-01", &state, Duration::from_secs(60)).unwrap();
+        store.save("session-02", &state, Duration::ZERO).unwrap();
+        assert_eq!(Some(state.clone()), store.load("session-01").unwrap());
+        assert_eq!(None, store.load("session-02").unwrap());
+        assert_eq!(None, store.load("unknown").unwrap());
+
+        // Updates never bring back a session that is gone
+        let changed: SessionState = [("user-id".to_owned(), "\"user-03\"".to_owned())].into_iter().collect();
+        assert!(store.update("session-01", &changed, Duration::from_secs(60)).unwrap());
+        assert_eq!(Some(changed.clone()), store.load("session-01").unwrap());
+        assert!(store.touch("session-01", Duration::from_secs(60)).unwrap());
+        assert!(!store.update("session-02", &changed, Duration::from_secs(60)).unwrap());
+        assert!(!store.touch("session-02", Duration::from_secs(60)).unwrap());
+        assert!(!store.update("unknown", &changed, Duration::from_secs(60)).unwrap());
+        assert!(!store.touch("unknown", Duration::from_secs(60)).unwrap());
+        assert_eq!(None, store.load("unknown").unwrap());
+
+        assert_eq!(1, store.sweep().unwrap());
+        store.delete("session-01").unwrap();
+        assert_eq!(None, store.load("session-01").unwrap());
+        assert_eq!(0, store.sweep().unwrap());
+
+        let other: SessionState = [("user-id".to_owned(), "\"user-02\"".to_owned())].into_iter().collect();
+        store.save("session-03", &state, Duration::from_secs(60)).unwrap();
+        store.save("session-04", &state, Duration::from_secs(60)).unwrap();
+        store.save("session-05", &other, Duration::from_secs(60)).unwrap();
+        assert_eq!(vec![state.clone(), state], store.delete_matching("user-id", "\"user-01\"").unwrap());
+        assert_eq!(None, store.load("session-03").unwrap());
+        assert_eq!(Some(other), store.load("session-05").unwrap());
+    }
+
+    #[test]
+    fn test_memory_store() {
+        check_store(&MemorySessionStore::default());
+    }
+}
@@ -0,0 +1,96 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use structopt::StructOpt;
+
+use webauthexp::app::config::{AppConfig, GithubConfig, OidcConfig};
+use webauthexp::app::models::client_auth::{ClientAuthMethod, ClientAuthentication};
+use webauthexp::app::models::device::{DeviceAuthorizationRequest, DeviceAuthorizationResponse, DeviceTokenRequest};
+use webauthexp::app::models::github::{self, UserRequest};
+use webauthexp::app::models::keys::SigningKey;
+use webauthexp::app::models::mtls::ClientCertificate;
+use webauthexp::app::models::oidc::jwks_store::JwksStore;
+use webauthexp::app::models::oidc::signin::{OidcIdentification, client_authentication, discover_endpoints};
+use webauthexp::app::models::token::TokenSet;
+
+#[derive(StructOpt)]
+#[structopt(name = "device_login")]
+struct DeviceLoginArgs {
+    #[structopt(short, long, parse(from_os_str))]
+    config: PathBuf,
+
+    /// "github", "google" or the name of an [[oidc]] provider
+    provider: String,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = DeviceLoginArgs::from_args();
+    let config = AppConfig::load(&args.config).await?;
+
+    let signing_key = match &config.signing_key {
+        Some(signing_key_config) => Some(SigningKey::load(signing_key_config).await?),
+        None => None,
+    };
+
+    match args.provider.as_str() {
+        "github" => github_login(&config.github).await,
+        "google" => oidc_login(&config.google.to_oidc_config(), signing_key.as_ref()).await,
+        name => {
+            let oidc = config.oidc.iter()
+                .find(|oidc| oidc.name == name)
+                .ok_or_else(|| anyhow!("no provider named {}", name))?;
+            oidc_login(oidc, signing_key.as_ref()).await
+        },
+    }
+}
+
+async fn github_login(config: &GithubConfig) -> Result<()> {
+    // GitHub's device flow is for public clients and takes no client secret
+    let authentication = ClientAuthentication::new(ClientAuthMethod::None, &config.client_id, &config.client_secret);
+    let authorization = DeviceAuthorizationRequest::new(github::DEVICE_AUTHORIZATION_ENDPOINT, &authentication, &config.scope)
+        .execute()
+        .await?;
+    print_instructions(&authorization);
+
+    let token_response = DeviceTokenRequest::new(github::TOKEN_ENDPOINT, &authentication)
+        .poll(&authorization)
+        .await?;
+    let user = UserRequest::new().execute(&token_response.access_token).await?;
+    println!("{}", serde_json::to_string_pretty(&user)?);
+    Ok(())
+}
+
+async fn oidc_login(config: &OidcConfig, signing_key: Option<&SigningKey>) -> Result<()> {
+    let client_certificate = match &config.client_certificate {
+        Some(certificate_config) => Some(ClientCertificate::load(certificate_config).await?),
+        None => None,
+    };
+    let openid_config = discover_endpoints(config, client_certificate.as_ref()).await?;
+    let endpoint = openid_config.device_authorization_endpoint.as_ref()
+        .ok_or_else(|| anyhow!("{} does not support the device authorization grant", config.issuer))?;
+    let authentication = client_authentication(config, &openid_config, signing_key, client_certificate.as_ref())?;
+    let authorization = DeviceAuthorizationRequest::new(endpoint, &authentication, &config.scope)
+        .execute()
+        .await?;
+    print_instructions(&authorization);
+
+    let token_response = DeviceTokenRequest::new(&openid_config.token_endpoint, &authentication)
+        .poll(&authorization)
+        .await?;
+    let jwks_store = JwksStore::default();
+    let id = OidcIdentification::new(config, &jwks_store, &openid_config)
+        .with_http_client(authentication.http_client())
+        .execute(&TokenSet::new(token_response))
+        .await?;
+    println!("{}", serde_json::to_string_pretty(&id)?);
+    Ok(())
+}
+
+fn print_instructions(authorization: &DeviceAuthorizationResponse) {
+    println!("Open {} and enter the code {}", authorization.verification_uri, authorization.user_code);
+    if let Some(uri) = &authorization.verification_uri_complete {
+        println!("or open {} directly", uri);
+    }
+    println!("Waiting for approval (expires in {} seconds)...", authorization.expires_in);
+}
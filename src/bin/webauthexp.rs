@@ -1,27 +1,80 @@
-use actix_session::CookieSession;
-use actix_web::{middleware::Logger, App, HttpServer};
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::{middleware::Logger, web::Data, App, HttpServer};
 use anyhow::Result;
 use env_logger::Env;
 
-use webauthexp::app::config::AppArgs;
-use webauthexp::app::handlers::{github, google, spotify};
+use webauthexp::app::config::{AppArgs, AppCommand};
+use webauthexp::app::handlers::{introspection, jwks, oidc, providers, request_objects, users, webauthn};
+use webauthexp::app::models::keys::SigningKey;
+use webauthexp::app::models::oidc::introspection::Introspection;
+use webauthexp::app::models::oidc::jwks_store::JwksStore;
+use webauthexp::app::models::oidc::request_object::RequestObjectStore;
+use webauthexp::app::models::provider::ProviderRegistry;
+use webauthexp::app::models::token::TokenStore;
+use webauthexp::app::models::user::UserStore;
+use webauthexp::app::session::{self, cookie::{SessionCookie, generate_key}, middleware::ServerSession, store::SessionResources};
 
 #[actix_rt::main]
 async fn main() -> Result<()> {
     let args = AppArgs::new();
+    if let Some(AppCommand::GenerateSessionKey) = args.command() {
+        println!("{}", generate_key());
+        return Ok(())
+    }
     let config = args.load_config().await?;
 
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
 
     let bind_address = config.bind_address();
+    let jwks_store = Data::new(JwksStore::default());
+    let token_store = Data::new(TokenStore::new(Duration::from_secs(60), config.session.ttl()));
+    let request_object_store = Data::new(RequestObjectStore::new(
+        config.public_url().map(|url| format!("{}/request-objects", url)),
+        Duration::from_secs(60),
+    ));
+    let signing_key = match &config.signing_key {
+        Some(signing_key_config) => Some(Data::new(SigningKey::load(signing_key_config).await?)),
+        None => None,
+    };
+    let providers = Data::new(ProviderRegistry::from_config(&config).await?);
+    let user_store = Data::new(UserStore::open(&config.users.database)?);
+    let session_store = session::open_store(&config.session)?;
+    let session_resources: Arc<dyn SessionResources> = token_store.clone().into_inner();
+    session::spawn_sweeper(session_store.clone(), Some(session_resources.clone()), config.session.sweep_interval());
+    let session_cookie = Arc::new(SessionCookie::from_config(&config.session).await?);
+    let introspection = match &config.introspection {
+        Some(introspection_config) => Some(Data::new(Introspection::discover(introspection_config).await?)),
+        None => None,
+    };
     let server = HttpServer::new(move || {
-        App::new()
+        let app = App::new()
+            .app_data(jwks_store.clone())
+            .app_data(token_store.clone())
+            .app_data(request_object_store.clone())
+            .app_data(providers.clone())
+            .app_data(user_store.clone())
+            .app_data(Data::from(session_store.clone()))
             .wrap(Logger::default())
             .wrap(Logger::new("%a %t \"%r\" %s %b \"%{Referer}i\" \"%{User-Agent}i\" %T"))
-            .wrap(CookieSession::signed(&[0; 32]).secure(false))
-            .service(github::create_scope(&config.github))
-            .service(google::create_scope(&config.google))
-            .service(spotify::create_scope(&config.spotify))
+            .wrap(ServerSession::new(session_store.clone(), session_cookie.clone(), config.session.ttl()).with_resources(session_resources.clone()))
+            .service(jwks::create_scope())
+            .service(request_objects::create_scope())
+            .service(users::create_scope())
+            .service(oidc::create_scope(&config))
+            .service(webauthn::create_scope(&config.webauthn));
+        let app = match &signing_key {
+            Some(signing_key) => app.app_data(signing_key.clone()),
+            None => app,
+        };
+        let app = match (&config.introspection, &introspection) {
+            (Some(introspection_config), Some(introspection)) => app
+                .app_data(introspection.clone())
+                .service(introspection::create_scope(introspection_config)),
+            _ => app,
+        };
+        app.service(providers::create_scope())
     });
     server.bind(bind_address)?.run().await?;
 